@@ -0,0 +1,205 @@
+use std::alloc::{self, Layout};
+use std::ffi::c_void;
+use std::fmt;
+use std::hint::spin_loop;
+use std::ptr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// The allocator may still be replaced.
+const UNUSED: u8 = 0;
+/// [`set_allocator`] is currently replacing the allocator.
+const SETTING: u8 = 1;
+/// Tree-sitter may have allocated memory with the current allocator. The allocator can no longer
+/// be replaced since memory allocated by the old allocator would be freed by the new one.
+const IN_USE: u8 = 2;
+
+static ALLOCATOR_STATE: AtomicU8 = AtomicU8::new(UNUSED);
+
+/// Marks the current allocator as in use. Must be called before creating any object which
+/// tree-sitter allocates.
+///
+/// Waits for a concurrent [`set_allocator`] to finish installing its allocator.
+#[inline]
+pub(crate) fn lock_allocator() {
+    if ALLOCATOR_STATE.load(Ordering::Acquire) == IN_USE {
+        return;
+    }
+    while let Err(SETTING) =
+        ALLOCATOR_STATE.compare_exchange(UNUSED, IN_USE, Ordering::Acquire, Ordering::Acquire)
+    {
+        spin_loop();
+    }
+}
+
+type MallocFn = unsafe extern "C" fn(size: usize) -> *mut c_void;
+type CallocFn = unsafe extern "C" fn(count: usize, size: usize) -> *mut c_void;
+type ReallocFn = unsafe extern "C" fn(ptr: *mut c_void, size: usize) -> *mut c_void;
+type FreeFn = unsafe extern "C" fn(ptr: *mut c_void);
+
+/// A set of allocation functions for the tree-sitter C library. See [`set_allocator`].
+#[derive(Debug, Clone, Copy)]
+pub struct Allocator {
+    malloc: MallocFn,
+    calloc: CallocFn,
+    realloc: ReallocFn,
+    free: FreeFn,
+}
+
+impl Allocator {
+    /// Creates an allocator from functions with the semantics of the C standard library's
+    /// `malloc`, `calloc`, `realloc` and `free`.
+    ///
+    /// # Safety
+    ///
+    /// The functions must behave like their C standard library counterparts: memory returned by
+    /// `malloc`, `calloc` and `realloc` must be valid for the requested size, aligned for any
+    /// type and stay valid until it is passed to `realloc` or `free`. The functions must be safe
+    /// to call from any thread.
+    pub const unsafe fn new(
+        malloc: MallocFn,
+        calloc: CallocFn,
+        realloc: ReallocFn,
+        free: FreeFn,
+    ) -> Self {
+        Self {
+            malloc,
+            calloc,
+            realloc,
+            free,
+        }
+    }
+
+    /// An allocator which allocates with Rust's global allocator, so that tree-sitter's
+    /// allocations go through the `#[global_allocator]` of the binary.
+    pub const fn rust() -> Self {
+        Self {
+            malloc: rust_malloc,
+            calloc: rust_calloc,
+            realloc: rust_realloc,
+            free: rust_free,
+        }
+    }
+}
+
+/// An error returned by [`set_allocator`] when tree-sitter was already used.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AllocatorInUseError;
+
+impl fmt::Display for AllocatorInUseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(
+            "the tree-sitter allocator must be set before any parser, query or query cursor is \
+             created",
+        )
+    }
+}
+
+impl std::error::Error for AllocatorInUseError {}
+
+/// Replaces the functions the tree-sitter C library uses to allocate memory.
+///
+/// This must be called once at startup: replacing the allocator after tree-sitter allocated
+/// memory would free that memory with the wrong allocator, so an error is returned once any
+/// [`Parser`](crate::Parser), [`Query`](crate::Query) or query cursor was created. Creating
+/// these objects on another thread while this function runs waits until the new allocator is
+/// installed.
+///
+/// Memory which tree-sitter returns to the caller, like
+/// [`ChangedRanges`](crate::ChangedRanges), is always freed with the installed allocator.
+#[doc(alias = "ts_set_allocator")]
+pub fn set_allocator(allocator: Allocator) -> Result<(), AllocatorInUseError> {
+    loop {
+        match ALLOCATOR_STATE.compare_exchange(
+            UNUSED,
+            SETTING,
+            Ordering::Acquire,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => break,
+            Err(IN_USE) => return Err(AllocatorInUseError),
+            Err(_) => spin_loop(),
+        }
+    }
+    unsafe {
+        ts_set_allocator(
+            allocator.malloc,
+            allocator.calloc,
+            allocator.realloc,
+            allocator.free,
+        )
+    }
+    // Publish the new allocation functions to the threads which lock the allocator.
+    ALLOCATOR_STATE.store(UNUSED, Ordering::Release);
+    Ok(())
+}
+
+/// The alignment of allocations made by [`Allocator::rust`]. C requires allocations to be
+/// aligned for any type, which is at most 16 bytes on all supported platforms.
+const ALIGN: usize = 16;
+/// The size of the header storing the size of an allocation in front of it. Rust's allocator
+/// needs the size to free the allocation, which `free` doesn't receive. The header is as large
+/// as the alignment so that the memory after it stays aligned.
+const HEADER: usize = ALIGN;
+
+fn layout(size: usize) -> Option<Layout> {
+    Layout::from_size_align(size.checked_add(HEADER)?, ALIGN).ok()
+}
+
+/// Stores the size in the header at `ptr` and returns the memory after the header.
+unsafe fn finish_alloc(ptr: *mut u8, layout: Layout) -> *mut c_void {
+    if ptr.is_null() {
+        alloc::handle_alloc_error(layout)
+    }
+    ptr.cast::<usize>().write(layout.size());
+    ptr.add(HEADER).cast()
+}
+
+/// Returns the start and layout of the allocation of `ptr`, which was returned by
+/// [`finish_alloc`].
+unsafe fn allocation(ptr: *mut c_void) -> (*mut u8, Layout) {
+    let ptr = ptr.cast::<u8>().sub(HEADER);
+    let size = ptr.cast::<usize>().read();
+    (ptr, Layout::from_size_align_unchecked(size, ALIGN))
+}
+
+unsafe extern "C" fn rust_malloc(size: usize) -> *mut c_void {
+    let Some(layout) = layout(size) else {
+        return ptr::null_mut();
+    };
+    finish_alloc(alloc::alloc(layout), layout)
+}
+
+unsafe extern "C" fn rust_calloc(count: usize, size: usize) -> *mut c_void {
+    let Some(layout) = count.checked_mul(size).and_then(layout) else {
+        return ptr::null_mut();
+    };
+    finish_alloc(alloc::alloc_zeroed(layout), layout)
+}
+
+unsafe extern "C" fn rust_realloc(ptr: *mut c_void, size: usize) -> *mut c_void {
+    if ptr.is_null() {
+        return rust_malloc(size);
+    }
+    let Some(new_layout) = layout(size) else {
+        return ptr::null_mut();
+    };
+    let (ptr, layout) = allocation(ptr);
+    finish_alloc(alloc::realloc(ptr, layout, new_layout.size()), new_layout)
+}
+
+unsafe extern "C" fn rust_free(ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    let (ptr, layout) = allocation(ptr);
+    alloc::dealloc(ptr, layout)
+}
+
+extern "C" {
+    fn ts_set_allocator(
+        new_malloc: MallocFn,
+        new_calloc: CallocFn,
+        new_realloc: ReallocFn,
+        new_free: FreeFn,
+    );
+}
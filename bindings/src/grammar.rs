@@ -1,6 +1,10 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::ffi::c_void;
 use std::fmt;
 use std::path::{Path, PathBuf};
 use std::ptr::NonNull;
+use std::sync::{Arc, Mutex};
 
 use libloading::{Library, Symbol};
 #[cfg(feature = "tree-sitter-language")]
@@ -35,26 +39,57 @@ impl Grammar {
     /// Loads a shared library containing a tree sitter grammar with name `name`
     // from `library_path`.
     ///
+    /// The library is never closed so the returned grammar (and the trees and nodes derived
+    /// from it) can't dangle. Use [`LoadedGrammar`] to close the library again.
+    ///
     /// # Safety
     ///
     /// `library_path` must be a valid tree sitter grammar
     pub unsafe fn new(name: &str, library_path: &Path) -> Result<Grammar, Error> {
-        let library = unsafe {
-            Library::new(library_path).map_err(|err| Error::DlOpen {
-                err,
-                path: library_path.to_owned(),
-            })?
-        };
+        let library = unsafe { open_library(library_path)? };
+        let grammar = unsafe { Grammar::from_library(&library, name)? };
+        std::mem::forget(library);
+        Ok(grammar)
+    }
+
+    /// Loads the grammar `name` from an already opened shared library.
+    ///
+    /// # Safety
+    ///
+    /// `library` must be a valid tree sitter grammar and must outlive the returned grammar.
+    unsafe fn from_library(library: &Library, name: &str) -> Result<Grammar, Error> {
         let language_fn_name = format!("tree_sitter_{}", name.replace('-', "_"));
-        let language_fn: Symbol<unsafe extern "C" fn() -> NonNull<GrammarData>> = library
+        let language_fn: Symbol<unsafe extern "C" fn() -> *const c_void> = library
             .get(language_fn_name.as_bytes())
             .map_err(|err| Error::DlSym {
                 err,
                 symbol: name.to_owned(),
             })?;
-        let grammar = Grammar::from_grammar_data(language_fn())?;
-        std::mem::forget(library);
-        Ok(grammar)
+        Grammar::from_raw(language_fn())
+    }
+
+    /// Creates a grammar from a pointer to a tree sitter language, for example the return value
+    /// of a statically linked `tree_sitter_<name>` function.
+    ///
+    /// # Safety
+    ///
+    /// `language` must be null or a pointer to a valid tree sitter grammar which is never
+    /// freed. A null pointer is rejected with [`Error::NullGrammar`].
+    pub unsafe fn from_raw(language: *const c_void) -> Result<Grammar, Error> {
+        let ptr = NonNull::new(language.cast_mut().cast()).ok_or(Error::NullGrammar)?;
+        Grammar::from_grammar_data(ptr)
+    }
+
+    /// Creates a grammar from a `tree_sitter_<name>` language function, for example one that
+    /// is statically linked into the application.
+    ///
+    /// # Safety
+    ///
+    /// `language_fn` must be the language function of a tree sitter grammar.
+    pub unsafe fn new_from_fn(
+        language_fn: unsafe extern "C" fn() -> *const c_void,
+    ) -> Result<Grammar, Error> {
+        unsafe { Grammar::from_raw(language_fn()) }
     }
 
     fn from_grammar_data(ptr: NonNull<GrammarData>) -> Result<Grammar, Error> {
@@ -75,6 +110,148 @@ impl Grammar {
         let symbol_type = unsafe { ts_language_symbol_type(self, kind_id) };
         symbol_type <= (SymbolType::Anonymous as u32)
     }
+
+    /// Returns the parse state the parser reaches from `state` after a node with the grammar
+    /// kind id `symbol`, see [`Node::grammar_kind_id`](crate::Node::grammar_kind_id) and
+    /// [`Node::parse_state`](crate::Node::parse_state).
+    #[doc(alias = "ts_language_next_state")]
+    pub fn next_state(self, state: u16, symbol: u16) -> u16 {
+        unsafe { ts_language_next_state(self, state, symbol) }
+    }
+}
+
+/// A grammar which shares ownership of the shared library it was loaded from.
+///
+/// Unlike [`Grammar::new`], which keeps the library loaded forever, the library is closed when
+/// the last `LoadedGrammar` loaded from it is dropped. This is useful for tools which load many
+/// grammars once, for example to validate them. Cloning a `LoadedGrammar` is cheap.
+#[derive(Clone)]
+pub struct LoadedGrammar {
+    grammar: Grammar,
+    _library: Arc<Library>,
+}
+
+impl LoadedGrammar {
+    /// Loads the grammar with name `name` from the shared library at `library_path`.
+    ///
+    /// # Safety
+    ///
+    /// `library_path` must be a valid tree sitter grammar
+    pub unsafe fn new(name: &str, library_path: &Path) -> Result<LoadedGrammar, Error> {
+        let library = Arc::new(unsafe { open_library(library_path)? });
+        unsafe { LoadedGrammar::from_library(library, name) }
+    }
+
+    /// # Safety
+    ///
+    /// `library` must be a valid tree sitter grammar.
+    unsafe fn from_library(library: Arc<Library>, name: &str) -> Result<LoadedGrammar, Error> {
+        let grammar = unsafe { Grammar::from_library(&library, name)? };
+        Ok(LoadedGrammar {
+            grammar,
+            _library: library,
+        })
+    }
+
+    /// Returns the [`Grammar`], for example to pass it to
+    /// [`Parser::set_grammar`](crate::Parser::set_grammar).
+    ///
+    /// # Safety
+    ///
+    /// `Grammar` is `Copy` and not tied to the lifetime of the library, so the compiler can't
+    /// check that it doesn't outlive the library. All copies of the grammar and all parsers,
+    /// trees, nodes and queries created with it must be dropped before the last clone of this
+    /// `LoadedGrammar` (or of the [`GrammarCache`] it was loaded from) since they point into the
+    /// library which is closed at that point.
+    pub unsafe fn grammar(&self) -> Grammar {
+        self.grammar
+    }
+
+    pub fn abi_version(&self) -> u32 {
+        self.grammar.abi_version()
+    }
+
+    pub fn node_kind_is_visible(&self, kind_id: u16) -> bool {
+        self.grammar.node_kind_is_visible(kind_id)
+    }
+
+    /// See [`Grammar::next_state`].
+    pub fn next_state(&self, state: u16, symbol: u16) -> u16 {
+        self.grammar.next_state(state, symbol)
+    }
+}
+
+impl PartialEq for LoadedGrammar {
+    fn eq(&self, other: &Self) -> bool {
+        self.grammar == other.grammar
+    }
+}
+
+impl Eq for LoadedGrammar {}
+
+impl fmt::Debug for LoadedGrammar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoadedGrammar").finish_non_exhaustive()
+    }
+}
+
+unsafe fn open_library(library_path: &Path) -> Result<Library, Error> {
+    unsafe {
+        Library::new(library_path).map_err(|err| Error::DlOpen {
+            err,
+            path: library_path.to_owned(),
+        })
+    }
+}
+
+/// A cache of grammars loaded from shared libraries.
+///
+/// Each shared library is opened at most once, no matter how many times a grammar is loaded from
+/// it. Unlike repeated calls to [`LoadedGrammar::new`] this makes it cheap to load grammars
+/// lazily, for example from a language loader which is asked for the same language many times.
+///
+/// A library stays open as long as the cache or any [`LoadedGrammar`] loaded from it is alive.
+#[derive(Debug, Default)]
+pub struct GrammarCache {
+    state: Mutex<GrammarCacheState>,
+}
+
+#[derive(Debug, Default)]
+struct GrammarCacheState {
+    libraries: HashMap<PathBuf, Arc<Library>>,
+    grammars: HashMap<(PathBuf, String), LoadedGrammar>,
+}
+
+impl GrammarCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the grammar with name `name` from `library_path`, reusing the grammar or the
+    /// library if they were loaded by this cache before.
+    ///
+    /// # Safety
+    ///
+    /// `library_path` must be a valid tree sitter grammar
+    pub unsafe fn load(&self, name: &str, library_path: &Path) -> Result<LoadedGrammar, Error> {
+        // The lock is held while the library is opened so that concurrent loads of the same
+        // library don't open it twice.
+        let mut state = self.state.lock().unwrap();
+        let key = (library_path.to_owned(), name.to_owned());
+        if let Some(grammar) = state.grammars.get(&key) {
+            return Ok(grammar.clone());
+        }
+        let library = match state.libraries.entry(key.0.clone()) {
+            Entry::Occupied(entry) => entry.get().clone(),
+            Entry::Vacant(entry) => {
+                let library = unsafe { open_library(library_path)? };
+                entry.insert(Arc::new(library)).clone()
+            }
+        };
+        let grammar = unsafe { LoadedGrammar::from_library(library, name)? };
+        state.grammars.insert(key, grammar.clone());
+        Ok(grammar)
+    }
 }
 
 #[cfg(feature = "tree-sitter-language")]
@@ -82,8 +259,7 @@ impl TryFrom<LanguageFn> for Grammar {
     type Error = Error;
 
     fn try_from(builder: LanguageFn) -> Result<Self, Self::Error> {
-        let ptr = unsafe { NonNull::new_unchecked(builder.into_raw()().cast_mut().cast()) };
-        Self::from_grammar_data(ptr)
+        unsafe { Self::from_raw(builder.into_raw()().cast()) }
     }
 }
 
@@ -103,21 +279,38 @@ pub enum Error {
     },
     #[error("Tried to load grammar with incompatible ABI {version}.")]
     IncompatibleVersion { version: u32 },
+    #[error("The language function returned a null pointer")]
+    NullGrammar,
 }
 
 /// An error that occurred when trying to assign an incompatible [`Grammar`] to
 /// a [`crate::parser::Parser`].
 #[derive(Debug, PartialEq, Eq)]
 pub struct IncompatibleGrammarError {
-    pub abi_version: u32,
+    /// The ABI version the grammar was generated with.
+    pub grammar_version: u32,
+    /// The lowest ABI version supported by this build, see [`MIN_COMPATIBLE_ABI_VERSION`].
+    pub min_supported: u32,
+    /// The highest ABI version supported by this build, see [`ABI_VERSION`].
+    pub max_supported: u32,
+}
+
+impl IncompatibleGrammarError {
+    pub(crate) fn new(grammar: Grammar) -> Self {
+        Self {
+            grammar_version: grammar.abi_version(),
+            min_supported: MIN_COMPATIBLE_ABI_VERSION,
+            max_supported: ABI_VERSION,
+        }
+    }
 }
 
 impl fmt::Display for IncompatibleGrammarError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "Tried to load grammar with incompatible ABI version {}.",
-            self.abi_version,
+            "grammar uses ABI {} but this build supports {}..={}",
+            self.grammar_version, self.min_supported, self.max_supported,
         )
     }
 }
@@ -143,4 +336,9 @@ extern "C" {
     ///
     /// See also `ts_node_is_named`. Hidden nodes are never returned from the API.
     pub fn ts_language_symbol_type(grammar: Grammar, symbol: u16) -> u32;
+
+    /// Get the next parse state. Combine this with lookahead iterators to generate completion
+    /// suggestions or valid symbols in error nodes. Use `ts_node_grammar_symbol` for valid
+    /// symbols.
+    pub fn ts_language_next_state(grammar: Grammar, state: u16, symbol: u16) -> u16;
 }
@@ -1,3 +1,4 @@
+mod alloc;
 mod grammar;
 mod node;
 mod parser;
@@ -13,13 +14,17 @@ pub use ropey::RopeInput;
 
 use std::ops;
 
-pub use grammar::{Grammar, IncompatibleGrammarError};
+pub use alloc::{set_allocator, Allocator, AllocatorInUseError};
+pub use grammar::{
+    Grammar, GrammarCache, IncompatibleGrammarError, LoadedGrammar, ABI_VERSION,
+    MIN_COMPATIBLE_ABI_VERSION,
+};
 pub use node::Node;
-pub use parser::{Parser, ParserInputRaw};
-pub use query::{Capture, Pattern, Query, QueryStr};
+pub use parser::{InvalidRangeKind, InvalidRangesError, ParseState, Parser, ParserInputRaw};
+pub use query::{Capture, Pattern, Quantifier, Query, QueryStr};
 pub use query_cursor::{InactiveQueryCursor, MatchedNode, MatchedNodeIdx, QueryCursor, QueryMatch};
-pub use tree::{ChangedRanges, InputEdit, Tree};
-pub use tree_cursor::TreeCursor;
+pub use tree::{ChangedRanges, InputEdit, Tree, TreeId};
+pub use tree_cursor::{DescendantsWithinDepth, TreeCursor};
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -45,6 +50,26 @@ pub struct Range {
     pub end_byte: u32,
 }
 
+/// Frees memory which the tree-sitter C library allocated and handed over to the caller, like
+/// the arrays returned by `ts_tree_get_changed_ranges`.
+///
+/// This must be used instead of calling `free` directly: tree-sitter allocates with
+/// `ts_current_malloc`, which may have been replaced with `ts_set_allocator`, and on Windows
+/// the `free` linked into the Rust binary may belong to a different C runtime than the one
+/// the vendored library was compiled against. Freeing across C runtimes corrupts the heap.
+///
+/// # Safety
+///
+/// `ptr` must be null or have been allocated by tree-sitter and not been freed yet.
+pub(crate) unsafe fn ts_free(ptr: *mut std::ffi::c_void) {
+    extern "C" {
+        /// The deallocation function used by the tree-sitter library. This always matches the
+        /// allocation functions used by the library since both are set by `ts_set_allocator`.
+        static ts_current_free: unsafe extern "C" fn(ptr: *mut std::ffi::c_void);
+    }
+    ts_current_free(ptr)
+}
+
 pub trait Input {
     type Cursor: regex_cursor::Cursor;
     fn cursor_at(&mut self, offset: u32) -> &mut Self::Cursor;
@@ -4,9 +4,9 @@ use std::marker::PhantomData;
 use std::ops::Range;
 use std::ptr::NonNull;
 
-use crate::tree::Tree;
-use crate::tree_cursor::TreeCursor;
-use crate::Grammar;
+use crate::tree::{InputEdit, Tree, TreeId};
+use crate::tree_cursor::{DescendantsWithinDepth, TreeCursor};
+use crate::{Grammar, Point};
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -62,6 +62,12 @@ impl<'tree> Node<'tree> {
         }
     }
 
+    /// Returns the id of the [`Tree`] this node belongs to, see [`Tree::id`].
+    #[inline]
+    pub fn tree_id(&self) -> TreeId {
+        TreeId(self.tree.as_ptr() as usize)
+    }
+
     pub fn id(&self) -> usize {
         self.id.as_ptr() as usize
     }
@@ -80,6 +86,15 @@ impl<'tree> Node<'tree> {
         unsafe { ts_node_symbol(self.as_raw()) }
     }
 
+    /// Get this node's type as a numerical id as it appears in the grammar, ignoring aliases.
+    ///
+    /// This should be used with [`Grammar::next_state`] instead of [`Node::kind_id`].
+    #[inline]
+    #[doc(alias = "ts_node_grammar_symbol")]
+    pub fn grammar_kind_id(&self) -> u16 {
+        unsafe { ts_node_grammar_symbol(self.as_raw()) }
+    }
+
     /// Get the [`Grammar`] that was used to parse this node's syntax tree.
     #[inline]
     pub fn grammar(&self) -> Grammar {
@@ -98,11 +113,33 @@ impl<'tree> Node<'tree> {
     /// Returns true if and only if this node is contained "inside" the given
     /// input range, i.e. either start_new > start_old and end_new <= end_old OR
     /// start_new >= start_old and end_new < end_old
+    ///
+    /// Use [`Node::intersects`] to check for overlap instead.
     pub fn is_contained_within(&self, range: Range<u32>) -> bool {
         (self.start_byte() > range.start && self.end_byte() <= range.end)
             || (self.start_byte() >= range.start && self.end_byte() < range.end)
     }
 
+    /// Returns true if the given byte lies inside this node.
+    ///
+    /// Like [`Node::byte_range`] this is half-open: the node contains its start byte but not its
+    /// end byte, so an empty node contains no bytes.
+    #[inline]
+    pub fn contains(&self, byte: u32) -> bool {
+        self.start_byte() <= byte && byte < self.end_byte()
+    }
+
+    /// Returns true if this node overlaps the given byte range.
+    ///
+    /// A node and a range which only touch do not intersect, unless they start at the same byte:
+    /// then they always intersect, even if the node or the range is empty. This way an empty
+    /// range, like a cursor, intersects the nodes starting at its position.
+    #[inline]
+    pub fn intersects(&self, range: Range<u32>) -> bool {
+        let (start, end) = (self.start_byte(), self.end_byte());
+        start == range.start || (end > range.start && range.end > start)
+    }
+
     /// Check if this node is *missing*.
     ///
     /// Missing nodes are inserted by the parser in order to recover from
@@ -112,6 +149,45 @@ impl<'tree> Node<'tree> {
         unsafe { ts_node_is_missing(self.as_raw()) }
     }
 
+    /// Check if this node represents a syntax error.
+    ///
+    /// Syntax errors represent parts of the code that could not be incorporated
+    /// into a valid syntax tree.
+    #[inline]
+    pub fn is_error(&self) -> bool {
+        unsafe { ts_node_is_error(self.as_raw()) }
+    }
+
+    /// Check if this node is a syntax error or contains any syntax errors.
+    #[inline]
+    pub fn has_error(&self) -> bool {
+        unsafe { ts_node_has_error(self.as_raw()) }
+    }
+
+    /// Get the parse state of the grammar before this node.
+    ///
+    /// Parse states can be used with [`Grammar::next_state`] to find out which nodes the parser
+    /// expects at a position, for example to indent an incomplete construct the same way as the
+    /// complete one.
+    #[inline]
+    #[doc(alias = "ts_node_parse_state")]
+    pub fn parse_state(&self) -> u16 {
+        unsafe { ts_node_parse_state(self.as_raw()) }
+    }
+
+    /// Get the parse state of the grammar after this node.
+    #[inline]
+    #[doc(alias = "ts_node_next_parse_state")]
+    pub fn next_parse_state(&self) -> u16 {
+        unsafe { ts_node_next_parse_state(self.as_raw()) }
+    }
+
+    /// Check if this node has been edited.
+    #[inline]
+    pub fn has_changes(&self) -> bool {
+        unsafe { ts_node_has_changes(self.as_raw()) }
+    }
+
     /// Check if this node is *extra*.
     ///
     /// Extra nodes represent things like comments, which are not required by the
@@ -145,12 +221,45 @@ impl<'tree> Node<'tree> {
         unsafe { ts_node_end_byte(self.as_raw()) }
     }
 
+    /// Get the row and column where this node starts.
+    ///
+    /// The column is measured in bytes.
+    #[inline]
+    pub fn start_position(&self) -> Point {
+        unsafe { ts_node_start_point(self.as_raw()) }
+    }
+
+    /// Get the row and column where this node ends.
+    ///
+    /// The column is measured in bytes.
+    #[inline]
+    pub fn end_position(&self) -> Point {
+        unsafe { ts_node_end_point(self.as_raw()) }
+    }
+
     /// Get the byte range of source code that this node represents.
     #[inline]
     pub fn byte_range(&self) -> Range<u32> {
         self.start_byte()..self.end_byte()
     }
 
+    /// Get the text of this node from the `source` the tree was parsed from.
+    ///
+    /// Panics if the node lies outside of `source`.
+    #[cfg(feature = "ropey")]
+    #[inline]
+    pub fn text<'a>(&self, source: ::ropey::RopeSlice<'a>) -> ::ropey::RopeSlice<'a> {
+        source.byte_slice(self.start_byte() as usize..self.end_byte() as usize)
+    }
+
+    /// Get the text of this node from the `source` the tree was parsed from.
+    ///
+    /// Panics if the node lies outside of `source` or doesn't start and end at char boundaries.
+    #[inline]
+    pub fn text_str<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.start_byte() as usize..self.end_byte() as usize]
+    }
+
     /// Get the node's child at the given index, where zero represents the first
     /// child.
     ///
@@ -240,6 +349,32 @@ impl<'tree> Node<'tree> {
         }
     }
 
+    /// Get the smallest node within this node that spans the given range of row and (byte)
+    /// column positions.
+    #[inline]
+    pub fn descendant_for_point_range(&self, start: Point, end: Point) -> Option<Self> {
+        unsafe {
+            Self::from_raw(ts_node_descendant_for_point_range(
+                self.as_raw(),
+                start,
+                end,
+            ))
+        }
+    }
+
+    /// Get the smallest named node within this node that spans the given range of row and (byte)
+    /// column positions.
+    #[inline]
+    pub fn named_descendant_for_point_range(&self, start: Point, end: Point) -> Option<Self> {
+        unsafe {
+            Self::from_raw(ts_node_named_descendant_for_point_range(
+                self.as_raw(),
+                start,
+                end,
+            ))
+        }
+    }
+
     /// Iterate over this node's children.
     ///
     /// A [`TreeCursor`] is used to retrieve the children efficiently. Obtain
@@ -262,6 +397,50 @@ impl<'tree> Node<'tree> {
     pub fn walk(&self) -> TreeCursor<'tree> {
         TreeCursor::new(self)
     }
+
+    /// Get the number of descendants of this node, including this node itself.
+    #[inline]
+    pub fn descendant_count(&self) -> u32 {
+        unsafe { ts_node_descendant_count(self.as_raw()) }
+    }
+
+    /// Iterates over this node and its descendants in pre-order, skipping any descendants which
+    /// are more than `max_depth` levels below this node.
+    ///
+    /// A `max_depth` of zero only yields this node and a `max_depth` of one yields this node and
+    /// its children.
+    pub fn descendants_within_depth(&self, max_depth: u32) -> DescendantsWithinDepth<'tree> {
+        DescendantsWithinDepth::new(self.walk(), max_depth)
+    }
+
+    /// Returns an S-expression representing this node and its named descendants, for example
+    /// `(source_file (function_item name: (identifier) ...))`.
+    ///
+    /// This is mostly useful for debugging and for tests.
+    #[doc(alias = "ts_node_string")]
+    pub fn to_sexp(&self) -> String {
+        unsafe {
+            let ptr = ts_node_string(self.as_raw());
+            let sexp = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+            crate::ts_free(ptr.cast());
+            sexp
+        }
+    }
+
+    /// Edit this node to keep it in-sync with source code that has been edited.
+    ///
+    /// This is only needed for nodes which are held onto across edits. Nodes retrieved from a
+    /// tree after [`Tree::edit`] is called already reflect the edit.
+    ///
+    /// Note that this only updates the position of this node. The tree this node belongs to
+    /// must be edited with the same `edit` as well, otherwise the node desyncs from its tree
+    /// and any nodes reached from it (children, siblings, parents) will have stale positions.
+    #[inline]
+    pub fn edit(&mut self, edit: &InputEdit) {
+        let mut raw = self.as_raw();
+        unsafe { ts_node_edit(&mut raw, edit) };
+        self.context = raw.context;
+    }
 }
 
 impl PartialEq for Node<'_> {
@@ -276,12 +455,20 @@ unsafe impl Send for Node<'_> {}
 unsafe impl Sync for Node<'_> {}
 
 extern "C" {
+    /// Get an S-expression representing the node as a string. This string is allocated with
+    /// tree-sitter's allocator and the caller is responsible for freeing it using
+    /// [`crate::ts_free`] (`ts_current_free`).
+    fn ts_node_string(node: NodeRaw) -> *mut c_char;
     /// Get the node's type as a null-terminated string.
     fn ts_node_type(node: NodeRaw) -> *const c_char;
 
     /// Get the node's type as a numerical id.
     fn ts_node_symbol(node: NodeRaw) -> u16;
 
+    /// Get the node's type as a numerical id as it appears in the grammar ignoring aliases. This
+    /// should be used in `ts_language_next_state` instead of `ts_node_symbol`.
+    fn ts_node_grammar_symbol(node: NodeRaw) -> u16;
+
     /// Get the node's language.
     fn ts_node_language(node: NodeRaw) -> Grammar;
 
@@ -300,6 +487,21 @@ extern "C" {
     /// grammar, but can appear anywhere.
     fn ts_node_is_extra(node: NodeRaw) -> bool;
 
+    /// Check if the node is a syntax error.
+    fn ts_node_is_error(node: NodeRaw) -> bool;
+
+    /// Check if the node is a syntax error or contains any syntax errors.
+    fn ts_node_has_error(node: NodeRaw) -> bool;
+
+    /// Check if a syntax node has been edited.
+    fn ts_node_has_changes(node: NodeRaw) -> bool;
+
+    /// Get this node's parse state.
+    fn ts_node_parse_state(node: NodeRaw) -> u16;
+
+    /// Get the parse state after this node.
+    fn ts_node_next_parse_state(node: NodeRaw) -> u16;
+
     /// Get the node's immediate parent
     fn ts_node_parent(node: NodeRaw) -> NodeRaw;
 
@@ -335,6 +537,33 @@ extern "C" {
     /// of bytes or (row, column) positions
     fn ts_node_named_descendant_for_byte_range(node: NodeRaw, start: u32, end: u32) -> NodeRaw;
 
+    /// Get the smallest node within this node that spans the given range of (row, column)
+    /// positions
+    fn ts_node_descendant_for_point_range(node: NodeRaw, start: Point, end: Point) -> NodeRaw;
+
+    /// Get the smallest named node within this node that spans the given range of (row, column)
+    /// positions
+    fn ts_node_named_descendant_for_point_range(node: NodeRaw, start: Point, end: Point)
+        -> NodeRaw;
+
     /// Get the node's end byte.
     fn ts_node_end_byte(node: NodeRaw) -> u32;
+
+    /// Get the node's number of descendants, including one for the node itself.
+    fn ts_node_descendant_count(node: NodeRaw) -> u32;
+
+    /// Get the node's start position in terms of rows and columns.
+    fn ts_node_start_point(node: NodeRaw) -> Point;
+
+    /// Get the node's end position in terms of rows and columns.
+    fn ts_node_end_point(node: NodeRaw) -> Point;
+
+    /// Edit the node to keep it in-sync with source code that has been edited.
+    ///
+    /// This function is only rarely needed. When you edit a syntax tree with the
+    /// `ts_tree_edit` function, all of the nodes that you retrieve from the tree
+    /// afterward will already reflect the edit. You only need to use `ts_node_edit`
+    /// when you have a `TSNode` instance that you want to keep and continue to use
+    /// after an edit.
+    fn ts_node_edit(node: *mut NodeRaw, edit: &InputEdit);
 }
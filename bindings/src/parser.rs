@@ -3,7 +3,7 @@ use std::os::raw::c_void;
 use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::ptr::NonNull;
 use std::time::Duration;
-use std::{fmt, mem, ptr};
+use std::{fmt, mem, ptr, slice};
 
 use regex_cursor::Cursor;
 
@@ -45,7 +45,10 @@ impl Parser {
                 mem::forget(cached);
                 ptr
             }
-            None => unsafe { ts_parser_new() },
+            None => {
+                crate::alloc::lock_allocator();
+                unsafe { ts_parser_new() }
+            }
         };
         Parser { ptr }
     }
@@ -55,9 +58,7 @@ impl Parser {
         if unsafe { ts_parser_set_language(self.ptr, grammar) } {
             Ok(())
         } else {
-            Err(IncompatibleGrammarError {
-                abi_version: grammar.abi_version(),
-            })
+            Err(IncompatibleGrammarError::new(grammar))
         }
     }
 
@@ -73,8 +74,10 @@ impl Parser {
     /// of a document but still return a syntax tree whose ranges match up with the document as a
     /// whole. You can also pass multiple disjoint ranges.
     ///
-    /// `ranges` must be non-overlapping and sorted.
+    /// `ranges` must be non-overlapping and sorted. Otherwise the error reports the first invalid
+    /// range and the ranges of the parser are left unchanged.
     pub fn set_included_ranges(&mut self, ranges: &[Range]) -> Result<(), InvalidRangesError> {
+        InvalidRangesError::check(ranges)?;
         // TODO: save some memory by only storing byte ranges and converting them to TS ranges in an
         // internal buffer here. Points are not used by TS. Alternatively we can patch the TS C code
         // to accept a simple pair (struct with two fields) of byte positions here instead of a full
@@ -82,20 +85,86 @@ impl Parser {
         let success = unsafe {
             ts_parser_set_included_ranges(self.ptr, ranges.as_ptr(), ranges.len() as u32)
         };
-        if success {
-            Ok(())
-        } else {
-            Err(InvalidRangesError)
+        if !success {
+            return Err(InvalidRangesError {
+                index: 0,
+                kind: InvalidRangeKind::Rejected,
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the ranges of text that the parser includes when parsing.
+    ///
+    /// If no ranges were set with [`Parser::set_included_ranges`] (or they were set to an empty
+    /// slice) this is a single range covering the entire document.
+    #[doc(alias = "ts_parser_included_ranges")]
+    #[must_use]
+    pub fn included_ranges(&self) -> Vec<Range> {
+        let mut count = 0;
+        unsafe {
+            let ranges = ts_parser_included_ranges(self.ptr, &mut count);
+            slice::from_raw_parts(ranges, count as usize).to_vec()
         }
     }
 
+    /// Parse the input, reusing the unchanged parts of `old_tree` if it was edited to match the
+    /// input.
+    ///
+    /// Returns `None` if the timeout set with [`Parser::set_timeout`] was exceeded. The
+    /// tree-sitter C library doesn't expose the incomplete tree of a parse which timed out, so no
+    /// partial tree can be returned. Callers who want to keep showing stale results can keep
+    /// using `old_tree` instead. The parser keeps the progress of a parse which timed out:
+    /// parsing the same input again resumes where the previous parse stopped. Setting a grammar
+    /// resets the parser.
     #[must_use]
     pub fn parse<I: Input>(
         &mut self,
         input: impl IntoInput<Input = I>,
         old_tree: Option<&Tree>,
     ) -> Option<Tree> {
-        let mut input = input.into_input();
+        self.parse_with_options(input.into_input(), old_tree, ParseOptions::default())
+    }
+
+    /// Parse the input like [`Parser::parse`] while periodically reporting the progress of the
+    /// parse to the `progress` callback.
+    ///
+    /// The callback may cancel the parse by returning `true`, in which case `None` is returned.
+    /// The timeout set with [`Parser::set_timeout`] still applies.
+    #[must_use]
+    pub fn parse_with_progress<I: Input>(
+        &mut self,
+        input: impl IntoInput<Input = I>,
+        old_tree: Option<&Tree>,
+        mut progress: impl FnMut(&ParseState) -> bool,
+    ) -> Option<Tree> {
+        unsafe extern "C" fn progress_callback<F: FnMut(&ParseState) -> bool>(
+            state: NonNull<ParseState>,
+        ) -> bool {
+            let cancel = catch_unwind(AssertUnwindSafe(move || {
+                let state = state.as_ref();
+                let progress: &mut F = state.payload.cast().as_mut();
+                progress(state)
+            }));
+            // Cancel the parse if the callback panicked.
+            cancel.unwrap_or(true)
+        }
+        fn options<F: FnMut(&ParseState) -> bool>(progress: &mut F) -> ParseOptions {
+            ParseOptions {
+                payload: Some(NonNull::from(progress).cast()),
+                progress_callback: Some(progress_callback::<F>),
+            }
+        }
+        let options = options(&mut progress);
+        self.parse_with_options(input.into_input(), old_tree, options)
+    }
+
+    fn parse_with_options<I: Input>(
+        &mut self,
+        mut input: I,
+        old_tree: Option<&Tree>,
+        options: ParseOptions,
+    ) -> Option<Tree> {
         unsafe extern "C" fn read<C: Input>(
             payload: NonNull<c_void>,
             byte_index: u32,
@@ -130,7 +199,7 @@ impl Parser {
 
         unsafe {
             let old_tree = old_tree.map(|tree| tree.as_raw());
-            let new_tree = ts_parser_parse(self.ptr, old_tree, input);
+            let new_tree = ts_parser_parse_with_options(self.ptr, old_tree, input, options);
             new_tree.map(|raw| Tree::from_raw(raw))
         }
     }
@@ -151,14 +220,66 @@ impl Drop for Parser {
     }
 }
 
-/// An error that occurred when trying to assign an incompatible [`Grammar`] to
-/// a [`Parser`].
-#[derive(Debug, PartialEq, Eq)]
-pub struct InvalidRangesError;
+/// An error that occurred when passing invalid ranges to [`Parser::set_included_ranges`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidRangesError {
+    /// The index of the first invalid range.
+    pub index: usize,
+    pub kind: InvalidRangeKind,
+}
+
+/// The reason why a range passed to [`Parser::set_included_ranges`] is invalid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidRangeKind {
+    /// The range ends before it starts.
+    Reversed,
+    /// The range starts before the previous range.
+    OutOfOrder,
+    /// The range starts after the previous range but before the previous range ends.
+    Overlap,
+    /// Tree-sitter rejected the ranges even though none of the other kinds apply. Tree-sitter
+    /// doesn't report which range it rejected so the index of the error is always 0.
+    Rejected,
+}
+
+impl InvalidRangesError {
+    /// Checks the ranges like `ts_parser_set_included_ranges` does.
+    fn check(ranges: &[Range]) -> Result<(), Self> {
+        let mut previous: Option<&Range> = None;
+        for (index, range) in ranges.iter().enumerate() {
+            let kind = if range.end_byte < range.start_byte {
+                Some(InvalidRangeKind::Reversed)
+            } else {
+                previous.and_then(|previous| {
+                    if range.start_byte < previous.start_byte {
+                        Some(InvalidRangeKind::OutOfOrder)
+                    } else if range.start_byte < previous.end_byte {
+                        Some(InvalidRangeKind::Overlap)
+                    } else {
+                        None
+                    }
+                })
+            };
+            if let Some(kind) = kind {
+                return Err(Self { index, kind });
+            }
+            previous = Some(range);
+        }
+        Ok(())
+    }
+}
 
 impl fmt::Display for InvalidRangesError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "include ranges overlap or are not sorted",)
+        let reason = match self.kind {
+            InvalidRangeKind::Reversed => "ends before it starts",
+            InvalidRangeKind::OutOfOrder => "starts before the previous range",
+            InvalidRangeKind::Overlap => "overlaps the previous range",
+            InvalidRangeKind::Rejected => {
+                return f.write_str("tree-sitter rejected the included ranges")
+            }
+        };
+        write!(f, "included range {} {reason}", self.index)
     }
 }
 impl std::error::Error for InvalidRangesError {}
@@ -197,22 +318,34 @@ pub enum InputEncoding {
     Custom,
 }
 
-#[allow(unused)]
+/// The state of an in-progress parse passed to the callback of [`Parser::parse_with_progress`].
 #[repr(C)]
 #[derive(Debug)]
-struct ParseState {
+pub struct ParseState {
     /// The payload passed via `ParseOptions`' `payload` field.
     payload: NonNull<c_void>,
     current_byte_offset: u32,
     has_error: bool,
 }
 
+impl ParseState {
+    /// The byte offset the parser has currently reached.
+    #[must_use]
+    pub fn current_byte_offset(&self) -> u32 {
+        self.current_byte_offset
+    }
+
+    /// Whether the parser has encountered a syntax error so far.
+    #[must_use]
+    pub fn has_error(&self) -> bool {
+        self.has_error
+    }
+}
+
 /// A function that accepts the current parser state and returns `true` when the parse should be
 /// cancelled.
-#[allow(unused)]
 type ProgressCallback = unsafe extern "C" fn(state: NonNull<ParseState>) -> bool;
 
-#[allow(unused)]
 #[repr(C)]
 #[derive(Debug, Default)]
 struct ParseOptions {
@@ -250,11 +383,10 @@ extern "C" {
         count: u32,
     ) -> bool;
 
-    fn ts_parser_parse(
-        parser: NonNull<ParserData>,
-        old_tree: Option<NonNull<SyntaxTreeData>>,
-        input: ParserInputRaw,
-    ) -> Option<NonNull<SyntaxTreeData>>;
+    /// Get the ranges of text that the parser will include when parsing. The returned pointer
+    /// is owned by the parser. The caller should not free it or write to it. The length of the
+    /// array will be written to the given `count` pointer.
+    fn ts_parser_included_ranges(parser: NonNull<ParserData>, count: &mut u32) -> *const Range;
 
     /// Set the maximum duration in microseconds that parsing should be allowed to
     /// take before halting.
@@ -269,7 +401,6 @@ extern "C" {
     /// See `ts_parser_parse` for more details.
     ///
     /// See `TSParseOptions` for more details on the options.
-    #[allow(unused)]
     fn ts_parser_parse_with_options(
         parser: NonNull<ParserData>,
         old_tree: Option<NonNull<SyntaxTreeData>>,
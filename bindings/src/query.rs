@@ -5,9 +5,11 @@ use std::{slice, str};
 
 use crate::query::predicate::TextPredicate;
 pub use crate::query::predicate::{InvalidPredicateError, Predicate};
+use crate::query::property::QueryProperty;
 use crate::Grammar;
 
 mod predicate;
+pub mod predicates;
 mod property;
 
 #[derive(Debug)]
@@ -59,6 +61,7 @@ pub enum QueryData {}
 #[derive(Debug)]
 pub(super) struct PatternData {
     text_predicates: Range<u32>,
+    property_settings: Range<u32>,
 }
 
 #[derive(Debug)]
@@ -67,6 +70,7 @@ pub struct Query {
     num_captures: u32,
     num_strings: u32,
     text_predicates: Vec<TextPredicate>,
+    property_settings: Vec<QueryProperty>,
     patterns: Box<[PatternData]>,
 }
 
@@ -95,6 +99,7 @@ impl Query {
         let bytes = source.as_bytes();
 
         // Compile the query.
+        crate::alloc::lock_allocator();
         let ptr = unsafe {
             ts_query_new(
                 grammar,
@@ -160,6 +165,7 @@ impl Query {
             num_captures,
             num_strings,
             text_predicates: Vec::new(),
+            property_settings: Vec::new(),
             patterns: Box::default(),
         };
         let patterns: Result<_, ParseError> = (0..num_patterns)
@@ -267,33 +273,111 @@ impl Query {
         None
     }
 
-    pub(crate) fn pattern_text_predicates(&self, pattern_idx: u16) -> &[TextPredicate] {
-        let range = self.patterns[pattern_idx as usize].text_predicates.clone();
+    /// Returns the keys and values of the `(#set! key value)` properties of the given pattern
+    /// in the order they appear in the query.
+    ///
+    /// The properties are also passed to the custom predicate callback of [`Query::new`] as
+    /// [`UserPredicate::SetProperty`].
+    pub fn property_settings(
+        &self,
+        pattern: Pattern,
+    ) -> impl ExactSizeIterator<Item = (&str, Option<&str>)> {
+        let range = self.patterns[pattern.idx()].property_settings.clone();
+        self.property_settings[range.start as usize..range.end as usize]
+            .iter()
+            .map(|property| {
+                (
+                    property.key.get(self),
+                    property.val.map(|val| val.get(self)),
+                )
+            })
+    }
+
+    pub(crate) fn pattern_text_predicates(&self, pattern: Pattern) -> &[TextPredicate] {
+        let range = self.patterns[pattern.idx()].text_predicates.clone();
         &self.text_predicates[range.start as usize..range.end as usize]
     }
 
     /// Get the byte offset where the given pattern starts in the query's
     /// source.
+    ///
+    /// The offset points into the query text that was passed to [`Query::new`].
     #[doc(alias = "ts_query_start_byte_for_pattern")]
     #[must_use]
-    pub fn start_byte_for_pattern(&self, pattern: Pattern) -> usize {
+    pub fn start_byte_for_pattern(&self, pattern: Pattern) -> u32 {
         assert!(
-            pattern.0 < self.text_predicates.len() as u32,
+            pattern.idx() < self.patterns.len(),
             "Pattern index is {pattern:?} but the pattern count is {}",
-            self.text_predicates.len(),
+            self.patterns.len(),
         );
-        unsafe { ts_query_start_byte_for_pattern(self.raw, pattern.0) as usize }
+        unsafe { ts_query_start_byte_for_pattern(self.raw, pattern.0) }
     }
 
-    /// Get the number of patterns in the query.
+    /// Check if the given pattern in the query has a single root node.
+    #[doc(alias = "ts_query_is_pattern_rooted")]
     #[must_use]
-    pub fn pattern_count(&self) -> usize {
-        unsafe { ts_query_pattern_count(self.raw) as usize }
+    pub fn is_pattern_rooted(&self, pattern: Pattern) -> bool {
+        assert!(
+            pattern.idx() < self.patterns.len(),
+            "Pattern index is {pattern:?} but the pattern count is {}",
+            self.patterns.len(),
+        );
+        unsafe { ts_query_is_pattern_rooted(self.raw, pattern.0) }
     }
+
+    /// Check if the given pattern in the query is 'non local'.
+    ///
+    /// A non-local pattern has multiple root nodes and can match within a
+    /// repeating sequence of nodes, as specified by the grammar. Non-local
+    /// patterns disable certain optimizations that would otherwise be possible
+    /// when executing a query on a specific range of a syntax tree.
+    #[doc(alias = "ts_query_is_pattern_non_local")]
+    #[must_use]
+    pub fn is_pattern_non_local(&self, pattern: Pattern) -> bool {
+        assert!(
+            pattern.idx() < self.patterns.len(),
+            "Pattern index is {pattern:?} but the pattern count is {}",
+            self.patterns.len(),
+        );
+        unsafe { ts_query_is_pattern_non_local(self.raw, pattern.0) }
+    }
+
+    /// Get the quantifier of the given capture within the given pattern.
+    ///
+    /// For example the capture `@x` has the quantifier [`Quantifier::OneOrMore`] in the pattern
+    /// `(comment)+ @x` and [`Quantifier::Zero`] in patterns which do not use the capture at all.
+    #[doc(alias = "ts_query_capture_quantifier_for_id")]
+    #[must_use]
+    pub fn capture_quantifier(&self, pattern: Pattern, capture: Capture) -> Quantifier {
+        assert!(
+            pattern.idx() < self.patterns.len(),
+            "Pattern index is {pattern:?} but the pattern count is {}",
+            self.patterns.len(),
+        );
+        assert!(capture.0 < self.num_captures, "invalid capture index");
+        let quantifier =
+            unsafe { ts_query_capture_quantifier_for_id(self.raw, pattern.0, capture.0) };
+        match quantifier {
+            0 => Quantifier::Zero,
+            1 => Quantifier::ZeroOrOne,
+            2 => Quantifier::ZeroOrMore,
+            3 => Quantifier::One,
+            4 => Quantifier::OneOrMore,
+            _ => unreachable!("invalid quantifier {quantifier}"),
+        }
+    }
+
     /// Get the number of patterns in the query.
+    #[doc(alias = "ts_query_pattern_count")]
+    #[must_use]
+    pub fn pattern_count(&self) -> u32 {
+        self.patterns.len() as u32
+    }
+
+    /// Iterate over all patterns in the query.
     #[must_use]
     pub fn patterns(&self) -> impl ExactSizeIterator<Item = Pattern> {
-        (0..self.pattern_count() as u32).map(Pattern)
+        (0..self.pattern_count()).map(Pattern)
     }
 
     /// Disable a certain capture within a query.
@@ -307,6 +391,23 @@ impl Query {
             ts_query_disable_capture(self.raw, bytes.as_ptr(), bytes.len() as u32);
         }
     }
+
+    /// Disable a certain pattern within a query.
+    ///
+    /// This prevents the pattern from matching and removes most of the overhead associated
+    /// with the pattern. Currently, there is no way to undo this.
+    ///
+    /// Patterns should be disabled before the query is executed by a query cursor: cursors
+    /// which are already running the query may still return matches of the pattern.
+    #[doc(alias = "ts_query_disable_pattern")]
+    pub fn disable_pattern(&mut self, pattern: Pattern) {
+        assert!(
+            pattern.idx() < self.patterns.len(),
+            "Pattern index is {pattern:?} but the pattern count is {}",
+            self.patterns.len(),
+        );
+        unsafe { ts_query_disable_pattern(self.raw, pattern.0) }
+    }
 }
 
 impl Drop for Query {
@@ -319,6 +420,26 @@ impl Drop for Query {
 #[repr(transparent)]
 pub struct Capture(u32);
 
+/// How often a capture may occur within a single match of a pattern.
+///
+/// See [`Query::capture_quantifier`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Quantifier {
+    /// The capture does not occur in the pattern.
+    Zero,
+    ZeroOrOne,
+    ZeroOrMore,
+    One,
+    OneOrMore,
+}
+
+impl Quantifier {
+    /// Whether the capture may capture more than one node in a single match.
+    pub fn is_repeated(self) -> bool {
+        matches!(self, Quantifier::ZeroOrMore | Quantifier::OneOrMore)
+    }
+}
+
 impl Capture {
     pub fn name(self, query: &Query) -> &str {
         query.capture_name(self)
@@ -340,6 +461,8 @@ impl QueryStr {
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct ParserErrorLocation {
+    /// at which byte offset in the query text the error starts
+    pub byte_offset: u32,
     /// at which line the error occurred
     pub line: u32,
     /// at which codepoints/columns the errors starts in the line
@@ -386,6 +509,7 @@ impl ParserErrorLocation {
         }
 
         ParserErrorLocation {
+            byte_offset: start as u32,
             line: line as u32,
             column: column as u32,
             len: len as u32,
@@ -459,6 +583,50 @@ pub enum ParseError {
     ImpossiblePattern(ParserErrorLocation),
 }
 
+impl ParseError {
+    /// Returns the kind of this error, mirroring tree-sitter's `TSQueryError`.
+    pub fn kind(&self) -> QueryErrorKind {
+        match self {
+            ParseError::UnexpectedEof | ParseError::SyntaxError(_) => QueryErrorKind::Syntax,
+            ParseError::InvalidNodeType { .. } => QueryErrorKind::NodeType,
+            ParseError::InvalidFieldName { .. } => QueryErrorKind::Field,
+            ParseError::InvalidCaptureName { .. } => QueryErrorKind::Capture,
+            ParseError::InvalidPredicate { .. } => QueryErrorKind::Predicate,
+            ParseError::ImpossiblePattern(_) => QueryErrorKind::Structure,
+        }
+    }
+
+    /// Returns the location of the error within the query text, if known.
+    pub fn location(&self) -> Option<&ParserErrorLocation> {
+        match self {
+            ParseError::UnexpectedEof => None,
+            ParseError::SyntaxError(location)
+            | ParseError::InvalidNodeType { location, .. }
+            | ParseError::InvalidFieldName { location, .. }
+            | ParseError::InvalidCaptureName { location, .. }
+            | ParseError::InvalidPredicate { location, .. }
+            | ParseError::ImpossiblePattern(location) => Some(location),
+        }
+    }
+}
+
+/// The kind of a [`ParseError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QueryErrorKind {
+    /// The query is not a valid S-expression.
+    Syntax,
+    /// The query references a node type which does not exist in the grammar.
+    NodeType,
+    /// The query references a field which does not exist in the grammar.
+    Field,
+    /// A predicate references a capture which is not defined in its pattern.
+    Capture,
+    /// The pattern can never match because of the structure of the grammar.
+    Structure,
+    /// A predicate is unknown or has invalid arguments.
+    Predicate,
+}
+
 #[repr(C)]
 // warns about never being constructed but it's constructed by C code
 // and wrwitten into a mutable reference
@@ -503,8 +671,22 @@ extern "C" {
     /// source code strings.
     fn ts_query_start_byte_for_pattern(query: NonNull<QueryData>, pattern_index: u32) -> u32;
 
-    // fn ts_query_is_pattern_rooted(query: NonNull<QueryData>, pattern_index: u32) -> bool;
-    // fn ts_query_is_pattern_non_local(query: NonNull<QueryData>, pattern_index: u32) -> bool;
+    /// Check if the given pattern in the query has a single root node.
+    fn ts_query_is_pattern_rooted(query: NonNull<QueryData>, pattern_index: u32) -> bool;
+    /// Check if the given pattern in the query is 'non local'.
+    ///
+    /// A non-local pattern has multiple root nodes and can match within a
+    /// repeating sequence of nodes, as specified by the grammar. Non-local
+    /// patterns disable certain optimizations that would otherwise be possible
+    /// when executing a query on a specific range of a syntax tree.
+    fn ts_query_is_pattern_non_local(query: NonNull<QueryData>, pattern_index: u32) -> bool;
+    /// Get the quantifier of the query's captures. Each capture is associated
+    /// with a numeric id based on the order that it appeared in the query's source.
+    fn ts_query_capture_quantifier_for_id(
+        query: NonNull<QueryData>,
+        pattern_index: u32,
+        capture_index: u32,
+    ) -> u32;
     // fn ts_query_is_pattern_guaranteed_at_step(query: NonNull<QueryData>, byte_offset: u32) -> bool;
     /// Get the name and length of one of the query's captures, or one of the
     /// query's string literals. Each capture and string is associated with a
@@ -527,4 +709,10 @@ extern "C" {
     /// any resource usage associated with recording the capture. Currently, there
     /// is no way to undo this.
     fn ts_query_disable_capture(self_: NonNull<QueryData>, name: *const u8, length: u32);
+
+    /// Disable a certain pattern within a query.
+    ///
+    /// This prevents the pattern from matching and removes most of the overhead
+    /// associated with the pattern. Currently, there is no way to undo this.
+    fn ts_query_disable_pattern(self_: NonNull<QueryData>, pattern_index: u32);
 }
@@ -139,13 +139,16 @@ impl Query {
         mut custom_predicate: impl FnMut(Pattern, UserPredicate) -> Result<(), InvalidPredicateError>,
     ) -> Result<PatternData, InvalidPredicateError> {
         let text_predicate_start = self.text_predicates.len() as u32;
+        let property_setting_start = self.property_settings.len() as u32;
 
         let predicate_steps = unsafe {
             let mut len = 0u32;
             let raw_predicates = ts_query_predicates_for_pattern(self.raw, pattern.0, &mut len);
-            (len != 0)
-                .then(|| slice::from_raw_parts(raw_predicates, len as usize))
-                .unwrap_or_default()
+            if len != 0 {
+                slice::from_raw_parts(raw_predicates, len as usize)
+            } else {
+                &[]
+            }
         };
         let predicates = predicate_steps
             .split(|step| step.kind == PredicateStepKind::Done)
@@ -160,7 +163,7 @@ impl Query {
                     let capture_idx = predicate.capture_arg(0)?;
                     let arg2 = predicate.arg(1);
 
-                    let negated = matches!(predicate.name(), "not-eq?" | "not-any-eq?");
+                    let negated = matches!(predicate.name(), "not-eq?" | "any-not-eq?");
                     let match_all = matches!(predicate.name(), "eq?" | "not-eq?");
                     let kind = match arg2 {
                         PredicateArg::Capture(capture) => TextPredicateKind::EqCapture(capture),
@@ -201,7 +204,8 @@ impl Query {
                             key: property.key.get(self),
                             val: property.val.map(|val| val.get(self)),
                         },
-                    )?
+                    )?;
+                    self.property_settings.push(property);
                 }
                 "is-not?" | "is?" => {
                     let property = QueryProperty::parse(&predicate)?;
@@ -238,6 +242,7 @@ impl Query {
         }
         Ok(PatternData {
             text_predicates: text_predicate_start..self.text_predicates.len() as u32,
+            property_settings: property_setting_start..self.property_settings.len() as u32,
         })
     }
 }
@@ -0,0 +1,50 @@
+use crate::query::{Pattern, Query};
+use crate::query_cursor::MatchedNode;
+use crate::{Input, IntoInput, QueryMatch};
+
+/// Evaluator for the standard text predicates of a [`Query`].
+///
+/// The text predicates are `#eq?`, `#not-eq?`, `#match?`, `#not-match?`, `#any-of?` and
+/// `#not-any-of?` plus the `#any-` prefixed variants of the `eq`/`match` predicates. They are
+/// parsed by [`Query::new`] and never passed to the custom predicate callback.
+///
+/// Matches yielded by a [`QueryCursor`](crate::QueryCursor) have already been checked against
+/// these predicates with the cursor's input. This evaluator is useful for consumers which need
+/// to check a match again, for example against a different revision of the source text.
+#[derive(Debug, Clone, Copy)]
+pub struct Predicates<'a> {
+    query: &'a Query,
+}
+
+impl<'a> Predicates<'a> {
+    pub fn new(query: &'a Query) -> Self {
+        Self { query }
+    }
+
+    /// Returns whether the given pattern has any text predicates.
+    pub fn has_predicates(&self, pattern: Pattern) -> bool {
+        !self.query.pattern_text_predicates(pattern).is_empty()
+    }
+
+    /// Checks whether all text predicates of the match's pattern are satisfied by the text of
+    /// the captured nodes in `input`.
+    pub fn is_satisfied(&self, query_match: &QueryMatch<'_, '_>, input: impl IntoInput) -> bool {
+        self.satisfied_by(
+            query_match.pattern(),
+            query_match.matched_nodes,
+            &mut input.into_input(),
+        )
+    }
+
+    pub(crate) fn satisfied_by<I: Input>(
+        &self,
+        pattern: Pattern,
+        matched_nodes: &[MatchedNode],
+        input: &mut I,
+    ) -> bool {
+        self.query
+            .pattern_text_predicates(pattern)
+            .iter()
+            .all(|predicate| predicate.satisfied(input, matched_nodes, self.query))
+    }
+}
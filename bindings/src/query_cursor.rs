@@ -1,23 +1,65 @@
 use core::slice;
-use std::cell::UnsafeCell;
+use std::cell::RefCell;
 use std::marker::PhantomData;
 use std::mem;
 use std::ops::Range;
 use std::ptr::{self, NonNull};
 
 use crate::node::NodeRaw;
+use crate::query::predicates::Predicates;
 use crate::query::{Capture, Pattern, Query, QueryData};
 use crate::{Input, IntoInput, Node, Tree};
 
 enum QueryCursorData {}
 
+/// The maximum number of unused query cursors kept around for reuse per thread.
+const MAX_CACHED_CURSORS: usize = 16;
+
 thread_local! {
-    static CURSOR_CACHE: UnsafeCell<Vec<InactiveQueryCursor>> = UnsafeCell::new(Vec::with_capacity(8));
+    /// Query cursors own an allocation for their in-progress matches which is expensive to
+    /// create, so cursors are returned to this pool when dropped and reused by
+    /// [`InactiveQueryCursor::new`].
+    static CURSOR_CACHE: RefCell<Vec<RawQueryCursor>> = RefCell::new(Vec::with_capacity(8));
 }
 
-/// SAFETY: must not call itself recursively
-unsafe fn with_cache<T>(f: impl FnOnce(&mut Vec<InactiveQueryCursor>) -> T) -> T {
-    CURSOR_CACHE.with(|cache| f(&mut *cache.get()))
+struct RawQueryCursor {
+    ptr: NonNull<QueryCursorData>,
+}
+
+impl Drop for RawQueryCursor {
+    fn drop(&mut self) {
+        unsafe { ts_query_cursor_delete(self.ptr.as_ptr()) }
+    }
+}
+
+fn take_cached_cursor() -> NonNull<QueryCursorData> {
+    let cached = CURSOR_CACHE
+        .try_with(|cache| cache.borrow_mut().pop())
+        .ok()
+        .flatten();
+    match cached {
+        Some(cached) => {
+            let ptr = cached.ptr;
+            mem::forget(cached);
+            ptr
+        }
+        None => {
+            crate::alloc::lock_allocator();
+            unsafe { NonNull::new_unchecked(ts_query_cursor_new()) }
+        }
+    }
+}
+
+fn return_cached_cursor(ptr: NonNull<QueryCursorData>) {
+    let cursor = RawQueryCursor { ptr };
+    // If the pool is full or has already been destroyed because the thread is exiting, the
+    // cursor is dropped (and deleted) instead.
+    let _ = CURSOR_CACHE.try_with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if cache.len() < MAX_CACHED_CURSORS {
+            cache.push(cursor);
+        }
+    });
 }
 
 pub struct QueryCursor<'a, 'tree, I: Input> {
@@ -47,16 +89,15 @@ impl<'tree, I: Input> QueryCursor<'_, 'tree, I> {
                     query_match.capture_count as usize,
                 )
             };
-            let satisfies_predicates = self
-                .query
-                .pattern_text_predicates(query_match.pattern_index)
-                .iter()
-                .all(|predicate| predicate.satisfied(&mut self.input, matched_nodes, self.query));
+            let pattern = Pattern(query_match.pattern_index as u32);
+            let satisfies_predicates =
+                Predicates::new(self.query).satisfied_by(pattern, matched_nodes, &mut self.input);
             if satisfies_predicates {
                 let res = QueryMatch {
                     id: query_match.id,
-                    pattern: Pattern(query_match.pattern_index as u32),
+                    pattern,
                     matched_nodes,
+                    query: self.query,
                     query_cursor: unsafe { self.ptr.as_mut() },
                     _tree: PhantomData,
                 };
@@ -86,16 +127,15 @@ impl<'tree, I: Input> QueryCursor<'_, 'tree, I> {
                     query_match.capture_count as usize,
                 )
             };
-            let satisfies_predicates = self
-                .query
-                .pattern_text_predicates(query_match.pattern_index)
-                .iter()
-                .all(|predicate| predicate.satisfied(&mut self.input, matched_nodes, self.query));
+            let pattern = Pattern(query_match.pattern_index as u32);
+            let satisfies_predicates =
+                Predicates::new(self.query).satisfied_by(pattern, matched_nodes, &mut self.input);
             if satisfies_predicates {
                 let res = QueryMatch {
                     id: query_match.id,
-                    pattern: Pattern(query_match.pattern_index as u32),
+                    pattern,
                     matched_nodes,
+                    query: self.query,
                     query_cursor: unsafe { self.ptr.as_mut() },
                     _tree: PhantomData,
                 };
@@ -108,6 +148,10 @@ impl<'tree, I: Input> QueryCursor<'_, 'tree, I> {
         }
     }
 
+    /// Restricts the remaining matches to nodes intersecting `range`.
+    ///
+    /// This may be called while iterating, for example to follow a moving viewport without
+    /// executing the query again. Matches which have already been found are not affected.
     pub fn set_byte_range(&mut self, range: Range<u32>) {
         unsafe {
             ts_query_cursor_set_byte_range(self.ptr.as_ptr(), range.start, range.end);
@@ -123,7 +167,7 @@ impl<'tree, I: Input> QueryCursor<'_, 'tree, I> {
 
 impl<I: Input> Drop for QueryCursor<'_, '_, I> {
     fn drop(&mut self) {
-        unsafe { with_cache(|cache| cache.push(InactiveQueryCursor { ptr: self.ptr })) }
+        return_cached_cursor(self.ptr)
     }
 }
 
@@ -135,15 +179,13 @@ pub struct InactiveQueryCursor {
 impl InactiveQueryCursor {
     #[must_use]
     pub fn new(range: Range<u32>, limit: u32) -> Self {
-        let mut this = unsafe {
-            with_cache(|cache| {
-                cache.pop().unwrap_or_else(|| InactiveQueryCursor {
-                    ptr: NonNull::new_unchecked(ts_query_cursor_new()),
-                })
-            })
+        let mut this = InactiveQueryCursor {
+            ptr: take_cached_cursor(),
         };
         this.set_byte_range(range);
         this.set_match_limit(limit);
+        // Cursors are reused so reset any limit set by a previous user.
+        this.set_max_start_depth(u32::MAX);
         this
     }
 
@@ -177,6 +219,19 @@ impl InactiveQueryCursor {
         }
     }
 
+    /// Set the maximum depth, relative to the node the query is executed on, at which the root
+    /// node of a pattern may start. Deeper nodes are still visited to match the children of a
+    /// pattern.
+    ///
+    /// A depth of zero only matches patterns rooted at the node the query is executed on.
+    /// `u32::MAX` removes the limit, which is the default.
+    #[doc(alias = "ts_query_cursor_set_max_start_depth")]
+    pub fn set_max_start_depth(&mut self, max_start_depth: u32) {
+        unsafe {
+            ts_query_cursor_set_max_start_depth(self.ptr.as_ptr(), max_start_depth);
+        }
+    }
+
     pub fn execute_query<'a, 'tree, I: IntoInput>(
         self,
         query: &'a Query,
@@ -203,7 +258,7 @@ impl Default for InactiveQueryCursor {
 
 impl Drop for InactiveQueryCursor {
     fn drop(&mut self) {
-        unsafe { ts_query_cursor_delete(self.ptr.as_ptr()) }
+        return_cached_cursor(self.ptr)
     }
 }
 
@@ -219,7 +274,8 @@ pub struct MatchedNode<'tree> {
 pub struct QueryMatch<'cursor, 'tree> {
     id: u32,
     pattern: Pattern,
-    matched_nodes: &'cursor [MatchedNode<'tree>],
+    pub(crate) matched_nodes: &'cursor [MatchedNode<'tree>],
+    query: &'cursor Query,
     query_cursor: &'cursor mut QueryCursorData,
     _tree: PhantomData<&'tree super::Tree>,
 }
@@ -246,6 +302,22 @@ impl<'tree> QueryMatch<'_, 'tree> {
             .map(|mat| &mat.node)
     }
 
+    /// Returns the first node captured by `capture` in this match.
+    pub fn capture_node(&self, capture: Capture) -> Option<&MatchedNode<'tree>> {
+        self.matched_nodes.iter().find(|mat| mat.capture == capture)
+    }
+
+    /// Returns all nodes captured by the capture named `name` in this match.
+    ///
+    /// The iterator is empty if the query has no capture with this name.
+    pub fn captures_named(&self, name: &str) -> impl Iterator<Item = &Node<'tree>> {
+        let capture = self.query.get_capture(name);
+        self.matched_nodes
+            .iter()
+            .filter(move |mat| Some(mat.capture) == capture)
+            .map(|mat| &mat.node)
+    }
+
     pub fn matched_node(&self, i: MatchedNodeIdx) -> &MatchedNode<'tree> {
         &self.matched_nodes[i as usize]
     }
@@ -260,10 +332,42 @@ impl<'tree> QueryMatch<'_, 'tree> {
         self.pattern
     }
 
+    /// Returns the number of captured nodes in this match.
+    ///
+    /// A capture which matched multiple nodes, for example a capture on a repeated pattern,
+    /// counts once per node.
+    #[must_use]
+    pub fn capture_count(&self) -> u32 {
+        self.matched_nodes.len() as u32
+    }
+
+    /// Returns the query which produced this match.
+    #[must_use]
+    pub fn query(&self) -> &Query {
+        self.query
+    }
+
+    /// Checks whether the standard text predicates of this match's pattern are satisfied by
+    /// the text of the captured nodes in `input`.
+    ///
+    /// Matches yielded by the cursor were already checked against the cursor's input, so this
+    /// is only needed to check a match against different text. See [`Predicates`].
+    pub fn satisfies_text_predicates(&self, input: impl IntoInput) -> bool {
+        Predicates::new(self.query).is_satisfied(self, input)
+    }
+
+    /// Removes this match from the cursor.
+    ///
+    /// [`QueryCursor::next_matched_node`] yields the captured nodes of a match one at a time,
+    /// interleaved with the nodes of other matches, and keeps returning nodes of this match
+    /// until all of its captures were returned. Removing the match stops the cursor from
+    /// returning its remaining nodes. This is how custom predicates reject a match: the first
+    /// node of the match is checked and the match is removed if it is rejected, so none of its
+    /// other nodes are returned. It is also useful to skip the rest of a match once it has been
+    /// fully processed.
+    ///
+    /// Removing a match does not affect other matches of the same pattern.
     #[doc(alias = "ts_query_cursor_remove_match")]
-    /// removes this match from the cursor so that further captures
-    /// from its cursor so that future captures that belong to this match
-    /// are no longer returned by capture iterators
     pub fn remove(self) {
         unsafe {
             ts_query_cursor_remove_match(self.query_cursor, self.id);
@@ -344,5 +448,8 @@ extern "C" {
     /// Set the range of bytes or (row, column) positions in which the query
     /// will be executed.
     fn ts_query_cursor_set_byte_range(self_: *mut QueryCursorData, start_byte: u32, end_byte: u32);
+    /// Set the maximum start depth for a query cursor. Set to `UINT32_MAX` to remove the
+    /// maximum start depth.
+    fn ts_query_cursor_set_max_start_depth(self_: *mut QueryCursorData, max_start_depth: u32);
 
 }
@@ -11,6 +11,10 @@ pub struct Tree {
     ptr: NonNull<SyntaxTreeData>,
 }
 
+/// An opaque identifier of a [`Tree`], see [`Tree::id`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TreeId(pub(crate) usize);
+
 impl Tree {
     pub(super) unsafe fn from_raw(raw: NonNull<SyntaxTreeData>) -> Tree {
         Tree { ptr: raw }
@@ -20,6 +24,31 @@ impl Tree {
         self.ptr
     }
 
+    /// Returns an identifier of this tree which is unique while the tree is alive.
+    ///
+    /// The identity of a tree is the underlying `TSTree` pointer rather than its contents: a
+    /// [`Clone`] of a tree has a new identity even though it is structurally identical, and an
+    /// edited tree keeps its identity. Ids may be reused once a tree is dropped.
+    #[inline]
+    pub fn id(&self) -> TreeId {
+        TreeId(self.ptr.as_ptr() as usize)
+    }
+
+    /// Checks whether both handles refer to the same tree, see [`Tree::id`].
+    #[inline]
+    pub fn ptr_eq(&self, other: &Tree) -> bool {
+        self.ptr == other.ptr
+    }
+
+    /// Checks whether `node` belongs to this tree.
+    ///
+    /// This compares the identity of the trees (see [`Tree::id`]) so nodes of the original tree
+    /// don't belong to a clone.
+    #[inline]
+    pub fn contains_node(&self, node: &Node) -> bool {
+        node.tree_id() == self.id()
+    }
+
     pub fn root_node(&self) -> Node<'_> {
         unsafe { Node::from_raw(ts_tree_root_node(self.ptr)).unwrap() }
     }
@@ -133,11 +162,7 @@ impl ChangedRanges {
 impl Drop for ChangedRanges {
     fn drop(&mut self) {
         if !self.ptr.is_null() {
-            // Use the standard C library free, which is what tree-sitter uses internally.
-            extern "C" {
-                fn free(ptr: *mut std::ffi::c_void);
-            }
-            unsafe { free(self.ptr.cast()) }
+            unsafe { crate::ts_free(self.ptr.cast()) }
         }
     }
 }
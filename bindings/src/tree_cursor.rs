@@ -162,6 +162,53 @@ impl<'tree> Iterator for TreeRecursiveWalker<'tree> {
     }
 }
 
+/// An iterator over a node and its descendants up to a maximum depth, see
+/// [`Node::descendants_within_depth`].
+pub struct DescendantsWithinDepth<'tree> {
+    cursor: TreeCursor<'tree>,
+    depth: u32,
+    max_depth: u32,
+    done: bool,
+}
+
+impl<'tree> DescendantsWithinDepth<'tree> {
+    pub(crate) fn new(cursor: TreeCursor<'tree>, max_depth: u32) -> Self {
+        Self {
+            cursor,
+            depth: 0,
+            max_depth,
+            done: false,
+        }
+    }
+}
+
+impl<'tree> Iterator for DescendantsWithinDepth<'tree> {
+    type Item = Node<'tree>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let node = self.cursor.node();
+        if self.depth < self.max_depth && self.cursor.goto_first_child() {
+            self.depth += 1;
+        } else {
+            loop {
+                if self.depth == 0 {
+                    self.done = true;
+                    break;
+                }
+                if self.cursor.goto_next_sibling() {
+                    break;
+                }
+                self.cursor.goto_parent();
+                self.depth -= 1;
+            }
+        }
+        Some(node)
+    }
+}
+
 extern "C" {
     /// Create a new tree cursor starting from the given node.
     ///
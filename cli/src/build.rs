@@ -2,6 +2,7 @@ use std::num::NonZeroUsize;
 use std::path::PathBuf;
 
 use anyhow::Context;
+use skidder::ScannerKind;
 
 use crate::flags;
 
@@ -15,8 +16,37 @@ impl flags::Build {
             repos: vec![skidder::Repo::Local { path: repo }],
             index: PathBuf::new(),
             verbose: self.verbose,
+            verify_checksums: false,
+            queries_path: None,
         };
-        if let Some(grammar) = self.grammar {
+        if self.dry_run {
+            let grammars = match self.grammar {
+                Some(grammar) => vec![grammar],
+                None => skidder::list_grammars(&config)?
+                    .iter()
+                    .filter_map(|path| Some(path.file_name()?.to_str()?.to_owned()))
+                    .collect(),
+            };
+            for grammar in grammars {
+                let plan = skidder::build_grammar_plan(&config, &grammar, self.force)?;
+                match plan.reason {
+                    None => println!("{grammar}: fresh"),
+                    Some(reason) => {
+                        let scanner = match plan.scanner {
+                            Some(ScannerKind::C) => "C scanner",
+                            Some(ScannerKind::Cpp) => "C++ scanner",
+                            None => "no scanner",
+                        };
+                        let parser = if plan.parser_compressed {
+                            "compressed parser"
+                        } else {
+                            "parser"
+                        };
+                        println!("{grammar}: would compile {parser} and {scanner} ({reason})")
+                    }
+                }
+            }
+        } else if let Some(grammar) = self.grammar {
             skidder::build_grammar(&config, &grammar, self.force)?;
         } else {
             skidder::build_all_grammars(
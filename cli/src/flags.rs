@@ -7,32 +7,64 @@ xflags::xflags! {
         cmd import {
             /// Whether to import queries
             optional --import-queries
+            /// Only import queries, leaving the parser and metadata untouched
+            optional --queries-only
             /// Whether to (re)generate metadata
             optional --metadata
+            /// The zstd compression level of `parser.c` and `grammar.json`.
+            /// Defaults to 22
+            optional --compression-level compression_level: u32
             /// The repository/directory where repos are copied into.
             /// Defaults to the current working directory
             optional -r,--repo repo: PathBuf
             /// The path of the grammars to import. The name of the directory
             /// will be used as the grammar name. To overwrite you can append
-            /// the grammar name with a colon
+            /// the grammar name with a colon. A directory without a `src/parser.c`
+            /// imports each subdirectory which contains one as a separate grammar
             repeated path: PathBuf
         }
         cmd build {
             optional --verbose
             optional -j, --threads threads: usize
             optional -f, --force
+            /// Print whether each grammar would be compiled and why instead of compiling it
+            optional --dry-run
             required repo: PathBuf
             optional grammar: String
         }
+        /// Updates the git repositories of a config file to the latest revision
+        cmd update {
+            optional --verbose
+            /// Build the grammars after updating them
+            optional -b, --build
+            optional -j, --threads threads: usize
+            /// Rebuild grammars even if they are up to date
+            optional -f, --force
+            /// Only update the repository which provides this grammar
+            optional --grammar grammar: String
+            /// The config file listing the repositories
+            required config: PathBuf
+        }
         cmd init-repo {
             required repo: PathBuf
         }
         cmd load-grammar {
             optional -r, --recursive
+            /// Print the ABI version of every loaded grammar
+            optional -v, --verbose
             required path: PathBuf
         }
         cmd regenerate-parser {
             optional -r, --recursive
+            /// Only report outdated parsers instead of updating them
+            optional --check
+            optional -j, --threads threads: usize
+            /// Skip all grammars whose name sorts before this name
+            optional --from from: String
+            /// Only regenerate the grammars with these names
+            repeated --only only: String
+            /// Skip the grammars with these names
+            repeated --skip skip: String
             required path: PathBuf
         }
         default cmd version {
@@ -53,6 +85,7 @@ pub enum SkidderCmd {
     Version(Version),
     Import(Import),
     Build(Build),
+    Update(Update),
     InitRepo(InitRepo),
     LoadGrammar(LoadGrammar),
     RegenerateParser(RegenerateParser),
@@ -68,7 +101,9 @@ pub struct Import {
     pub path: Vec<PathBuf>,
 
     pub import_queries: bool,
+    pub queries_only: bool,
     pub metadata: bool,
+    pub compression_level: Option<u32>,
     pub repo: Option<PathBuf>,
 }
 
@@ -80,6 +115,18 @@ pub struct Build {
     pub verbose: bool,
     pub threads: Option<usize>,
     pub force: bool,
+    pub dry_run: bool,
+}
+
+#[derive(Debug)]
+pub struct Update {
+    pub config: PathBuf,
+
+    pub verbose: bool,
+    pub build: bool,
+    pub threads: Option<usize>,
+    pub force: bool,
+    pub grammar: Option<String>,
 }
 
 #[derive(Debug)]
@@ -92,6 +139,7 @@ pub struct LoadGrammar {
     pub path: PathBuf,
 
     pub recursive: bool,
+    pub verbose: bool,
 }
 
 #[derive(Debug)]
@@ -99,6 +147,11 @@ pub struct RegenerateParser {
     pub path: PathBuf,
 
     pub recursive: bool,
+    pub check: bool,
+    pub threads: Option<usize>,
+    pub from: Option<String>,
+    pub only: Vec<String>,
+    pub skip: Vec<String>,
 }
 
 impl Skidder {
@@ -1,7 +1,11 @@
 use std::fs::{self, File};
 use std::io::Write;
+use std::num::NonZeroUsize;
 use std::path::Path;
 use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
 
 use anyhow::{ensure, Context, Result};
 use skidder::{decompress, Metadata};
@@ -9,7 +13,6 @@ use tempfile::TempDir;
 
 use crate::collect_grammars;
 use crate::flags::RegenerateParser;
-use crate::import::import_compressed;
 
 impl RegenerateParser {
     pub fn run(self) -> Result<()> {
@@ -18,104 +21,173 @@ impl RegenerateParser {
         } else {
             vec![self.path.clone()]
         };
-        let temp_dir =
-            TempDir::new().context("failed to create temporary directory for decompression")?;
-        // create dummy file to prevent TS CLI from creating a full skeleton
-        File::create(temp_dir.path().join("grammar.js"))
-            .context("failed to create temporary directory for decompression")?;
-        let mut failed = Vec::new();
-        for grammar_dir in paths {
-            let grammar_name = grammar_dir.file_name().unwrap().to_str().unwrap();
-            if grammar_name <= "dart" {
-                continue;
+        let paths: Vec<_> = paths
+            .into_iter()
+            .filter(|grammar_dir| !self.is_skipped(grammar_name(grammar_dir)))
+            .collect();
+        let concurrency = self
+            .threads
+            .and_then(NonZeroUsize::new)
+            .or_else(|| thread::available_parallelism().ok())
+            .map_or(4, usize::from)
+            .min(paths.len());
+        let i = AtomicUsize::new(0);
+        let failed = Mutex::new(Vec::new());
+        let outdated = Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            for _ in 0..concurrency {
+                scope.spawn(|| {
+                    // `tree-sitter generate` writes into the current directory so every worker
+                    // needs its own temporary directory.
+                    let temp_dir = match temp_build_dir() {
+                        Ok(temp_dir) => temp_dir,
+                        Err(err) => {
+                            eprintln!("error: {err:#}");
+                            return;
+                        }
+                    };
+                    while let Some(grammar_dir) = paths.get(i.fetch_add(1, Ordering::Relaxed)) {
+                        let grammar_name = grammar_name(grammar_dir);
+                        match self.regenerate(grammar_dir, grammar_name, temp_dir.path()) {
+                            Ok(true) => {}
+                            Ok(false) => outdated.lock().unwrap().push(grammar_name.to_owned()),
+                            Err(err) => {
+                                eprintln!("error: {err:#}");
+                                failed.lock().unwrap().push(grammar_name.to_owned())
+                            }
+                        }
+                    }
+                });
             }
-            println!("checking {grammar_name}");
+        });
+        let mut failed = failed.into_inner().unwrap();
+        let mut outdated = outdated.into_inner().unwrap();
+        failed.sort();
+        outdated.sort();
+        ensure!(
+            failed.is_empty(),
+            "failed to regenerate parser.c for {failed:?}!"
+        );
+        ensure!(
+            !self.check || outdated.is_empty(),
+            "parser.c files are not up to date for {outdated:?}!"
+        );
+        if !outdated.is_empty() {
+            println!("updated parser.c for {outdated:?}");
+        }
+        Ok(())
+    }
+
+    fn is_skipped(&self, grammar_name: &str) -> bool {
+        self.from.as_deref().is_some_and(|from| grammar_name < from)
+            || !self.only.is_empty() && !self.only.iter().any(|only| only == grammar_name)
+            || self.skip.iter().any(|skip| skip == grammar_name)
+    }
 
-            let compressed = Metadata::read(&grammar_dir.join("metadata.json"))
-                .with_context(|| format!("failed to read metadata for {grammar_name}"))?
-                .parser_definition()
-                .unwrap()
-                .compressed;
+    /// Regenerates the parser of a single grammar in `temp_dir`, returning whether the existing
+    /// `parser.c` was up to date.
+    fn regenerate(&self, grammar_dir: &Path, grammar_name: &str, temp_dir: &Path) -> Result<bool> {
+        println!("checking {grammar_name}");
 
-            let src_path = grammar_dir.join("src");
-            let src_grammar_path = src_path.join("grammar.json");
-            let grammar_path = temp_dir.path().join("grammar.json");
-            if !src_grammar_path.exists() {
-                eprintln!("grammar.json not found for {grammar_name}");
-                failed.push(grammar_name.to_owned());
-                continue;
-            }
-            if compressed {
-                let dst = File::create(&grammar_path).with_context(|| {
-                    format!(
-                        "failed to create grammar.json file in temporary build directory {}",
-                        temp_dir.path().display()
-                    )
-                })?;
-                decompress_file(&src_grammar_path, dst).with_context(|| {
-                    format!("failed to decompress grammar.json for {grammar_name}")
-                })?;
-            } else {
-                fs::copy(src_grammar_path, &grammar_path)
-                    .with_context(|| format!("failed to copy grammar.json for {grammar_name}"))?;
-            }
-            println!("running tree-sitter generate {}", grammar_path.display());
-            let res = Command::new("tree-sitter")
-                .arg("generate")
-                .arg("--no-bindings")
-                .arg(&grammar_path)
-                .current_dir(temp_dir.path())
-                .status()
-                .with_context(|| {
-                    format!(
-                        "failed to execute tree-sitter generate {}",
-                        grammar_path.display()
-                    )
-                })?
-                .success();
-            if !res {
-                eprintln!(
+        let compressed = Metadata::read(&grammar_dir.join("metadata.json"))
+            .with_context(|| format!("failed to read metadata for {grammar_name}"))?
+            .parser_definition()
+            .with_context(|| format!("{grammar_name} reuses the parser of another grammar"))?
+            .compressed;
+
+        let src_path = grammar_dir.join("src");
+        let src_grammar_path = src_path.join("grammar.json");
+        let grammar_path = temp_dir.join("grammar.json");
+        ensure!(
+            src_grammar_path.exists(),
+            "grammar.json not found for {grammar_name}"
+        );
+        if compressed {
+            let dst = File::create(&grammar_path).with_context(|| {
+                format!(
+                    "failed to create grammar.json file in temporary build directory {}",
+                    temp_dir.display()
+                )
+            })?;
+            decompress_file(&src_grammar_path, dst)
+                .with_context(|| format!("failed to decompress grammar.json for {grammar_name}"))?;
+        } else {
+            fs::copy(src_grammar_path, &grammar_path)
+                .with_context(|| format!("failed to copy grammar.json for {grammar_name}"))?;
+        }
+        println!("running tree-sitter generate {}", grammar_path.display());
+        let res = Command::new("tree-sitter")
+            .arg("generate")
+            .arg("--no-bindings")
+            .arg(&grammar_path)
+            .current_dir(temp_dir)
+            .status()
+            .with_context(|| {
+                format!(
                     "failed to execute tree-sitter generate {}",
                     grammar_path.display()
-                );
-                failed.push(grammar_name.to_owned());
-                continue;
-            }
+                )
+            })?
+            .success();
+        ensure!(
+            res,
+            "failed to execute tree-sitter generate {}",
+            grammar_path.display()
+        );
 
-            let new_parser_path = temp_dir.path().join("src").join("parser.c");
-            let old_parser_path = src_path.join("parser.c");
-            let mut old_parser = Vec::new();
+        let new_parser_path = temp_dir.join("src").join("parser.c");
+        let old_parser_path = src_path.join("parser.c");
+        let mut old_parser = Vec::new();
+        if compressed {
             decompress_file(&old_parser_path, &mut old_parser)
                 .with_context(|| format!("failed to decompress parser for {grammar_name}"))?;
-            let old_parser = String::from_utf8_lossy(&old_parser);
-            let new_parser = fs::read_to_string(&new_parser_path)
-                .context("tree-sitter cli did not generate parser.c")?;
-            if old_parser.trim() == new_parser.trim() {
-                continue;
-            }
-            failed.push(grammar_name.to_owned());
-            eprintln!("existing parser.c was outdated updating...");
-            if compressed {
-                import_compressed(&new_parser_path, &old_parser_path).with_context(|| {
-                    format!("failed to compress new parser.c for {grammar_name}")
-                })?;
-            } else {
-                fs::copy(&new_parser_path, &old_parser_path)
-                    .with_context(|| format!("failed to opy new parser.c for {grammar_name}"))?;
-            }
+        } else {
+            old_parser = fs::read(&old_parser_path)
+                .with_context(|| format!("failed to read parser for {grammar_name}"))?;
         }
-        ensure!(
-            failed.is_empty(),
-            "parser.c files is not up to date for {failed:?}!"
-        );
-        Ok(())
+        let old_parser = String::from_utf8_lossy(&old_parser);
+        let new_parser = fs::read_to_string(&new_parser_path)
+            .context("tree-sitter cli did not generate parser.c")?;
+        if old_parser.trim() == new_parser.trim() {
+            return Ok(true);
+        }
+        if self.check {
+            eprintln!("existing parser.c of {grammar_name} is outdated");
+            return Ok(false);
+        }
+        eprintln!("existing parser.c of {grammar_name} was outdated updating...");
+        if compressed {
+            skidder::compress(
+                &new_parser_path,
+                &old_parser_path,
+                skidder::DEFAULT_COMPRESSION_LEVEL,
+            )
+            .with_context(|| format!("failed to compress new parser.c for {grammar_name}"))?;
+        } else {
+            fs::copy(&new_parser_path, &old_parser_path)
+                .with_context(|| format!("failed to copy new parser.c for {grammar_name}"))?;
+        }
+        Ok(false)
     }
 }
 
+fn grammar_name(grammar_dir: &Path) -> &str {
+    grammar_dir.file_name().unwrap().to_str().unwrap()
+}
+
+fn temp_build_dir() -> Result<TempDir> {
+    let temp_dir =
+        TempDir::new().context("failed to create temporary directory for decompression")?;
+    // create dummy file to prevent TS CLI from creating a full skeleton
+    File::create(temp_dir.path().join("grammar.js"))
+        .context("failed to create temporary directory for decompression")?;
+    Ok(temp_dir)
+}
+
 fn decompress_file(src: &Path, dst: impl Write) -> Result<()> {
     File::open(src)
         .map_err(anyhow::Error::from)
-        .and_then(|mut reader| decompress(&mut reader, dst))
+        .and_then(|mut reader| Ok(decompress(&mut reader, dst)?))
         .with_context(|| format!("failed to decompress {}", src.display()))?;
     Ok(())
 }
@@ -1,7 +1,5 @@
-use std::ffi::c_void;
-
 use anyhow::{Context, Result};
-use libloading::Symbol;
+use tree_house_bindings::LoadedGrammar;
 
 use crate::collect_grammars;
 use crate::flags::LoadGrammar;
@@ -18,13 +16,12 @@ impl LoadGrammar {
                 continue;
             };
             println!("loading {}", path.display());
-            unsafe {
-                let lib = libloading::Library::new(&path)
-                    .with_context(|| format!("failed to load {}", path.display()))?;
-                let language_fn_name = format!("tree_sitter_{}", name.replace('-', "_"));
-                let _language_fn: Symbol<unsafe extern "C" fn() -> *mut c_void> = lib
-                    .get(language_fn_name.as_bytes())
-                    .with_context(|| format!("failed to load {}", path.display()))?;
+            // The grammar is only used within this iteration so the library can be closed
+            // again when `grammar` is dropped.
+            let grammar = unsafe { LoadedGrammar::new(name, &path) }
+                .with_context(|| format!("failed to load {}", path.display()))?;
+            if self.verbose {
+                println!("loaded {name} (ABI {})", grammar.abi_version());
             }
         }
         Ok(())
@@ -10,6 +10,7 @@ mod generate_parser;
 mod import;
 mod init;
 mod load;
+mod update;
 
 fn get_version() -> String {
     const GIT_HASH: Option<&str> = option_env!("GIT_HASH");
@@ -28,6 +29,7 @@ fn wrapped_main() -> Result<()> {
     match flags.subcommand {
         flags::SkidderCmd::Import(import_cmd) => import_cmd.run(),
         flags::SkidderCmd::Build(build_cmd) => build_cmd.run(),
+        flags::SkidderCmd::Update(update_cmd) => update_cmd.run(),
         flags::SkidderCmd::InitRepo(init_cmd) => init_cmd.run(),
         flags::SkidderCmd::LoadGrammar(load_cmd) => load_cmd.run(),
         flags::SkidderCmd::RegenerateParser(generate_cmd) => generate_cmd.run(),
@@ -58,6 +60,8 @@ fn collect_grammars(repo: &Path) -> Result<Vec<PathBuf>> {
         }],
         index: PathBuf::new(),
         verbose: false,
+        verify_checksums: false,
+        queries_path: None,
     };
-    list_grammars(&config)
+    Ok(list_grammars(&config)?)
 }
@@ -0,0 +1,63 @@
+use std::num::NonZeroUsize;
+use std::time::Duration;
+
+use anyhow::bail;
+use indicatif::{ProgressBar, ProgressStyle};
+use skidder::SkidderError;
+
+use crate::flags;
+
+impl flags::Update {
+    pub fn run(self) -> anyhow::Result<()> {
+        let mut config = skidder::Config::from_path(&self.config)?;
+        config.verbose |= self.verbose;
+        match &self.grammar {
+            Some(grammar) => {
+                // A grammar of a repository which was never cloned can't be found, so clone the
+                // missing repositories first.
+                if config.grammar_dir(grammar).is_none() {
+                    fetch(&config, false)?;
+                }
+                let Some(&(repo, _)) = config.find_grammar(grammar).first() else {
+                    bail!(SkidderError::GrammarNotFound(grammar.clone()));
+                };
+                config.repos = vec![config.repos[repo].clone()];
+                fetch(&config, true)?;
+            }
+            None => fetch(&config, true)?,
+        }
+        if !self.build {
+            return Ok(());
+        }
+        match &self.grammar {
+            Some(grammar) => {
+                skidder::build_grammar(&config, grammar, self.force)?;
+            }
+            None => {
+                skidder::build_all_grammars(
+                    &config,
+                    self.force,
+                    self.threads.and_then(NonZeroUsize::new),
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fetches the repositories while showing a spinner with the repository which is being fetched,
+/// unless the config is verbose and git reports its own progress.
+fn fetch(config: &skidder::Config, update: bool) -> Result<(), SkidderError> {
+    if config.verbose {
+        return skidder::fetch(config, update);
+    }
+    let bar = ProgressBar::new_spinner()
+        .with_style(ProgressStyle::with_template("{spinner} {msg} [{elapsed}]").unwrap());
+    bar.enable_steady_tick(Duration::from_millis(100));
+    let res = skidder::fetch_with_progress(config, update, |name| {
+        bar.reset_elapsed();
+        bar.set_message(format!("Fetching {name}"));
+    });
+    bar.finish_and_clear();
+    res
+}
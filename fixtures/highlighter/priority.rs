@@ -0,0 +1,8 @@
+   fn add(a: u8) -> u8 {
+//    ┡━┛ ╿  ┡┛     ┗┹─ type
+//    │   │  ╰─ type
+//    │   ╰─ function
+//    ╰─ function
+    a
+//  ╰─ function
+   }
@@ -0,0 +1,10 @@
+   // A comment which spans
+// ┗━━━━━━━━━━━━━━━━━━━━━━┹─ comment.around
+   // multiple lines.
+// ━━━━━━━━━━━━━━━━━┛
+   fn main() {
+       // Another comment.
+//     ┗━━━━━━━━━━━━━━━━━┹─ comment.around
+       let x = 1; // Trailing.
+//                ┗━━━━━━━━━━┹─ comment.around
+   }
@@ -0,0 +1,12 @@
+   fn main() {
+//           ╰─ function.inside
+       let add = |a, b| a + b;
+// ━━━━━━━━━━━━━━━━━━━━┛┡━━━┛╰─ function.inside
+//                      ╰─ function.inside function.inside
+       println!("{}", add(1, 2));
+// ━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━
+   }
+// ━
+
+   fn empty() {}
+//            ┗┹─ function.inside
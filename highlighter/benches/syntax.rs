@@ -0,0 +1,233 @@
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use ropey::Rope;
+use skidder::Repo;
+use tree_house::highlighter::{Highlight, Highlighter};
+use tree_house::tree_sitter::{Grammar, InputEdit, Point};
+use tree_house::{InjectionLanguageMarker, Language, LanguageConfig, LanguageLoader, Syntax};
+
+const PARSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A loader for the grammars in `./test-grammars` in the root of the repo.
+struct Loader {
+    names: Vec<String>,
+    configs: Vec<LanguageConfig>,
+}
+
+impl Loader {
+    fn new() -> Self {
+        let config = skidder::Config {
+            repos: vec![Repo::Local {
+                path: Path::new("../test-grammars").canonicalize().unwrap(),
+            }],
+            index: PathBuf::new(),
+            verbose: false,
+            verify_checksums: false,
+            queries_path: None,
+        };
+        skidder::build_all_grammars(&config, false, None).unwrap();
+        let names: Vec<String> = skidder::list_grammars(&config)
+            .unwrap()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap().to_owned())
+            .collect();
+        let configs = names
+            .iter()
+            .map(|name| {
+                let grammar_dir = config.grammar_dir(name).unwrap();
+                let parser_path = skidder::build_grammar(&config, name, false).unwrap();
+                let grammar = unsafe { Grammar::new(name, &parser_path).unwrap() };
+                let query =
+                    |file: &str| fs::read_to_string(grammar_dir.join(file)).unwrap_or_default();
+                let config = LanguageConfig::new(
+                    grammar,
+                    &query("highlights.scm"),
+                    &query("injections.scm"),
+                    &query("locals.scm"),
+                )
+                .unwrap();
+                // Every scope is highlighted so that the locals query is not skipped.
+                let mut highlights = 0;
+                config.configure(|_scope| {
+                    highlights += 1;
+                    Some(Highlight::new(highlights - 1))
+                });
+                config
+            })
+            .collect();
+        Self { names, configs }
+    }
+
+    fn get(&self, name: &str) -> Language {
+        let idx = self.names.iter().position(|it| it == name).unwrap();
+        Language::new(idx as u32)
+    }
+}
+
+impl LanguageLoader for Loader {
+    fn language_for_marker(&self, marker: InjectionLanguageMarker) -> Option<Language> {
+        let name: Cow<str> = match marker {
+            InjectionLanguageMarker::Name(name) => name.into(),
+            InjectionLanguageMarker::Match(text) => text.into(),
+            _ => return None,
+        };
+        let idx = self.names.iter().position(|it| **it == *name)?;
+        Some(Language::new(idx as u32))
+    }
+
+    fn get_config(&self, lang: Language) -> Option<&LanguageConfig> {
+        self.configs.get(lang.idx())
+    }
+}
+
+/// A markdown document with many rust code blocks.
+fn markdown_with_code_blocks() -> String {
+    let mut text = String::from("# Title\n\n");
+    for i in 0..200 {
+        text.push_str(&format!(
+            "Section {i}\n\n```rust\nfn add_{i}(left: usize, right: usize) -> usize {{\n    let sum = left + right;\n    sum\n}}\n```\n\n"
+        ));
+    }
+    text
+}
+
+/// The sources of this crate concatenated into a single large rust file.
+fn rust_source() -> String {
+    let mut paths: Vec<_> = fs::read_dir("src")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "rs"))
+        .collect();
+    paths.sort();
+    paths
+        .iter()
+        .map(|path| fs::read_to_string(path).unwrap())
+        .collect()
+}
+
+fn parse(c: &mut Criterion) {
+    let loader = Loader::new();
+    let rust = loader.get("rust");
+    let source = Rope::from(rust_source());
+
+    c.bench_function("parse rust", |b| {
+        b.iter(|| Syntax::new(source.slice(..), rust, PARSE_TIMEOUT, &loader).unwrap())
+    });
+
+    // Alternates between rust and markdown layers.
+    let source =
+        Rope::from(fs::read_to_string("../fixtures/highlighter/injectionception.rs").unwrap());
+    c.bench_function("parse injectionception", |b| {
+        b.iter(|| Syntax::new(source.slice(..), rust, PARSE_TIMEOUT, &loader).unwrap())
+    });
+}
+
+fn update(c: &mut Criterion) {
+    let loader = Loader::new();
+    let markdown = loader.get("markdown");
+    let before = Rope::from(markdown_with_code_blocks());
+    let mut after = before.clone();
+    after.insert(7, "!");
+    let edit = InputEdit {
+        start_byte: 7,
+        old_end_byte: 7,
+        new_end_byte: 8,
+        start_point: Point { row: 0, col: 7 },
+        old_end_point: Point { row: 0, col: 7 },
+        new_end_point: Point { row: 0, col: 8 },
+    };
+    let syntax = Syntax::new(before.slice(..), markdown, PARSE_TIMEOUT, &loader).unwrap();
+
+    // An edit before all code blocks moves all rust layers without modifying them.
+    c.bench_function("update moving injections", |b| {
+        b.iter_batched(
+            || syntax.clone(),
+            |mut syntax| {
+                syntax
+                    .update(after.slice(..), PARSE_TIMEOUT, &[edit], &loader)
+                    .unwrap();
+                syntax
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    // A small edit within a large rust file. An edit at the start of the file moves all
+    // injections (doc comments and the rust code blocks within them) without modifying them.
+    let rust = loader.get("rust");
+    let before = Rope::from(rust_source());
+    let syntax = Syntax::new(before.slice(..), rust, PARSE_TIMEOUT, &loader).unwrap();
+    let middle = before.line_to_byte(before.byte_to_line(before.len_bytes() / 2)) as u32;
+    for (name, pos) in [("update rust", middle), ("update rust at the start", 0)] {
+        let mut after = before.clone();
+        after.insert(before.byte_to_char(pos as usize), "x");
+        let start_point = Syntax::byte_to_point(before.slice(..), pos);
+        let edit = InputEdit {
+            start_byte: pos,
+            old_end_byte: pos,
+            new_end_byte: pos + 1,
+            start_point,
+            old_end_point: start_point,
+            new_end_point: Point {
+                row: start_point.row,
+                col: start_point.col + 1,
+            },
+        };
+
+        c.bench_function(name, |b| {
+            b.iter_batched(
+                || syntax.clone(),
+                |mut syntax| {
+                    syntax
+                        .update(after.slice(..), PARSE_TIMEOUT, &[edit], &loader)
+                        .unwrap();
+                    syntax
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+}
+
+fn highlight(c: &mut Criterion) {
+    let loader = Loader::new();
+    let rust = Rope::from(rust_source());
+    let rust_syntax =
+        Syntax::new(rust.slice(..), loader.get("rust"), PARSE_TIMEOUT, &loader).unwrap();
+    let markdown = Rope::from(markdown_with_code_blocks());
+    let markdown_syntax = Syntax::new(
+        markdown.slice(..),
+        loader.get("markdown"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+
+    for (name, source, syntax) in [
+        ("highlight rust", &rust, &rust_syntax),
+        (
+            "highlight markdown with injections",
+            &markdown,
+            &markdown_syntax,
+        ),
+    ] {
+        c.bench_function(name, |b| {
+            b.iter(|| {
+                let mut highlighter = Highlighter::new(syntax, source.slice(..), &loader, ..);
+                let mut highlights = 0;
+                while highlighter.next_event_offset() != u32::MAX {
+                    let (_event, new_highlights) = highlighter.advance();
+                    highlights += new_highlights.len();
+                }
+                black_box(highlights)
+            })
+        });
+    }
+}
+
+criterion_group!(benches, parse, update, highlight);
+criterion_main!(benches);
@@ -1,79 +1,622 @@
 use once_cell::sync::Lazy;
 use regex::Regex;
-use tree_sitter::{query, Grammar};
+use ropey::RopeSlice;
+use tree_sitter::query::{self, ParserErrorLocation};
+use tree_sitter::Grammar;
 
 use crate::highlighter::{Highlight, HighlightQuery};
 use crate::injections_query::{InjectionLanguageMarker, InjectionsQuery};
-use crate::Language;
+use crate::tags::TagsQuery;
+use crate::text_object::TextObjectQuery;
+use crate::{Language, TREE_SITTER_MATCH_LIMIT};
 
-use std::fmt::Write;
+use std::borrow::Cow;
+use std::fmt::{self, Write};
 
 #[derive(Debug)]
 pub struct LanguageConfig {
     pub grammar: Grammar,
-    pub highlight_query: HighlightQuery,
+    /// The highlight query of the language, or `None` if the language has no highlight query.
+    ///
+    /// Layers of a language without a highlight query produce no highlights but their
+    /// injections are still highlighted.
+    pub highlight_query: Option<HighlightQuery>,
     pub injection_query: InjectionsQuery,
+    /// The text object query of the language, if any. See [`LanguageConfigBuilder::text_objects`].
+    pub text_object_query: Option<TextObjectQuery>,
+    /// The tags query of the language, if any. See [`LanguageConfigBuilder::tags`].
+    pub tags_query: Option<TagsQuery>,
+    /// The maximum number of in-progress matches for query cursors running this language's
+    /// queries. Defaults to [`TREE_SITTER_MATCH_LIMIT`].
+    ///
+    /// The text object and tags queries carry their own copy of the limit, use
+    /// [`LanguageConfig::with_match_limit`] to change the limit of all queries.
+    pub match_limit: u32,
+}
+
+/// Clamps a match limit to the range supported by tree-sitter.
+pub(crate) fn clamp_match_limit(match_limit: u32) -> u32 {
+    match_limit.clamp(1, 65536)
+}
+
+/// The query file which a [`QueryError`] originates from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QuerySource {
+    Highlights,
+    Injections,
+    Locals,
+    TextObjects,
+    Tags,
+}
+
+impl fmt::Display for QuerySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Highlights => "highlights",
+            Self::Injections => "injections",
+            Self::Locals => "locals",
+            Self::TextObjects => "textobjects",
+            Self::Tags => "tags",
+        })
+    }
+}
+
+/// An error in one of the queries of a [`LanguageConfig`].
+///
+/// The location of the error is relative to the text of the query named by `source`, even if the
+/// query was concatenated with another query before being parsed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct QueryError {
+    pub source: QuerySource,
+    // Boxed since `ParseError` is large and this is returned in the `Err` variant.
+    pub error: Box<query::ParseError>,
+}
+
+impl QueryError {
+    pub(crate) fn new(source: QuerySource) -> impl FnOnce(query::ParseError) -> Self {
+        move |error| Self {
+            source,
+            error: Box::new(error),
+        }
+    }
+
+    /// Attributes an error in the concatenation of the `first` and `second` query texts to the
+    /// query which contains the error.
+    pub(crate) fn from_concatenated(
+        error: query::ParseError,
+        (first_source, first): (QuerySource, &str),
+        (second_source, second): (QuerySource, &str),
+    ) -> Self {
+        let in_second = !second.is_empty()
+            && error
+                .location()
+                .is_some_and(|location| location.byte_offset as usize >= first.len());
+        if !in_second {
+            return Self {
+                source: first_source,
+                error: Box::new(error),
+            };
+        }
+        let relocate = |location: ParserErrorLocation| {
+            ParserErrorLocation::new(
+                second,
+                location.byte_offset as usize - first.len(),
+                location.len as usize,
+            )
+        };
+        use query::ParseError::*;
+        let error = match error {
+            UnexpectedEof => UnexpectedEof,
+            SyntaxError(location) => SyntaxError(relocate(location)),
+            InvalidNodeType { node, location } => InvalidNodeType {
+                node,
+                location: relocate(location),
+            },
+            InvalidFieldName { field, location } => InvalidFieldName {
+                field,
+                location: relocate(location),
+            },
+            InvalidCaptureName { capture, location } => InvalidCaptureName {
+                capture,
+                location: relocate(location),
+            },
+            InvalidPredicate { message, location } => InvalidPredicate {
+                message,
+                location: relocate(location),
+            },
+            ImpossiblePattern(location) => ImpossiblePattern(relocate(location)),
+        };
+        Self {
+            source: second_source,
+            error: Box::new(error),
+        }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid {} query: {}", self.source, self.error)
+    }
+}
+
+impl std::error::Error for QueryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.error)
+    }
 }
 
 impl LanguageConfig {
+    /// Creates the configuration for a language from the text of its queries.
+    ///
+    /// An empty `highlight_query_text` (or one containing only whitespace) means that the
+    /// language has no highlight query: see [`LanguageConfig::highlight_query`].
     pub fn new(
         grammar: Grammar,
         highlight_query_text: &str,
         injection_query_text: &str,
         local_query_text: &str,
-    ) -> Result<Self, query::ParseError> {
-        // NOTE: the injection queries are parsed first since the local query is passed as-is
-        // to `Query::new` in `InjectionsQuery::new`. This ensures that the more readable error
-        // bubbles up first if the locals queries have an issue.
-        let injection_query =
-            InjectionsQuery::new(grammar, injection_query_text, local_query_text)?;
-        let highlight_query = HighlightQuery::new(grammar, highlight_query_text, local_query_text)?;
+    ) -> Result<Self, QueryError> {
+        Self::new_impl(
+            grammar,
+            highlight_query_text,
+            injection_query_text,
+            local_query_text,
+            false,
+        )
+    }
+
+    fn new_impl(
+        grammar: Grammar,
+        highlight_query_text: &str,
+        injection_query_text: &str,
+        local_query_text: &str,
+        custom_properties: bool,
+    ) -> Result<Self, QueryError> {
+        // NOTE: the injection queries are parsed first since the local query is parsed on its own
+        // in `InjectionsQuery::new`. This way an error in the locals query is reported before the
+        // same error is found again in the highlights query, which concatenates both queries.
+        let injection_query = InjectionsQuery::new_impl(
+            grammar,
+            injection_query_text,
+            local_query_text,
+            custom_properties,
+        )?;
+        let highlight_query = if highlight_query_text.trim().is_empty() {
+            None
+        } else {
+            Some(HighlightQuery::new(
+                grammar,
+                highlight_query_text,
+                local_query_text,
+                custom_properties,
+            )?)
+        };
 
         Ok(Self {
             grammar,
             highlight_query,
             injection_query,
+            text_object_query: None,
+            tags_query: None,
+            match_limit: TREE_SITTER_MATCH_LIMIT,
         })
     }
 
-    pub fn configure(&self, mut f: impl FnMut(&str) -> Option<Highlight>) {
-        self.highlight_query.configure(&mut f);
-        self.injection_query.configure(&mut f);
+    /// Creates a builder for the configuration of a language, which accepts the text of the
+    /// optional queries in addition to the ones taken by [`LanguageConfig::new`].
+    pub fn builder<'a>(grammar: Grammar) -> LanguageConfigBuilder<'a> {
+        LanguageConfigBuilder {
+            grammar,
+            highlights: "",
+            injections: "",
+            locals: "",
+            text_objects: None,
+            tags: None,
+            match_limit: TREE_SITTER_MATCH_LIMIT,
+            custom_properties: false,
+        }
+    }
+
+    /// Creates the configuration for a language like [`LanguageConfig::new`] after applying
+    /// `overrides` to the text of its queries.
+    ///
+    /// This allows users to patch the queries which are shipped for a language, for example to
+    /// add highlights for a framework. Errors in an appended query are reported like errors in
+    /// the query it was appended to.
+    pub fn new_with_overrides(
+        grammar: Grammar,
+        highlight_query_text: &str,
+        injection_query_text: &str,
+        local_query_text: &str,
+        overrides: &QueryOverrides,
+    ) -> Result<Self, QueryError> {
+        fn apply<'a>(query_override: &'a Option<QueryOverride>, text: &'a str) -> Cow<'a, str> {
+            match query_override {
+                Some(query_override) => query_override.apply(text),
+                None => Cow::Borrowed(text),
+            }
+        }
+        Self::new(
+            grammar,
+            &apply(&overrides.highlights, highlight_query_text),
+            &apply(&overrides.injections, injection_query_text),
+            &apply(&overrides.locals, local_query_text),
+        )
+    }
+
+    /// Sets the match limit used by query cursors for this language, including the cursors of
+    /// the text object and tags queries.
+    ///
+    /// See [`TREE_SITTER_MATCH_LIMIT`] for the tradeoffs involved. Tree-sitter supports limits
+    /// from 1 to 65536, limits outside of this range are clamped to it.
+    pub fn with_match_limit(mut self, match_limit: u32) -> Self {
+        let match_limit = clamp_match_limit(match_limit);
+        self.match_limit = match_limit;
+        self.text_object_query = self
+            .text_object_query
+            .map(|query| query.with_match_limit(match_limit));
+        self.tags_query = self
+            .tags_query
+            .map(|query| query.with_match_limit(match_limit));
+        self
+    }
+
+    /// Configures the highlights of the captures in this language's queries.
+    ///
+    /// `f` maps a capture name like `function.builtin` to its highlight. It should try the full
+    /// name first and then fall back to its parent scopes, `function` in this case. `f` is
+    /// called for every capture, but the configuration is only replaced if a highlight changed,
+    /// so calling this repeatedly with the same theme is cheap. Returns whether any highlight
+    /// changed, which callers can use to skip re-highlighting documents.
+    ///
+    /// The configuration is swapped atomically, so this may be called while other threads are
+    /// highlighting with this config. Highlighters read the configuration as they go, so a
+    /// highlighter running concurrently may produce a mix of the old and new highlights.
+    /// Documents should be re-highlighted after the configuration changes.
+    pub fn configure(&self, mut f: impl FnMut(&str) -> Option<Highlight>) -> bool {
+        let highlights_changed = self
+            .highlight_query
+            .as_ref()
+            .is_some_and(|query| query.configure(&mut f));
+        let locals_changed = self.injection_query.configure(&mut f);
+        highlights_changed || locals_changed
+    }
+
+    /// Returns every highlight name which [`LanguageConfig::configure`] may be asked for, sorted
+    /// and without duplicates.
+    ///
+    /// These are the capture names of the highlight query (see
+    /// [`HighlightQuery::capture_names`]) and the highlights of local definitions, `variable`
+    /// for a `@local.definition.variable` capture for example. This allows building a complete
+    /// theme for a language without parsing its queries.
+    pub fn highlight_scopes(&self) -> Vec<&str> {
+        let local_definitions = self
+            .injection_query
+            .local_query
+            .captures()
+            .filter_map(|(_, name)| name.strip_prefix("local.definition."));
+        let mut scopes: Vec<_> = self
+            .highlight_query
+            .iter()
+            .flat_map(HighlightQuery::capture_names)
+            .chain(local_definitions)
+            .collect();
+        scopes.sort_unstable();
+        scopes.dedup();
+        scopes
+    }
+}
+
+/// A builder for a [`LanguageConfig`], see [`LanguageConfig::builder`].
+///
+/// Queries which are not set are empty: the language has no highlight, injection or locals
+/// patterns and no text object or tags query.
+#[derive(Debug, Clone)]
+pub struct LanguageConfigBuilder<'a> {
+    grammar: Grammar,
+    highlights: &'a str,
+    injections: &'a str,
+    locals: &'a str,
+    text_objects: Option<&'a str>,
+    tags: Option<&'a str>,
+    match_limit: u32,
+    custom_properties: bool,
+}
+
+impl<'a> LanguageConfigBuilder<'a> {
+    /// Sets the text of the highlight query (`highlights.scm`).
+    pub fn highlights(mut self, query_text: &'a str) -> Self {
+        self.highlights = query_text;
+        self
+    }
+
+    /// Sets the text of the injection query (`injections.scm`).
+    pub fn injections(mut self, query_text: &'a str) -> Self {
+        self.injections = query_text;
+        self
+    }
+
+    /// Sets the text of the locals query (`locals.scm`).
+    pub fn locals(mut self, query_text: &'a str) -> Self {
+        self.locals = query_text;
+        self
+    }
+
+    /// Sets the text of the text object query (`textobjects.scm`), which becomes
+    /// [`LanguageConfig::text_object_query`].
+    ///
+    /// Unknown predicates are ignored since text object queries commonly use editor-specific
+    /// predicates.
+    pub fn text_objects(mut self, query_text: &'a str) -> Self {
+        self.text_objects = Some(query_text);
+        self
+    }
+
+    /// Sets the text of the tags query (`tags.scm`), which becomes
+    /// [`LanguageConfig::tags_query`].
+    pub fn tags(mut self, query_text: &'a str) -> Self {
+        self.tags = Some(query_text);
+        self
+    }
+
+    /// Sets the match limit, see [`LanguageConfig::with_match_limit`].
+    pub fn match_limit(mut self, match_limit: u32) -> Self {
+        self.match_limit = match_limit;
+        self
+    }
+
+    /// Sets whether the highlight, injection and locals queries accept `#set!` properties with
+    /// keys this crate doesn't know, like `(#set! conceal "")` or `(#set! spell)`.
+    /// Defaults to `false`, rejecting such properties.
+    ///
+    /// The properties can be read with
+    /// [`Query::property_settings`](tree_sitter::Query::property_settings). Properties in the
+    /// `injection.` and `local.` namespaces are still checked so that typos in the properties
+    /// used by this crate are caught.
+    pub fn custom_properties(mut self, custom_properties: bool) -> Self {
+        self.custom_properties = custom_properties;
+        self
+    }
+
+    /// Parses the queries and creates the configuration.
+    pub fn build(self) -> Result<LanguageConfig, QueryError> {
+        let mut config = LanguageConfig::new_impl(
+            self.grammar,
+            self.highlights,
+            self.injections,
+            self.locals,
+            self.custom_properties,
+        )?;
+        config.text_object_query = self
+            .text_objects
+            .map(|query_text| TextObjectQuery::new(self.grammar, query_text))
+            .transpose()
+            .map_err(QueryError::new(QuerySource::TextObjects))?;
+        config.tags_query = self
+            .tags
+            .map(|query_text| TagsQuery::new(self.grammar, query_text))
+            .transpose()
+            .map_err(QueryError::new(QuerySource::Tags))?;
+        Ok(config.with_match_limit(self.match_limit))
+    }
+}
+
+/// Returns whether `predicate` is a `#set!` property which isn't in one of the namespaces used
+/// by this crate, see [`LanguageConfigBuilder::custom_properties`].
+pub(crate) fn is_custom_property(predicate: &query::UserPredicate) -> bool {
+    match *predicate {
+        query::UserPredicate::SetProperty { key, .. } => {
+            !key.starts_with("injection.") && !key.starts_with("local.")
+        }
+        _ => false,
     }
 }
 
-static INHERITS_REGEX: Lazy<Regex> =
-    Lazy::new(|| Regex::new(r";+\s*inherits\s*:?\s*([a-z_,()-]+)\s*").unwrap());
+/// Overrides for the queries of a language, see [`LanguageConfig::new_with_overrides`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryOverrides {
+    pub highlights: Option<QueryOverride>,
+    pub injections: Option<QueryOverride>,
+    pub locals: Option<QueryOverride>,
+}
+
+/// Replaces or extends the text of a query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryOverride {
+    /// Uses the given text instead of the query. An empty highlight query disables highlighting
+    /// for the language, see [`LanguageConfig::highlight_query`].
+    Replace(String),
+    /// Appends the given text to the query. Since later patterns take precedence when
+    /// highlighting, the appended patterns shadow the original ones for the same nodes.
+    Append(String),
+}
+
+impl QueryOverride {
+    /// Applies this override to `query_text`.
+    pub fn apply<'a>(&'a self, query_text: &'a str) -> Cow<'a, str> {
+        match self {
+            Self::Replace(text) => Cow::Borrowed(text),
+            Self::Append(text) => Cow::Owned(format!("{query_text}\n{text}")),
+        }
+    }
+}
+
+/// Matches an inherits directive. The directive must be a comment on a line of its own so that
+/// the word `inherits` within strings, like `#match?` patterns, or within the text of other
+/// comments isn't mistaken for a directive.
+static INHERITS_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?m)^[ \t]*;+[ \t]*inherits[ \t]*:?[ \t]*([a-z_,()-]+)[ \t]*\r?$").unwrap()
+});
 
 /// reads a query by invoking `read_query_text`, handles any `inherits` directives
+///
+/// `read_query_text` should return an empty string for languages without the query. A language
+/// which would inherit itself, directly or through other languages, is not inherited again:
+/// the directive is replaced with a comment instead.
 pub fn read_query(language: &str, mut read_query_text: impl FnMut(&str) -> String) -> String {
-    fn read_query_impl(language: &str, read_query_text: &mut impl FnMut(&str) -> String) -> String {
+    fn read_query_impl(
+        language: &str,
+        // The languages which are currently being expanded.
+        stack: &mut Vec<String>,
+        read_query_text: &mut impl FnMut(&str) -> String,
+    ) -> String {
         let query = read_query_text(language);
+        stack.push(language.to_owned());
 
         // replaces all "; inherits <language>(,<language>)*" with the queries of the given language(s)
-        INHERITS_REGEX
+        let query = INHERITS_REGEX
             .replace_all(&query, |captures: &regex::Captures| {
                 captures[1]
                     .split(',')
                     .fold(String::new(), |mut output, language| {
                         // `write!` to a String cannot fail.
-                        write!(
-                            output,
-                            "\n{}\n",
-                            read_query_impl(language, &mut *read_query_text)
-                        )
-                        .unwrap();
+                        if stack.iter().any(|expanding| expanding == language) {
+                            write!(output, "\n; skipped cyclic inheritance of {language}\n")
+                                .unwrap();
+                        } else {
+                            write!(
+                                output,
+                                "\n{}\n",
+                                read_query_impl(language, stack, read_query_text)
+                            )
+                            .unwrap();
+                        }
                         output
                     })
             })
-            .into_owned()
+            .into_owned();
+        stack.pop();
+        query
+    }
+    read_query_impl(language, &mut Vec::new(), &mut read_query_text)
+}
+
+/// A table of regexes used to resolve the language of an injection from text within the
+/// document.
+///
+/// This implements the "longest matching regex" lookup described by
+/// [`InjectionLanguageMarker::Match`]: every regex is searched for in the text and the language
+/// of the regex with the longest match wins. For example a table containing `md|markdown` and
+/// `mark` resolves `"markdown"` to the language of the first regex.
+#[derive(Debug, Default, Clone)]
+pub struct LanguageMatchTable {
+    entries: Vec<(regex_cursor::engines::meta::Regex, Language)>,
+}
+
+impl LanguageMatchTable {
+    /// Creates a table from pairs of regexes and the language they resolve to.
+    pub fn new<'a>(
+        entries: impl IntoIterator<Item = (&'a str, Language)>,
+    ) -> Result<Self, InvalidRegexError> {
+        let mut table = Self::default();
+        for (regex, language) in entries {
+            table.insert(regex, language)?;
+        }
+        Ok(table)
+    }
+
+    /// Adds a regex which resolves to `language`.
+    pub fn insert(&mut self, regex: &str, language: Language) -> Result<(), InvalidRegexError> {
+        let compiled =
+            regex_cursor::engines::meta::Regex::new(regex).map_err(|err| InvalidRegexError {
+                regex: regex.to_owned(),
+                message: err.to_string(),
+            })?;
+        self.entries.push((compiled, language));
+        Ok(())
+    }
+
+    /// Returns the language whose regex has the longest match within `text`.
+    ///
+    /// If several regexes have a match of the same length the one inserted first wins.
+    pub fn longest_match(&self, text: RopeSlice) -> Option<Language> {
+        let mut best: Option<(usize, Language)> = None;
+        for (regex, language) in &self.entries {
+            let Some(mat) = regex.find(regex_cursor::Input::new(text)) else {
+                continue;
+            };
+            let len = mat.end() - mat.start();
+            if best.is_none_or(|(best_len, _)| len > best_len) {
+                best = Some((len, *language));
+            }
+        }
+        best.map(|(_, language)| language)
     }
-    read_query_impl(language, &mut read_query_text)
+
+    /// Resolves an injection language marker.
+    ///
+    /// [`InjectionLanguageMarker::Name`] is resolved by exact name with `language_for_name`.
+    /// All other markers are resolved with [`LanguageMatchTable::longest_match`]. This is meant
+    /// to be used to implement [`LanguageLoader::language_for_marker`].
+    pub fn language_for_marker(
+        &self,
+        marker: InjectionLanguageMarker,
+        language_for_name: impl FnOnce(&str) -> Option<Language>,
+    ) -> Option<Language> {
+        match marker {
+            InjectionLanguageMarker::Name(name) => language_for_name(name),
+            InjectionLanguageMarker::Match(text)
+            | InjectionLanguageMarker::Filename(text)
+            | InjectionLanguageMarker::Shebang(text) => self.longest_match(text),
+        }
+    }
+}
+
+/// The error returned when a regex of a [`LanguageMatchTable`] fails to compile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidRegexError {
+    /// The regex which failed to compile.
+    pub regex: String,
+    /// The error reported by the regex engine.
+    pub message: String,
 }
 
+impl fmt::Display for InvalidRegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid regex {:?}: {}", self.regex, self.message)
+    }
+}
+
+impl std::error::Error for InvalidRegexError {}
+
 pub trait LanguageLoader {
-    fn language_for_marker(&self, marker: InjectionLanguageMarker) -> Option<Language>;
+    /// Resolves the language of an injection.
+    ///
+    /// By default [`InjectionLanguageMarker::Name`] is resolved with
+    /// [`LanguageLoader::language_for_name`] and all other markers with the longest match in
+    /// [`LanguageLoader::language_match_table`].
+    fn language_for_marker(&self, marker: InjectionLanguageMarker) -> Option<Language> {
+        match marker {
+            InjectionLanguageMarker::Name(name) => self.language_for_name(name),
+            InjectionLanguageMarker::Match(text)
+            | InjectionLanguageMarker::Filename(text)
+            | InjectionLanguageMarker::Shebang(text) => {
+                self.language_match_table()?.longest_match(text)
+            }
+        }
+    }
+
+    /// Returns the language with the exact name `name`, used by the default implementation of
+    /// [`LanguageLoader::language_for_marker`]. Returns `None` by default.
+    fn language_for_name(&self, _name: &str) -> Option<Language> {
+        None
+    }
+
+    /// Returns the table used by the default implementation of
+    /// [`LanguageLoader::language_for_marker`] to resolve languages from text within the
+    /// document. Returns `None` by default.
+    fn language_match_table(&self) -> Option<&LanguageMatchTable> {
+        None
+    }
+
     fn get_config(&self, lang: Language) -> Option<&LanguageConfig>;
+
+    /// Returns the human-readable name of the language, for example `rust`.
+    ///
+    /// This is only used for display purposes. Returns `None` by default.
+    fn language_name(&self, _lang: Language) -> Option<&str> {
+        None
+    }
 }
 
 impl<T> LanguageLoader for &'_ T
@@ -84,7 +627,19 @@ where
         T::language_for_marker(self, marker)
     }
 
+    fn language_for_name(&self, name: &str) -> Option<Language> {
+        T::language_for_name(self, name)
+    }
+
+    fn language_match_table(&self) -> Option<&LanguageMatchTable> {
+        T::language_match_table(self)
+    }
+
     fn get_config(&self, lang: Language) -> Option<&LanguageConfig> {
         T::get_config(self, lang)
     }
+
+    fn language_name(&self, lang: Language) -> Option<&str> {
+        T::language_name(self, lang)
+    }
 }
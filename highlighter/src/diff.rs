@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+use ropey::RopeSlice;
+use tree_sitter::{InputEdit, Point};
+
+use crate::config::LanguageLoader;
+use crate::{Error, LayerChanges, Syntax};
+
+/// Above this number of line pairs the changed region is treated as a single edit instead of
+/// diffing it line by line, since the diff takes quadratic time and memory.
+const MAX_DIFF_LINE_PAIRS: usize = 1 << 20;
+
+impl Syntax {
+    /// Updates the syntax tree for the change from `old` to `new` without requiring the edits
+    /// that were made.
+    ///
+    /// This is useful when the document is replaced as a whole, for example when it is reloaded
+    /// from disk. The edits are computed from a line diff of `old` and `new` and passed to
+    /// [`Syntax::update`], so unchanged regions of the document are not parsed again. `old`
+    /// must be the text the syntax tree was last parsed or updated with.
+    pub fn update_from_diff(
+        &mut self,
+        old: RopeSlice,
+        new: RopeSlice,
+        timeout: Duration,
+        loader: &impl LanguageLoader,
+    ) -> Result<LayerChanges, Error> {
+        let edits = compute_edits(old, new);
+        if edits.is_empty() {
+            return Ok(LayerChanges::default());
+        }
+        self.update(new, timeout, &edits, loader)
+    }
+}
+
+/// Computes the edits which turn `old` into `new`.
+///
+/// Like the edits accepted by [`Syntax::update`], the edits are sorted, non-overlapping and
+/// relative to `old`.
+pub(crate) fn compute_edits(old: RopeSlice, new: RopeSlice) -> Vec<InputEdit> {
+    let prefix = old
+        .bytes()
+        .zip(new.bytes())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if prefix == old.len_bytes() && prefix == new.len_bytes() {
+        return Vec::new();
+    }
+    let max_suffix = old.len_bytes().min(new.len_bytes()) - prefix;
+    let suffix = old
+        .bytes_at(old.len_bytes())
+        .reversed()
+        .zip(new.bytes_at(new.len_bytes()).reversed())
+        .take(max_suffix)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    // Expand the changed region to whole lines. The expansion only covers text shared by `old`
+    // and `new` so the region stays the same relative to both.
+    let start = old.line_to_byte(old.byte_to_line(prefix));
+    let old_end = old.len_bytes() - suffix;
+    let line = old.byte_to_line(old_end);
+    let expand = if old.line_to_byte(line) == old_end {
+        0
+    } else {
+        (old.line_to_byte((line + 1).min(old.len_lines())) - old_end).min(suffix)
+    };
+    let old_end = old_end + expand;
+    let new_end = new.len_bytes() - suffix + expand;
+
+    let old_text = old.byte_slice(start..old_end).to_string();
+    let new_text = new.byte_slice(start..new_end).to_string();
+    let old_lines: Vec<&str> = old_text.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new_text.split_inclusive('\n').collect();
+    if old_lines.len().saturating_mul(new_lines.len()) > MAX_DIFF_LINE_PAIRS {
+        return vec![input_edit(old, start, &old_text, &new_text)];
+    }
+
+    let mut edits = Vec::new();
+    // The offsets into `old_text` and `new_text` of the change that is being built.
+    let mut old_pos = 0;
+    let mut new_pos = 0;
+    let mut change: Option<(usize, usize)> = None;
+    for line in diff::slice(&old_lines, &new_lines) {
+        match line {
+            diff::Result::Left(line) => {
+                change.get_or_insert((old_pos, new_pos));
+                old_pos += line.len();
+            }
+            diff::Result::Right(line) => {
+                change.get_or_insert((old_pos, new_pos));
+                new_pos += line.len();
+            }
+            diff::Result::Both(line, _) => {
+                if let Some((old_start, new_start)) = change.take() {
+                    edits.push(input_edit(
+                        old,
+                        start + old_start,
+                        &old_text[old_start..old_pos],
+                        &new_text[new_start..new_pos],
+                    ));
+                }
+                old_pos += line.len();
+                new_pos += line.len();
+            }
+        }
+    }
+    if let Some((old_start, new_start)) = change {
+        edits.push(input_edit(
+            old,
+            start + old_start,
+            &old_text[old_start..],
+            &new_text[new_start..],
+        ));
+    }
+    edits
+}
+
+/// Creates the edit replacing `removed` at byte `start` of `old` with `inserted`.
+fn input_edit(old: RopeSlice, start: usize, removed: &str, inserted: &str) -> InputEdit {
+    let start_point = Syntax::byte_to_point(old, start as u32);
+    let new_end_point = match inserted.rsplit_once('\n') {
+        Some((before, after)) => Point {
+            row: start_point.row + before.matches('\n').count() as u32 + 1,
+            col: after.len() as u32,
+        },
+        None => Point {
+            row: start_point.row,
+            col: start_point.col + inserted.len() as u32,
+        },
+    };
+    InputEdit {
+        start_byte: start as u32,
+        old_end_byte: (start + removed.len()) as u32,
+        new_end_byte: (start + inserted.len()) as u32,
+        start_point,
+        old_end_point: Syntax::byte_to_point(old, (start + removed.len()) as u32),
+        new_end_point,
+    }
+}
@@ -11,6 +11,7 @@ use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 use crate::config::LanguageLoader;
 use crate::highlighter::{Highlight, HighlightEvent, Highlighter};
 use crate::query_iter::{QueryIter, QueryIterEvent};
+use crate::text_object::TextObjectQuery;
 use crate::{Language, Range, Syntax};
 
 macro_rules! w {
@@ -117,18 +118,29 @@ pub fn check_injection_fixture<R: RangeBounds<usize>>(
     comment_prefix: &str,
     language: Language,
     loader: &impl LanguageLoader,
-    get_language_name: impl Fn(Language) -> String,
     range: impl Fn(RopeSlice) -> R,
 ) {
     check_fixture(path, move |src| {
-        roundtrip_injection_fixture(
-            comment_prefix,
-            language,
-            loader,
-            get_language_name,
-            src,
-            range,
-        )
+        roundtrip_injection_fixture(comment_prefix, language, loader, src, range)
+    })
+}
+
+/// Checks the fixture at `path` which annotates the ranges captured by `capture_name` in the
+/// text object queries of the document's languages, for example `function.inside`.
+///
+/// The text object query of each language is taken from
+/// [`LanguageConfig::text_object_query`](crate::LanguageConfig::text_object_query), so captures
+/// within injections are annotated as well. Nested captures are annotated like nested
+/// highlights: the capture name is repeated once per level of nesting.
+pub fn check_text_object_fixture(
+    path: impl AsRef<Path>,
+    comment_prefix: &str,
+    capture_name: &str,
+    language: Language,
+    loader: &impl LanguageLoader,
+) {
+    check_fixture(path, move |src| {
+        roundtrip_text_object_fixture(comment_prefix, capture_name, language, loader, src)
     })
 }
 
@@ -157,21 +169,25 @@ pub fn roundtrip_injection_fixture<R: RangeBounds<usize>>(
     comment_prefix: &str,
     language: Language,
     loader: &impl LanguageLoader,
-    get_language_name: impl Fn(Language) -> String,
     src: &str,
     range: impl Fn(RopeSlice) -> R,
 ) -> String {
     let raw = strip_annotations(src, comment_prefix);
     let syntax = Syntax::new(raw.slice(..), language, Duration::from_secs(60), loader).unwrap();
     let range = range(raw.slice(..));
-    injections_fixture(
-        comment_prefix,
-        loader,
-        get_language_name,
-        &syntax,
-        raw.slice(..),
-        range,
-    )
+    injections_fixture(comment_prefix, loader, &syntax, raw.slice(..), range)
+}
+
+pub fn roundtrip_text_object_fixture(
+    comment_prefix: &str,
+    capture_name: &str,
+    language: Language,
+    loader: &impl LanguageLoader,
+    src: &str,
+) -> String {
+    let raw = strip_annotations(src, comment_prefix);
+    let syntax = Syntax::new(raw.slice(..), language, Duration::from_secs(60), loader).unwrap();
+    text_object_fixture(comment_prefix, capture_name, loader, &syntax, raw.slice(..))
 }
 
 pub fn highlighter_fixture(
@@ -184,7 +200,7 @@ pub fn highlighter_fixture(
 ) -> String {
     let start = match range.start_bound() {
         Bound::Included(&i) => i,
-        Bound::Excluded(&i) => i + 1,
+        Bound::Excluded(&i) => i.saturating_add(1),
         Bound::Unbounded => 0,
     };
     let end = match range.end_bound() {
@@ -305,17 +321,25 @@ pub fn highlighter_fixture(
     res
 }
 
+/// Returns the name used to annotate injections of `lang`: the
+/// [`LanguageLoader::language_name`] if the loader provides one.
+fn language_name(loader: &impl LanguageLoader, lang: Language) -> String {
+    match loader.language_name(lang) {
+        Some(name) => name.to_owned(),
+        None => format!("{lang:?}"),
+    }
+}
+
 pub fn injections_fixture(
     comment_prefix: &str,
     loader: &impl LanguageLoader,
-    get_language_name: impl Fn(Language) -> String,
     syntax: &Syntax,
     src: RopeSlice<'_>,
     range: impl RangeBounds<usize>,
 ) -> String {
     let start = match range.start_bound() {
         Bound::Included(&i) => i,
-        Bound::Excluded(&i) => i + 1,
+        Bound::Excluded(&i) => i.saturating_add(1),
         Bound::Unbounded => 0,
     };
     let end = match range.end_bound() {
@@ -332,7 +356,7 @@ pub fn injections_fixture(
     let mut injection_stack = Vec::new();
     let mut pos = if let Some(QueryIterEvent::EnterInjection(injection)) = event {
         let language = syntax.layer(injection.layer).language;
-        injection_stack.push(get_language_name(language));
+        injection_stack.push(language_name(loader, language));
         injection.range.start
     } else {
         end as u32
@@ -342,9 +366,10 @@ pub fn injections_fixture(
     let mut line_end = src.line_to_byte(line_idx + 1) as u32;
     let mut line_labels = Vec::new();
     let mut res = String::new();
+    // NOTE: lines yielded by `lines` include their line ending.
     for line in src.byte_slice(..line_start as usize).lines() {
         if line.len_bytes() != 0 {
-            wln!(res, "{ident}{line}")
+            w!(res, "{ident}{line}")
         }
     }
     let mut errors = String::new();
@@ -380,6 +405,9 @@ pub fn injections_fixture(
                     src.byte_slice(line_start as usize..line_end as usize)
                         .chunks(),
                 );
+                if !res.ends_with('\n') {
+                    res.push('\n');
+                }
                 annotate_line(
                     comment_prefix,
                     src,
@@ -405,7 +433,8 @@ pub fn injections_fixture(
 
         match event {
             QueryIterEvent::EnterInjection(injection) => {
-                injection_stack.push(get_language_name(syntax.layer(injection.layer).language));
+                let language = syntax.layer(injection.layer).language;
+                injection_stack.push(language_name(loader, language));
             }
             QueryIterEvent::ExitInjection { .. } => {
                 injection_stack.pop();
@@ -434,8 +463,98 @@ pub fn injections_fixture(
     }
     for line in src.byte_slice(line_start as usize..).lines() {
         if line.len_bytes() != 0 {
-            wln!(res, "{ident}{line}")
+            w!(res, "{ident}{line}");
+            if !res.ends_with('\n') {
+                res.push('\n');
+            }
+        }
+    }
+    res
+}
+
+pub fn text_object_fixture(
+    comment_prefix: &str,
+    capture_name: &str,
+    loader: &impl LanguageLoader,
+    syntax: &Syntax,
+    src: RopeSlice<'_>,
+) -> String {
+    let ranges: Vec<Range> =
+        TextObjectQuery::capture_nodes(capture_name, syntax, src, .., |language| {
+            loader
+                .get_config(language)
+                .and_then(|config| config.text_object_query.as_ref())
+        })
+        .iter()
+        .map(|node| node.start_byte() as u32..node.end_byte() as u32)
+        .filter(|range| !range.is_empty())
+        .collect();
+    // Split the (possibly nested) captures into disjoint segments labeled once per capture
+    // which covers them.
+    let mut boundaries: Vec<u32> = ranges
+        .iter()
+        .flat_map(|range| [range.start, range.end])
+        .collect();
+    boundaries.sort_unstable();
+    boundaries.dedup();
+    let mut segments = boundaries
+        .windows(2)
+        .filter_map(|window| {
+            let segment = window[0]..window[1];
+            let depth = ranges
+                .iter()
+                .filter(|range| range.start <= segment.start && segment.end <= range.end)
+                .count();
+            (depth != 0).then(|| (segment, vec![capture_name.to_owned(); depth]))
+        })
+        .peekable();
+
+    let ident = " ".repeat(comment_prefix.width());
+    let mut res = String::new();
+    // The rest of a segment which continues past the end of the previous line.
+    let mut continued_segment: Option<Range> = None;
+    let mut line_start = 0;
+    // NOTE: lines yielded by `lines` include their line ending.
+    for line in src.lines() {
+        if line.len_bytes() == 0 {
+            continue;
+        }
+        let line_end = line_start + line.len_bytes() as u32;
+        // Avoid trailing whitespace on empty lines.
+        if line != "\n" {
+            res.push_str(&ident);
         }
+        w!(res, "{line}");
+        if !res.ends_with('\n') {
+            res.push('\n');
+        }
+        let mut annotations = Vec::new();
+        let mut continued = false;
+        if let Some(segment) = continued_segment.take() {
+            annotations.push((line_start..segment.end.min(line_end), Vec::new()));
+            if segment.end > line_end {
+                continued = true;
+                continued_segment = Some(segment);
+            }
+        }
+        while let Some((segment, labels)) =
+            segments.next_if(|(segment, _)| segment.start < line_end)
+        {
+            annotations.push((segment.start..segment.end.min(line_end), labels));
+            if segment.end > line_end {
+                continued = true;
+                continued_segment = Some(segment);
+            }
+        }
+        annotate_line(
+            comment_prefix,
+            src,
+            line_start,
+            &mut annotations,
+            &mut res,
+            continued,
+        );
+        line_start = line_end;
     }
     res
 }
@@ -3,19 +3,19 @@ use std::cmp;
 use std::fmt;
 use std::mem::replace;
 use std::num::NonZeroU32;
-use std::ops::RangeBounds;
+use std::ops::{Bound, RangeBounds};
 use std::slice;
 use std::sync::Arc;
 
-use crate::config::{LanguageConfig, LanguageLoader};
+use crate::config::{is_custom_property, LanguageConfig, LanguageLoader, QueryError, QuerySource};
 use crate::locals::ScopeCursor;
 use crate::query_iter::{MatchedNode, QueryIter, QueryIterEvent, QueryLoader};
-use crate::{Injection, Language, Layer, Syntax};
+use crate::{Injection, Language, Layer, Syntax, TREE_SITTER_MATCH_LIMIT};
 use arc_swap::ArcSwap;
 use hashbrown::{HashMap, HashSet};
 use ropey::RopeSlice;
 use tree_sitter::{
-    query::{self, InvalidPredicateError, Query, UserPredicate},
+    query::{InvalidPredicateError, Query, UserPredicate},
     Capture, Grammar,
 };
 use tree_sitter::{Pattern, QueryMatch};
@@ -31,14 +31,21 @@ pub struct HighlightQuery {
     /// Patterns that do not match when the node is a local.
     non_local_patterns: HashSet<Pattern>,
     local_reference_capture: Option<Capture>,
+    /// The `(#set! priority <n>)` of each pattern. Empty if no pattern sets a priority.
+    priorities: Box<[u32]>,
 }
 
 impl HighlightQuery {
+    /// The priority of patterns which don't set one with `(#set! priority <n>)`. This matches
+    /// the default priority of Neovim so that priorities written for Neovim queries keep working.
+    pub const DEFAULT_PRIORITY: u32 = 100;
+
     pub(crate) fn new(
         grammar: Grammar,
         highlight_query_text: &str,
         local_query_text: &str,
-    ) -> Result<Self, query::ParseError> {
+        custom_properties: bool,
+    ) -> Result<Self, QueryError> {
         // Concatenate the highlights and locals queries.
         let mut query_source =
             String::with_capacity(highlight_query_text.len() + local_query_text.len());
@@ -46,8 +53,18 @@ impl HighlightQuery {
         query_source.push_str(local_query_text);
 
         let mut non_local_patterns = HashSet::new();
+        let mut priorities = Vec::new();
         let mut query = Query::new(grammar, &query_source, |pattern, predicate| {
             match predicate {
+                UserPredicate::SetProperty {
+                    key: "priority",
+                    val,
+                } => {
+                    let priority = val
+                        .and_then(|val| val.parse().ok())
+                        .ok_or("priority must be a non-negative integer")?;
+                    priorities.push((pattern, priority));
+                }
                 // Allow the `(#set! local.scope-inherits <bool>)` property to be parsed.
                 // This information is not used by this query though, it's used in the
                 // injection query instead.
@@ -64,9 +81,17 @@ impl HighlightQuery {
                 } => {
                     non_local_patterns.insert(pattern);
                 }
+                _ if custom_properties && is_custom_property(&predicate) => (),
                 _ => return Err(InvalidPredicateError::unknown(predicate)),
             }
             Ok(())
+        })
+        .map_err(|err| {
+            QueryError::from_concatenated(
+                err,
+                (QuerySource::Highlights, highlight_query_text),
+                (QuerySource::Locals, local_query_text),
+            )
         })?;
 
         // The highlight query only cares about local.reference captures. All scope and definition
@@ -81,14 +106,51 @@ impl HighlightQuery {
             query.disable_capture(&name);
         }
 
+        let priorities = if priorities.is_empty() {
+            Box::default()
+        } else {
+            let mut pattern_priorities =
+                vec![Self::DEFAULT_PRIORITY; query.pattern_count() as usize].into_boxed_slice();
+            for (pattern, priority) in priorities {
+                pattern_priorities[pattern.idx()] = priority;
+            }
+            pattern_priorities
+        };
+
         Ok(Self {
             highlight_indices: ArcSwap::from_pointee(vec![None; query.num_captures() as usize]),
             non_local_patterns,
             local_reference_capture: query.get_capture("local.reference"),
+            priorities,
             query,
         })
     }
 
+    /// Returns the priority of the given pattern, set with `(#set! priority <n>)`.
+    ///
+    /// When the captures of multiple patterns highlight the same node, the capture of the
+    /// pattern with the highest priority is used. Patterns with the same priority fall back to
+    /// the order of the query: the pattern which comes last wins. Patterns default to
+    /// [`HighlightQuery::DEFAULT_PRIORITY`].
+    pub fn priority(&self, pattern: Pattern) -> u32 {
+        self.priorities
+            .get(pattern.idx())
+            .copied()
+            .unwrap_or(Self::DEFAULT_PRIORITY)
+    }
+
+    /// Returns the names of the highlight captures of the query, like `keyword.control`, in the
+    /// order in which they appear in the query.
+    ///
+    /// Each name is returned once. The captures of the locals query (`local.*`) and private
+    /// captures starting with an underscore are not highlights and are skipped.
+    pub fn capture_names(&self) -> impl Iterator<Item = &str> {
+        self.query
+            .captures()
+            .map(|(_, name)| name)
+            .filter(|name| !name.starts_with("local.") && !name.starts_with('_'))
+    }
+
     /// Configures the list of recognized highlight names.
     ///
     /// Tree-sitter syntax-highlighting queries specify highlights in the form of dot-separated
@@ -100,28 +162,50 @@ impl HighlightQuery {
     /// The closure provided to this function should therefore try to first lookup the full
     /// name. If no highlight was found for that name it should [`rsplit_once('.')`](str::rsplit_once)
     /// and retry until a highlight has been found. If none of the parent scopes are defined
-    /// then `Highlight::NONE` should be returned.
+    /// then `None` should be returned and the capture is not highlighted.
     ///
     /// When highlighting, results are returned as `Highlight` values, configured by this function.
     /// The meaning of these indices is up to the user of the implementation. The highlighter
     /// treats the indices as entirely opaque.
-    pub(crate) fn configure(&self, f: &mut impl FnMut(&str) -> Option<Highlight>) {
-        let highlight_indices = self
-            .query
-            .captures()
-            .map(|(_, capture_name)| f(capture_name))
-            .collect();
+    ///
+    /// Returns whether any highlight changed. If none did, the current configuration is kept
+    /// as-is without allocating.
+    pub(crate) fn configure(&self, f: &mut impl FnMut(&str) -> Option<Highlight>) -> bool {
+        let current = self.highlight_indices.load();
+        // Only allocate a new list once a highlight differs from the current configuration.
+        let mut highlight_indices: Option<Vec<_>> = None;
+        for (i, (_, capture_name)) in self.query.captures().enumerate() {
+            let highlight = f(capture_name);
+            match &mut highlight_indices {
+                Some(highlight_indices) => highlight_indices.push(highlight),
+                None if current.get(i) == Some(&highlight) => (),
+                None => {
+                    let mut new = Vec::with_capacity(current.len());
+                    new.extend_from_slice(&current[..i]);
+                    new.push(highlight);
+                    highlight_indices = Some(new);
+                }
+            }
+        }
+        let Some(highlight_indices) = highlight_indices else {
+            return false;
+        };
         self.highlight_indices.store(Arc::new(highlight_indices));
+        true
     }
 }
 
 /// Indicates which highlight should be applied to a region of source code.
 ///
 /// This type is represented as a non-max u32 - a u32 which cannot be `u32::MAX`. This is checked
-/// at runtime with assertions in `Highlight::new`.
+/// at runtime with assertions in `Highlight::new`, [`Highlight::try_new`] checks it without
+/// panicking. Thanks to this niche, the absence of a highlight is represented as
+/// `Option<Highlight>` which is the same size as a `u32` so no separate sentinel value is needed.
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Highlight(NonZeroU32);
 
+const _: () = assert!(std::mem::size_of::<Option<Highlight>>() == std::mem::size_of::<u32>());
+
 impl Highlight {
     pub const MAX: u32 = u32::MAX - 1;
 
@@ -131,6 +215,24 @@ impl Highlight {
         Self(unsafe { NonZeroU32::new_unchecked(inner ^ u32::MAX) })
     }
 
+    /// Creates a highlight like [`Highlight::new`] but returns `None` instead of panicking if
+    /// `inner` is `u32::MAX`.
+    pub const fn try_new(inner: u32) -> Option<Self> {
+        match NonZeroU32::new(inner ^ u32::MAX) {
+            Some(inner) => Some(Self(inner)),
+            None => None,
+        }
+    }
+
+    /// Creates a highlight like [`Highlight::new`] but uses [`Highlight::MAX`] instead of
+    /// panicking if `inner` is `u32::MAX`.
+    pub const fn saturating_new(inner: u32) -> Self {
+        match Self::try_new(inner) {
+            Some(highlight) => highlight,
+            None => Self::new(Self::MAX),
+        }
+    }
+
     pub const fn get(&self) -> u32 {
         self.0.get() ^ u32::MAX
     }
@@ -140,6 +242,26 @@ impl Highlight {
     }
 }
 
+impl TryFrom<u32> for Highlight {
+    type Error = InvalidHighlightError;
+
+    fn try_from(inner: u32) -> Result<Self, Self::Error> {
+        Self::try_new(inner).ok_or(InvalidHighlightError)
+    }
+}
+
+/// The error returned when converting `u32::MAX` into a [`Highlight`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidHighlightError;
+
+impl fmt::Display for InvalidHighlightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "highlights must be at most {}", Highlight::MAX)
+    }
+}
+
+impl std::error::Error for InvalidHighlightError {}
+
 impl fmt::Debug for Highlight {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_tuple("Highlight").field(&self.get()).finish()
@@ -150,6 +272,8 @@ impl fmt::Debug for Highlight {
 struct HighlightedNode {
     end: u32,
     highlight: Highlight,
+    /// The [`HighlightQuery::priority`] of the pattern which captured the node.
+    priority: u32,
 }
 
 #[derive(Debug, Default)]
@@ -186,8 +310,14 @@ pub struct Highlighter<'a, 'tree, Loader: LanguageLoader> {
     // Also see `Self::advance_query_iter`.
     current_layer: Layer,
     layer_states: HashMap<Layer, LayerData>,
+    keep_all_highlights: bool,
 }
 
+/// The highlights of a [`HighlightEvent`], ordered from the outermost to the innermost highlight.
+///
+/// When [`Highlighter::set_keep_all_highlights`] is enabled, a span may be covered by multiple
+/// highlights for the exact same range. These are yielded in the order in which they were first
+/// captured and each highlight is only yielded once per range.
 pub struct HighlightList<'a>(slice::Iter<'a, HighlightedNode>);
 
 impl Iterator for HighlightList<'_> {
@@ -239,12 +369,50 @@ impl<'a, 'tree: 'a, Loader: LanguageLoader> Highlighter<'a, 'tree, Loader> {
             active_highlights: Vec::new(),
             next_highlight_end: u32::MAX,
             next_highlight_start: 0,
+            keep_all_highlights: false,
             query,
         };
         res.advance_query_iter();
         res
     }
 
+    /// Creates a highlighter like [`Highlighter::new`] which keeps `default` at the bottom of
+    /// the active highlights.
+    ///
+    /// Every byte is then covered by at least one highlight, so consumers which need a style
+    /// for every byte - terminal renderers for example - don't need to fill the gaps between
+    /// highlights themselves. `default` is included in the highlights of every
+    /// [`HighlightEvent::Refresh`] and is never popped.
+    pub fn new_with_default(
+        syntax: &'tree Syntax,
+        src: RopeSlice<'a>,
+        loader: &'a Loader,
+        range: impl RangeBounds<u32>,
+        default: Highlight,
+    ) -> Self {
+        let mut res = Self::new(syntax, src, loader, range);
+        // The default highlight covers the whole document so it is popped only at `u32::MAX`,
+        // which is the offset reported once the highlighter is exhausted.
+        res.active_highlights.push(HighlightedNode {
+            end: u32::MAX,
+            highlight: default,
+            priority: HighlightQuery::DEFAULT_PRIORITY,
+        });
+        res
+    }
+
+    /// Controls how multiple patterns capturing the exact same node are handled.
+    ///
+    /// By default only the highlight of the last pattern which matched is kept. This matches
+    /// the precedence of Neovim, Zed, and tree-sitter-cli. When enabled, the highlights of all
+    /// matching patterns are kept on the stack instead, for example both `keyword` and
+    /// `keyword.control`, so that they can be combined by the consumer.
+    ///
+    /// This should be set before the first call to [`Highlighter::advance`].
+    pub fn set_keep_all_highlights(&mut self, keep_all_highlights: bool) {
+        self.keep_all_highlights = keep_all_highlights;
+    }
+
     pub fn active_highlights(&self) -> HighlightList<'_> {
         HighlightList(self.active_highlights.iter())
     }
@@ -257,7 +425,9 @@ impl<'a, 'tree: 'a, Loader: LanguageLoader> Highlighter<'a, 'tree, Loader> {
     /// This method consumes events until the highlighter is exhausted.
     ///
     /// Note: This returns individual highlight spans, not character ranges. Multiple highlights
-    /// may overlap (due to stacking) and should be applied in order from outer to inner.
+    /// may overlap (due to stacking) and should be applied in order from outer to inner. The
+    /// spans are sorted by their start and spans starting at the same byte are sorted from the
+    /// outermost to the innermost.
     pub fn collect_highlights(mut self) -> Vec<(Highlight, std::ops::Range<u32>)> {
         use std::collections::HashMap;
 
@@ -278,17 +448,21 @@ impl<'a, 'tree: 'a, Loader: LanguageLoader> Highlighter<'a, 'tree, Loader> {
 
             // Record any new highlights we see at this position.
             // On both Refresh and Push events, active_highlights contains highlights at this position.
-            for &HighlightedNode { end, highlight } in &self.active_highlights {
+            for &HighlightedNode { end, highlight, .. } in &self.active_highlights {
                 // Record this highlight if we haven't seen this (highlight, end) pair before.
                 seen_highlights.entry((highlight, end)).or_insert(last_pos);
             }
         }
 
-        // Convert all seen highlights into ranges.
-        seen_highlights
+        // Convert all seen highlights into ranges. The default highlight of
+        // `Highlighter::new_with_default` ends at `u32::MAX` so clamp ends to the document.
+        let len = self.query.source().len_bytes() as u32;
+        let mut highlights: Vec<_> = seen_highlights
             .into_iter()
-            .map(|((highlight, end), start)| (highlight, start..end))
-            .collect()
+            .map(|((highlight, end), start)| (highlight, start..end.min(len)))
+            .collect();
+        highlights.sort_unstable_by_key(|(_, range)| (range.start, cmp::Reverse(range.end)));
+        highlights
     }
 
     pub fn advance(&mut self) -> (HighlightEvent, HighlightList<'_>) {
@@ -415,15 +589,17 @@ impl<'a, 'tree: 'a, Loader: LanguageLoader> Highlighter<'a, 'tree, Loader> {
         let config = self
             .active_config
             .expect("must have an active config to emit matches");
+        // `HighlightQueryLoader` has no query for languages without a highlight query so their
+        // layers never emit matches.
+        let highlight_query = config
+            .highlight_query
+            .as_ref()
+            .expect("must have a highlight query to emit matches");
 
-        let highlight = if Some(node.capture) == config.highlight_query.local_reference_capture {
+        let highlight = if Some(node.capture) == highlight_query.local_reference_capture {
             // If this capture was a `@local.reference` from the locals queries, look up the
             // text of the node in the current locals cursor and use that highlight.
-            let text: Cow<str> = self
-                .query
-                .source()
-                .byte_slice(range.start as usize..range.end as usize)
-                .into();
+            let text: Cow<str> = node.node.text(self.query.source()).into();
             let Some(definition) = self
                 .query
                 .syntax()
@@ -441,16 +617,20 @@ impl<'a, 'tree: 'a, Loader: LanguageLoader> Highlighter<'a, 'tree, Loader> {
                 .get(&definition.capture)
                 .copied()
         } else {
-            config.highlight_query.highlight_indices.load()[node.capture.idx()]
+            highlight_query.highlight_indices.load()[node.capture.idx()]
         };
 
+        let priority = highlight_query.priority(node.pattern);
         let highlight = highlight.map(|highlight| HighlightedNode {
             end: range.end,
             highlight,
+            priority,
         });
 
-        // If multiple patterns match this exact node, prefer the last one which matched.
-        // This matches the precedence of Neovim, Zed, and tree-sitter-cli.
+        // If multiple patterns match this exact node, prefer the one with the highest priority
+        // and among those the last one which matched. This matches the precedence of Neovim,
+        // Zed, and tree-sitter-cli. With `keep_all_highlights` the highlights are instead stacked
+        // in the order they matched, below any highlights with a higher priority.
         if !*first_highlight {
             // NOTE: `!*first_highlight` implies that the start positions are the same.
             let insert_position = self
@@ -459,6 +639,27 @@ impl<'a, 'tree: 'a, Loader: LanguageLoader> Highlighter<'a, 'tree, Loader> {
                 .rposition(|h| h.end <= range.end);
             if let Some(idx) = insert_position {
                 match self.active_highlights[idx].end.cmp(&range.end) {
+                    // With `keep_all_highlights`, stack the highlight on top of the prior
+                    // highlights for this start..end range unless it is already present.
+                    cmp::Ordering::Equal if self.keep_all_highlights => {
+                        if let Some(highlight) = highlight {
+                            let same_range = self.active_highlights[..=idx]
+                                .iter()
+                                .rev()
+                                .take_while(|h| h.end == range.end);
+                            let duplicate = same_range
+                                .clone()
+                                .any(|h| h.highlight == highlight.highlight);
+                            if !duplicate {
+                                let higher_priority =
+                                    same_range.take_while(|h| h.priority > priority).count();
+                                self.active_highlights
+                                    .insert(idx + 1 - higher_priority, highlight);
+                            }
+                        }
+                    }
+                    // Keep a prior highlight for this start..end range with a higher priority.
+                    cmp::Ordering::Equal if self.active_highlights[idx].priority > priority => (),
                     // If there is a prior highlight for this start..end range, replace it.
                     cmp::Ordering::Equal => {
                         if let Some(highlight) = highlight {
@@ -503,13 +704,180 @@ impl<'a, 'tree: 'a, Loader: LanguageLoader> Highlighter<'a, 'tree, Loader> {
     }
 }
 
+/// Highlights the given range of the source and splits the result into lines.
+///
+/// Returns one entry per line that intersects `range`. Each line holds the non-overlapping spans
+/// within that line together with the innermost highlight that is active for the span. Ranges
+/// are absolute byte offsets into `src` and include the line ending. Regions without any active
+/// highlight are not included, so a line without highlights is empty.
+///
+/// Highlights which span multiple lines (block comments or strings for example) are split at
+/// line boundaries.
+pub fn highlight_to_lines(
+    syntax: &Syntax,
+    src: RopeSlice<'_>,
+    loader: &impl LanguageLoader,
+    range: impl RangeBounds<u32>,
+) -> Vec<Vec<(std::ops::Range<u32>, Highlight)>> {
+    let start = match range.start_bound() {
+        Bound::Included(&i) => i,
+        Bound::Excluded(&i) => i.saturating_add(1),
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&i) => i.saturating_add(1),
+        Bound::Excluded(&i) => i,
+        Bound::Unbounded => u32::MAX,
+    }
+    .min(src.len_bytes() as u32);
+    if start >= end {
+        return Vec::new();
+    }
+
+    let mut lines = LineSplitter::new(src, start, end);
+    let mut highlighter = Highlighter::new(syntax, src, loader, start..end);
+    let mut pos = start;
+    while pos < end {
+        let next = highlighter.next_event_offset().clamp(pos, end);
+        let highlight = highlighter.active_highlights().next_back();
+        lines.push(pos..next, highlight);
+        pos = next;
+        if pos < end {
+            highlighter.advance();
+        }
+    }
+    lines.lines
+}
+
+impl Syntax {
+    /// Returns the stack of highlights applying to the byte at `pos`, ordered from the outermost
+    /// to the innermost highlight.
+    ///
+    /// This is the random-access counterpart to [`Highlighter`]: only the queries around `pos`
+    /// are run, which makes it suitable for inspecting a single position, for example on hover.
+    pub fn highlights_at(
+        &self,
+        src: RopeSlice<'_>,
+        loader: &impl LanguageLoader,
+        pos: u32,
+    ) -> Vec<Highlight> {
+        if pos >= src.len_bytes() as u32 {
+            return Vec::new();
+        }
+        let mut highlighter = Highlighter::new(self, src, loader, pos..pos + 1);
+        while highlighter.next_event_offset() <= pos {
+            highlighter.advance();
+        }
+        highlighter.active_highlights().collect()
+    }
+
+    /// Returns the highlighted spans within `range` sorted by their start. Each span holds the
+    /// stack of highlights applying to it, ordered from the outermost to the innermost
+    /// highlight.
+    ///
+    /// The spans don't overlap and bytes without any highlight are not covered by a span. Since
+    /// the spans are materialized they can be iterated in any order, for example bottom-up when
+    /// rendering from the end of a viewport, which the forward-only [`Highlighter`] can't do.
+    pub fn highlights_in_range(
+        &self,
+        src: RopeSlice<'_>,
+        loader: &impl LanguageLoader,
+        range: std::ops::Range<u32>,
+    ) -> Vec<(std::ops::Range<u32>, Vec<Highlight>)> {
+        let end = range.end.min(src.len_bytes() as u32);
+        let mut pos = range.start;
+        let mut highlighter = Highlighter::new(self, src, loader, range);
+        let mut spans: Vec<(std::ops::Range<u32>, Vec<Highlight>)> = Vec::new();
+        while pos < end {
+            while highlighter.next_event_offset() <= pos {
+                highlighter.advance();
+            }
+            let span_end = highlighter.next_event_offset().min(end);
+            let highlights: Vec<Highlight> = highlighter.active_highlights().collect();
+            if !highlights.is_empty() {
+                // Merge with the previous span if the same highlights continue.
+                match spans.last_mut() {
+                    Some((prev, prev_highlights))
+                        if prev.end == pos && *prev_highlights == highlights =>
+                    {
+                        prev.end = span_end
+                    }
+                    _ => spans.push((pos..span_end, highlights)),
+                }
+            }
+            pos = span_end;
+        }
+        spans
+    }
+}
+
+struct LineSplitter<'a> {
+    src: RopeSlice<'a>,
+    end: u32,
+    line_idx: usize,
+    line_end: u32,
+    lines: Vec<Vec<(std::ops::Range<u32>, Highlight)>>,
+}
+
+impl<'a> LineSplitter<'a> {
+    fn new(src: RopeSlice<'a>, start: u32, end: u32) -> Self {
+        let line_idx = src.byte_to_line(start as usize);
+        let mut res = LineSplitter {
+            src,
+            end,
+            line_idx,
+            line_end: 0,
+            lines: vec![Vec::new()],
+        };
+        res.line_end = res.line_end_byte();
+        res
+    }
+
+    fn line_end_byte(&self) -> u32 {
+        self.src
+            .try_line_to_byte(self.line_idx + 1)
+            .unwrap_or(self.src.len_bytes()) as u32
+    }
+
+    fn push(&mut self, mut range: std::ops::Range<u32>, highlight: Option<Highlight>) {
+        while !range.is_empty() {
+            while range.start >= self.line_end && self.line_end < self.end {
+                self.line_idx += 1;
+                self.line_end = self.line_end_byte();
+                self.lines.push(Vec::new());
+            }
+            let span_end = range.end.min(self.line_end);
+            if let Some(highlight) = highlight {
+                let line = self.lines.last_mut().unwrap();
+                // Merge with the previous span if the highlight continues.
+                match line.last_mut() {
+                    Some((prev, prev_highlight))
+                        if prev.end == range.start && *prev_highlight == highlight =>
+                    {
+                        prev.end = span_end
+                    }
+                    _ => line.push((range.start..span_end, highlight)),
+                }
+            }
+            range.start = span_end;
+        }
+    }
+}
+
 pub(crate) struct HighlightQueryLoader<T>(T);
 
 impl<'a, T: LanguageLoader> QueryLoader<'a> for HighlightQueryLoader<&'a T> {
     fn get_query(&mut self, lang: Language) -> Option<&'a Query> {
         self.0
             .get_config(lang)
-            .map(|config| &config.highlight_query.query)
+            .and_then(|config| config.highlight_query.as_ref())
+            .map(|highlight_query| &highlight_query.query)
+    }
+
+    fn match_limit(&self, lang: Language) -> u32 {
+        self.0
+            .get_config(lang)
+            .map_or(TREE_SITTER_MATCH_LIMIT, |config| config.match_limit)
     }
 
     fn are_predicates_satisfied(
@@ -519,11 +887,11 @@ impl<'a, T: LanguageLoader> QueryLoader<'a> for HighlightQueryLoader<&'a T> {
         source: RopeSlice<'_>,
         locals_cursor: &ScopeCursor<'_>,
     ) -> bool {
-        let highlight_query = &self
+        let highlight_query = self
             .0
             .get_config(lang)
-            .expect("must have a config to emit matches")
-            .highlight_query;
+            .and_then(|config| config.highlight_query.as_ref())
+            .expect("must have a highlight query to emit matches");
 
         // Highlight queries should reject the match when a pattern is marked with
         // `(#is-not? local)` and any capture in the pattern matches a definition in scope.
@@ -538,9 +906,7 @@ impl<'a, T: LanguageLoader> QueryLoader<'a> for HighlightQueryLoader<&'a T> {
         {
             let has_local_reference = mat.matched_nodes().any(|n| {
                 let range = n.node.byte_range();
-                let text: Cow<str> = source
-                    .byte_slice(range.start as usize..range.end as usize)
-                    .into();
+                let text: Cow<str> = n.node.text(source).into();
                 locals_cursor
                     .locals
                     .lookup_reference(locals_cursor.current_scope(), &text)
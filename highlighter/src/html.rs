@@ -0,0 +1,97 @@
+//! Rendering of highlighted source code as HTML.
+
+use ropey::RopeSlice;
+
+use crate::config::LanguageLoader;
+use crate::highlighter::{Highlight, HighlightEvent, Highlighter};
+use crate::Syntax;
+
+/// Renders the source as HTML with each highlighted region wrapped in a
+/// `<span class="...">` element.
+///
+/// `class_for` maps a highlight to the value of the `class` attribute of its span. Nested
+/// highlights produce nested spans. Spans are closed at the end of each line and reopened at the
+/// start of the next line so that every line of the output is well-formed on its own. The text is
+/// HTML-escaped but the output is not wrapped in any `<pre>` or `<code>` element.
+pub fn render_html<'a>(
+    syntax: &Syntax,
+    src: RopeSlice<'_>,
+    loader: &impl LanguageLoader,
+    class_for: impl Fn(Highlight) -> &'a str,
+) -> String {
+    let mut renderer = HtmlRenderer {
+        out: String::with_capacity(src.len_bytes() * 2),
+        open: Vec::new(),
+        class_for,
+    };
+    let end = src.len_bytes() as u32;
+    let mut highlighter = Highlighter::new(syntax, src, loader, ..);
+    let mut pos = 0;
+    while pos < end {
+        let next = highlighter.next_event_offset().clamp(pos, end);
+        renderer.text(src.byte_slice(pos as usize..next as usize));
+        pos = next;
+        if pos < end {
+            let (event, highlights) = highlighter.advance();
+            if event == HighlightEvent::Refresh {
+                renderer.close_all();
+                renderer.open.clear();
+            }
+            for highlight in highlights {
+                renderer.open_span(highlight);
+                renderer.open.push(highlight);
+            }
+        }
+    }
+    renderer.close_all();
+    renderer.out
+}
+
+struct HtmlRenderer<F> {
+    out: String,
+    /// The stack of highlights with a currently open `<span>`.
+    open: Vec<Highlight>,
+    class_for: F,
+}
+
+impl<'a, F: Fn(Highlight) -> &'a str> HtmlRenderer<F> {
+    fn open_span(&mut self, highlight: Highlight) {
+        self.out.push_str("<span class=\"");
+        escape_into(&mut self.out, (self.class_for)(highlight));
+        self.out.push_str("\">");
+    }
+
+    fn close_all(&mut self) {
+        for _ in 0..self.open.len() {
+            self.out.push_str("</span>");
+        }
+    }
+
+    fn text(&mut self, text: RopeSlice<'_>) {
+        for chunk in text.chunks() {
+            let mut lines = chunk.split('\n');
+            escape_into(&mut self.out, lines.next().unwrap_or_default());
+            for line in lines {
+                self.close_all();
+                self.out.push('\n');
+                for i in 0..self.open.len() {
+                    self.open_span(self.open[i]);
+                }
+                escape_into(&mut self.out, line);
+            }
+        }
+    }
+}
+
+fn escape_into(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
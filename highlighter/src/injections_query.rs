@@ -9,17 +9,18 @@ use once_cell::sync::Lazy;
 use regex_cursor::engines::meta::Regex;
 use ropey::RopeSlice;
 
-use crate::config::{LanguageConfig, LanguageLoader};
+use crate::config::{is_custom_property, LanguageConfig, LanguageLoader, QueryError, QuerySource};
 use crate::highlighter::Highlight;
 use crate::locals::Locals;
 use crate::parse::LayerUpdateFlags;
-use crate::{Injection, Language, Layer, LayerData, Range, Syntax, TREE_SITTER_MATCH_LIMIT};
+use crate::{ranges_intersect, Injection, Language, Layer, LayerData, Range, Syntax};
 use tree_sitter::{
-    query::{self, InvalidPredicateError, UserPredicate},
+    query::{InvalidPredicateError, UserPredicate},
     Capture, Grammar, InactiveQueryCursor, MatchedNodeIdx, Node, Pattern, Query, QueryMatch,
 };
 
-const SHEBANG: &str = r"#!\s*(?:\S*[/\\](?:env\s+(?:\-\S+\s+)*)?)?([^\s\.\d]+)";
+/// Matches a shebang at the start of a line, capturing the name of the interpreter.
+const SHEBANG: &str = r"(?m)^[ \t]*#![ \t]*(?:\S*[/\\](?:env[ \t]+(?:\-\S+[ \t]+)*)?)?([^\s\.\d]+)";
 static SHEBANG_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(SHEBANG).unwrap());
 
 #[derive(Clone, Default, Debug)]
@@ -27,6 +28,15 @@ pub struct InjectionProperties {
     include_children: IncludedChildren,
     language: Option<Box<str>>,
     combined: bool,
+    /// Set by `(#set! injection.combined.include-gaps)`: the text between the nodes of an
+    /// injection spanning multiple nodes (for example the `///` prefixes of doc comments) is
+    /// included in the injected layer so that its parser sees contiguous text.
+    ///
+    /// Only the gaps between nodes are filled: excluded children and `@injection.exclude`
+    /// captures within a node are still excluded by `intersect_ranges` and gaps are clamped to
+    /// the ranges of the parent layer. The gaps aren't part of the layer's `Injection`s, so
+    /// edits within them cause the layer to be reparsed when the injection query runs.
+    include_gaps: bool,
 }
 
 /// An indicator in the document or query source file which used by the loader to know which
@@ -58,6 +68,11 @@ pub struct InjectionQueryMatch<'tree> {
     language: Language,
     scope: Option<InjectionScope>,
     node: Node<'tree>,
+    /// The ranges of the `@injection.exclude` captures of the match.
+    excluded_ranges: Vec<Range>,
+    /// Whether the gap between this node and the previous range of the layer is included in
+    /// the layer. Only set for injections which span multiple nodes.
+    include_gaps: bool,
     last_match: bool,
     pattern: Pattern,
 }
@@ -86,6 +101,7 @@ pub struct InjectionsQuery {
     injection_query: Query,
     injection_properties: HashMap<Pattern, InjectionProperties>,
     injection_content_capture: Option<Capture>,
+    injection_exclude_capture: Option<Capture>,
     injection_language_capture: Option<Capture>,
     injection_filename_capture: Option<Capture>,
     injection_shebang_capture: Option<Capture>,
@@ -102,12 +118,16 @@ impl InjectionsQuery {
         grammar: Grammar,
         injection_query_text: &str,
         local_query_text: &str,
-    ) -> Result<Self, query::ParseError> {
-        let mut query_source =
-            String::with_capacity(injection_query_text.len() + local_query_text.len());
-        query_source.push_str(injection_query_text);
-        query_source.push_str(local_query_text);
+    ) -> Result<Self, QueryError> {
+        Self::new_impl(grammar, injection_query_text, local_query_text, false)
+    }
 
+    pub(crate) fn new_impl(
+        grammar: Grammar,
+        injection_query_text: &str,
+        local_query_text: &str,
+        custom_properties: bool,
+    ) -> Result<Self, QueryError> {
         let mut injection_properties: HashMap<Pattern, InjectionProperties> = HashMap::new();
         let mut not_scope_inherits = HashSet::new();
         let injection_query = Query::new(grammar, injection_query_text, |pattern, predicate| {
@@ -139,12 +159,23 @@ impl InjectionsQuery {
                     key: "injection.combined",
                     val: None,
                 } => injection_properties.entry(pattern).or_default().combined = true,
+                UserPredicate::SetProperty {
+                    key: "injection.combined.include-gaps",
+                    val: None,
+                } => {
+                    injection_properties
+                        .entry(pattern)
+                        .or_default()
+                        .include_gaps = true
+                }
+                predicate if custom_properties && is_custom_property(&predicate) => (),
                 predicate => {
                     return Err(InvalidPredicateError::unknown(predicate));
                 }
             }
             Ok(())
-        })?;
+        })
+        .map_err(QueryError::new(QuerySource::Injections))?;
         let mut local_query = Query::new(grammar, local_query_text, |pattern, predicate| {
             match predicate {
                 UserPredicate::SetProperty {
@@ -155,12 +186,14 @@ impl InjectionsQuery {
                         not_scope_inherits.insert(pattern);
                     }
                 }
+                predicate if custom_properties && is_custom_property(&predicate) => (),
                 predicate => {
                     return Err(InvalidPredicateError::unknown(predicate));
                 }
             }
             Ok(())
-        })?;
+        })
+        .map_err(QueryError::new(QuerySource::Locals))?;
 
         // The injection queries do not track references - these are read by the highlight
         // query instead.
@@ -169,6 +202,7 @@ impl InjectionsQuery {
         Ok(InjectionsQuery {
             injection_properties,
             injection_content_capture: injection_query.get_capture("injection.content"),
+            injection_exclude_capture: injection_query.get_capture("injection.exclude"),
             injection_language_capture: injection_query.get_capture("injection.language"),
             injection_filename_capture: injection_query.get_capture("injection.filename"),
             injection_shebang_capture: injection_query.get_capture("injection.shebang"),
@@ -180,73 +214,129 @@ impl InjectionsQuery {
         })
     }
 
-    pub(crate) fn configure(&self, f: &mut impl FnMut(&str) -> Option<Highlight>) {
-        let local_definition_captures = self
-            .local_query
-            .captures()
-            .filter_map(|(capture, name)| {
-                let suffix = name.strip_prefix("local.definition.")?;
-                Some((capture, f(suffix)?))
-            })
-            .collect();
+    /// The query used to find injections, without the locals query.
+    pub fn query(&self) -> &Query {
+        &self.injection_query
+    }
+
+    /// Disables an injection pattern so that it no longer produces injections.
+    ///
+    /// This is useful to work around a known-buggy pattern in a shipped injections query without
+    /// editing the query source. The pattern must be disabled before the query is used to
+    /// parse any documents: layers which were already injected by the pattern are not removed.
+    pub fn disable_injection(&mut self, pattern: Pattern) {
+        self.injection_query.disable_pattern(pattern);
+    }
+
+    /// Returns whether the highlight of any local definition capture changed.
+    pub(crate) fn configure(&self, f: &mut impl FnMut(&str) -> Option<Highlight>) -> bool {
+        let current = self.local_definition_captures.load();
+        // Only allocate a new map once a highlight differs from the current configuration.
+        let mut local_definition_captures: Option<HashMap<_, _>> = None;
+        for (capture, name) in self.local_query.captures() {
+            let Some(suffix) = name.strip_prefix("local.definition.") else {
+                continue;
+            };
+            let highlight = f(suffix);
+            match &mut local_definition_captures {
+                Some(captures) => captures.extend(highlight.map(|highlight| (capture, highlight))),
+                None if current.get(&capture).copied() == highlight => (),
+                None => {
+                    // Captures are visited in order so all prior captures are unchanged.
+                    let mut captures: HashMap<_, _> = current
+                        .iter()
+                        .filter(|(prior, _)| prior.idx() < capture.idx())
+                        .map(|(&capture, &highlight)| (capture, highlight))
+                        .collect();
+                    captures.extend(highlight.map(|highlight| (capture, highlight)));
+                    local_definition_captures = Some(captures);
+                }
+            }
+        }
+        let Some(local_definition_captures) = local_definition_captures else {
+            return false;
+        };
         self.local_definition_captures
             .store(Arc::new(local_definition_captures));
+        true
     }
 
+    /// Processes a match of the injection query.
+    ///
+    /// `static_languages` caches the languages of patterns which set a static
+    /// `injection.language` property. The loader may resolve names differently over time (for
+    /// example after languages were added) and the query may be shared between loaders, so the
+    /// cache only lives for a single execution of the query.
     fn process_match<'a, 'tree>(
         &self,
         query_match: &QueryMatch<'a, 'tree>,
         node_idx: MatchedNodeIdx,
         source: RopeSlice<'a>,
         loader: impl LanguageLoader,
+        static_languages: &mut HashMap<Pattern, Option<Language>>,
     ) -> Option<InjectionQueryMatch<'tree>> {
         let properties = self.injection_properties.get(&query_match.pattern());
 
-        let mut marker = None;
-        let mut last_content_node = 0;
-        let mut content_nodes = 0;
-        for (i, matched_node) in query_match.matched_nodes().enumerate() {
-            let capture = Some(matched_node.capture);
-            if capture == self.injection_language_capture {
-                let range = matched_node.node.byte_range();
-                marker = Some(InjectionLanguageMarker::Match(
-                    source.byte_slice(range.start as usize..range.end as usize),
-                ));
-            } else if capture == self.injection_filename_capture {
-                let range = matched_node.node.byte_range();
-                marker = Some(InjectionLanguageMarker::Filename(
-                    source.byte_slice(range.start as usize..range.end as usize),
-                ));
-            } else if capture == self.injection_shebang_capture {
-                let range = matched_node.node.byte_range();
-                let node_slice = source.byte_slice(range.start as usize..range.end as usize);
-
+        // If a match captures more than one of the language, filename and shebang captures the
+        // marker is taken from the last of them.
+        let marker = query_match
+            .matched_nodes()
+            .filter(|matched_node| {
+                let capture = Some(matched_node.capture);
+                capture == self.injection_language_capture
+                    || capture == self.injection_filename_capture
+                    || capture == self.injection_shebang_capture
+            })
+            .last()
+            .and_then(|matched_node| {
+                let capture = Some(matched_node.capture);
+                let text = matched_node.node.text(source);
+                if capture == self.injection_language_capture {
+                    return Some(InjectionLanguageMarker::Match(text));
+                }
+                if capture == self.injection_filename_capture {
+                    return Some(InjectionLanguageMarker::Filename(text));
+                }
                 // some languages allow space and newlines before the actual string content
                 // so a shebang could be on either the first or second line
-                let lines = if let Ok(end) = node_slice.try_line_to_byte(2) {
-                    node_slice.byte_slice(..end)
+                let lines = if let Ok(end) = text.try_line_to_byte(2) {
+                    text.byte_slice(..end)
                 } else {
-                    node_slice
+                    text
                 };
 
-                marker = SHEBANG_REGEX
+                SHEBANG_REGEX
                     .captures_iter(regex_cursor::Input::new(lines))
                     .map(|cap| {
                         let cap = lines.byte_slice(cap.get_group(1).unwrap().range());
                         InjectionLanguageMarker::Shebang(cap)
                     })
                     .next()
-            } else if capture == self.injection_content_capture {
-                content_nodes += 1;
+            });
 
+        let mut last_content_node = 0;
+        let mut content_nodes = 0;
+        let mut excluded_ranges = Vec::new();
+        for (i, matched_node) in query_match.matched_nodes().enumerate() {
+            if Some(matched_node.capture) == self.injection_content_capture {
+                content_nodes += 1;
                 last_content_node = i as u32;
+            } else if Some(matched_node.capture) == self.injection_exclude_capture {
+                excluded_ranges.push(matched_node.node.byte_range());
             }
         }
-        let marker = marker.or(properties
-            .and_then(|p| p.language.as_deref())
-            .map(InjectionLanguageMarker::Name))?;
-
-        let language = loader.language_for_marker(marker)?;
+        let language = match marker {
+            Some(marker) => loader.language_for_marker(marker)?,
+            None => {
+                let name = properties.and_then(|p| p.language.as_deref())?;
+                let language = static_languages
+                    .entry(query_match.pattern())
+                    .or_insert_with(|| {
+                        loader.language_for_marker(InjectionLanguageMarker::Name(name))
+                    });
+                (*language)?
+            }
+        };
         let scope = if properties.is_some_and(|p| p.combined) {
             Some(InjectionScope::Pattern {
                 pattern: query_match.pattern(),
@@ -261,10 +351,12 @@ impl InjectionsQuery {
         };
 
         Some(InjectionQueryMatch {
+            include_gaps: scope.is_some() && properties.is_some_and(|p| p.include_gaps),
             language,
             scope,
             include_children: properties.map(|p| p.include_children).unwrap_or_default(),
             node: query_match.matched_node(node_idx).node.clone(),
+            excluded_ranges,
             last_match: last_content_node == node_idx,
             pattern: query_match.pattern(),
         })
@@ -289,16 +381,27 @@ impl InjectionsQuery {
         node: &Node<'a>,
         source: RopeSlice<'a>,
         loader: &'a impl LanguageLoader,
+        match_limit: u32,
     ) -> impl Iterator<Item = InjectionQueryMatch<'a>> + 'a {
-        let mut cursor = InactiveQueryCursor::new(0..u32::MAX, TREE_SITTER_MATCH_LIMIT)
-            .execute_query(&self.injection_query, node, source);
+        let mut cursor = InactiveQueryCursor::new(0..u32::MAX, match_limit).execute_query(
+            &self.injection_query,
+            node,
+            source,
+        );
         let injection_content_capture = self.injection_content_capture.unwrap();
+        let mut static_languages = HashMap::new();
         let iter = iter::from_fn(move || loop {
             let (query_match, node_idx) = cursor.next_matched_node()?;
             if query_match.matched_node(node_idx).capture != injection_content_capture {
                 continue;
             }
-            let Some(mat) = self.process_match(&query_match, node_idx, source, loader) else {
+            let Some(mat) = self.process_match(
+                &query_match,
+                node_idx,
+                source,
+                loader,
+                &mut static_languages,
+            ) else {
                 query_match.remove();
                 continue;
             };
@@ -378,6 +481,7 @@ impl Syntax {
         let layer_data = &mut self.layer_mut(layer);
         let Some(LanguageConfig {
             injection_query: ref injections_query,
+            ref match_limit,
             ..
         }) = loader.get_config(layer_data.language)
         else {
@@ -394,11 +498,24 @@ impl Syntax {
         let mut old_injections = take(&mut layer_data.injections).into_iter().peekable();
 
         profile_scope!("injection_query_execute");
-        let injection_query = injections_query.execute(&parse_tree.root_node(), source, loader);
+        let injection_query =
+            injections_query.execute(&parse_tree.root_node(), source, loader, *match_limit);
 
+        let language = layer_data.language;
+        let tree_range = parse_tree.root_node().byte_range();
         let mut combined_injections: HashMap<InjectionScope, Layer> = HashMap::with_capacity(32);
         for mat in injection_query {
             let matched_node_range = mat.node.byte_range();
+            // Injecting the language of this layer into a node which covers the whole layer
+            // would create a layer with the same text and language as this one, which would
+            // contain the same injection again, and so on. Skip these injections so that a
+            // misconfigured injection query can't nest layers endlessly.
+            if mat.language == language
+                && matched_node_range.start <= tree_range.start
+                && tree_range.end <= matched_node_range.end
+            {
+                continue;
+            }
             let mut insert_position = injections.len();
             // if a parent node already has an injection ignore this injection
             // in theory the first condition would be enough to detect that
@@ -460,19 +577,44 @@ impl Syntax {
             }
 
             let old_len = injections.len();
-            intersect_ranges(mat.include_children, mat.node, &parent_ranges, |range| {
-                layer_data.ranges.push(tree_sitter::Range {
-                    start_point: tree_sitter::Point::ZERO,
-                    end_point: tree_sitter::Point::ZERO,
-                    start_byte: range.start,
-                    end_byte: range.end,
-                });
-                injections.push(Injection {
-                    range,
-                    layer,
-                    matched_node_range: matched_node_range.clone(),
-                });
-            });
+            let mut fill_gap = mat.include_gaps;
+            intersect_ranges(
+                mat.include_children,
+                mat.node,
+                &mat.excluded_ranges,
+                &parent_ranges,
+                |range| {
+                    if take(&mut fill_gap) {
+                        if let Some(gap) =
+                            extend_to_gap(&mut layer_data.ranges, range.start, &parent_ranges)
+                        {
+                            // The gap isn't part of any injection so `map_injections` doesn't
+                            // notice edits within it. Reparse the layer if one was edited. The
+                            // gap is in new coordinates while each edit is relative to the
+                            // document before any of the edits were applied, so shift every edit
+                            // by the offset of the edits before it.
+                            let mut offset = 0i64;
+                            layer_data.flags.modified |= edits.iter().any(|edit| {
+                                let start = (edit.start_byte as i64 + offset) as u32;
+                                let end = (edit.new_end_byte as i64 + offset) as u32;
+                                offset += edit.offset() as i64;
+                                start <= gap.end && gap.start <= end
+                            });
+                        }
+                    }
+                    layer_data.ranges.push(tree_sitter::Range {
+                        start_point: tree_sitter::Point::ZERO,
+                        end_point: tree_sitter::Point::ZERO,
+                        start_byte: range.start,
+                        end_byte: range.end,
+                    });
+                    injections.push(Injection {
+                        range,
+                        layer,
+                        matched_node_range: matched_node_range.clone(),
+                    });
+                },
+            );
             if old_len != insert_position {
                 let inserted = injections.len() - old_len;
                 injections[insert_position..].rotate_right(inserted);
@@ -493,6 +635,36 @@ impl Syntax {
         layer_data.injections = injections;
     }
 
+    /// Moves the injections of a layer whose content wasn't modified through the edits instead of
+    /// running the injection query again like [`Syntax::run_injection_query`].
+    ///
+    /// The tree of the layer was only moved, so the query would find the same injections at the
+    /// new positions. The ranges of the injected layers lie within the ranges of this layer, so
+    /// they weren't edited either and only need to be moved as well.
+    pub(crate) fn move_injections(
+        &mut self,
+        layer: Layer,
+        edits: &[tree_sitter::InputEdit],
+        mut parse_layer: impl FnMut(Layer),
+    ) {
+        if !self.injections_enabled {
+            self.layer_mut(layer).injections.clear();
+            return;
+        }
+        self.map_injections(layer, None, edits);
+        let injections = take(&mut self.layer_mut(layer).injections);
+        for injection in &injections {
+            let layer_data = self.layer_mut(injection.layer);
+            // Combined injections visit their layer multiple times.
+            if !layer_data.flags.touched {
+                layer_data.flags.touched = true;
+                move_ranges(&mut layer_data.ranges, edits);
+                parse_layer(injection.layer);
+            }
+        }
+        self.layer_mut(layer).injections = injections;
+    }
+
     /// Maps the layers injection ranges through edits to enable incremental re-parsing.
     fn map_injections(
         &mut self,
@@ -611,7 +783,10 @@ impl Syntax {
                     parse_tree: None,
                     ranges: Vec::new(),
                     injections: Vec::new(),
-                    flags: LayerUpdateFlags::default(),
+                    flags: LayerUpdateFlags {
+                        created: true,
+                        ..LayerUpdateFlags::default()
+                    },
                     parent: Some(parent),
                     locals: Locals::default(),
                 });
@@ -647,9 +822,51 @@ impl Syntax {
     }
 }
 
+/// Moves the sorted `ranges` through the edits, none of which may intersect a range.
+fn move_ranges(ranges: &mut [tree_sitter::Range], edits: &[tree_sitter::InputEdit]) {
+    let mut edits = edits.iter().peekable();
+    let mut offset = 0;
+    for range in ranges {
+        while let Some(edit) = edits.next_if(|edit| edit.old_end_byte <= range.start_byte) {
+            offset += edit.offset();
+        }
+        range.start_byte = (range.start_byte as i32 + offset) as u32;
+        range.end_byte = (range.end_byte as i32 + offset) as u32;
+    }
+}
+
+/// Extends the last of the given `ranges` of a layer up to `start` for injections with the
+/// `injection.combined.include-gaps` property, returning the range which was added.
+///
+/// The gap is only filled within the parent range containing the end of the last range, so the
+/// layer never includes text which isn't part of the parent layer.
+fn extend_to_gap(
+    ranges: &mut [tree_sitter::Range],
+    start: u32,
+    parent_ranges: &[tree_sitter::Range],
+) -> Option<Range> {
+    let last = ranges.last_mut()?;
+    if last.end_byte >= start {
+        return None;
+    }
+    let i = parent_ranges.partition_point(|parent_range| parent_range.end_byte <= last.end_byte);
+    let parent_range = parent_ranges
+        .get(i)
+        .filter(|parent_range| parent_range.start_byte <= last.end_byte)?;
+    let gap = last.end_byte..start.min(parent_range.end_byte);
+    last.end_byte = gap.end;
+    Some(gap)
+}
+
+/// Computes the ranges of an injection into `node`: the parts of the node's range which lie
+/// within `parent_ranges` and outside of the excluded children and `excluded_ranges`.
+///
+/// `excluded_ranges` may be unsorted and may overlap each other, the children of the node or
+/// the bounds of the node.
 fn intersect_ranges(
     include_children: IncludedChildren,
     node: Node,
+    excluded_ranges: &[Range],
     parent_ranges: &[tree_sitter::Range],
     push_range: impl FnMut(Range),
 ) {
@@ -658,6 +875,33 @@ fn intersect_ranges(
     let parent_ranges = parent_ranges[i..]
         .iter()
         .map(|range| range.start_byte..range.end_byte);
+    if !excluded_ranges.is_empty() {
+        // Explicitly excluded ranges are rare so the children and the explicit exclusions are
+        // simply merged into one buffer.
+        let mut excluded: Vec<Range> = match include_children {
+            IncludedChildren::None => node.children().map(|node| node.byte_range()).collect(),
+            IncludedChildren::All => Vec::new(),
+            IncludedChildren::Unnamed => node
+                .children()
+                .filter(|node| node.is_named())
+                .map(|node| node.byte_range())
+                .collect(),
+        };
+        excluded.extend(excluded_ranges.iter().map(|excluded| {
+            excluded.start.clamp(range.start, range.end)..excluded.end.clamp(range.start, range.end)
+        }));
+        excluded.sort_unstable_by_key(|range| range.start);
+        excluded.dedup_by(|next, prev| {
+            // Merge overlapping ranges since `intersect_ranges_impl` expects disjoint ones.
+            if next.start <= prev.end {
+                prev.end = prev.end.max(next.end);
+                true
+            } else {
+                false
+            }
+        });
+        return intersect_ranges_impl(range, excluded.into_iter(), parent_ranges, push_range);
+    }
     match include_children {
         IncludedChildren::None => intersect_ranges_impl(
             range,
@@ -718,8 +962,3 @@ fn intersect_ranges_impl(
         push_range(start..range.end)
     }
 }
-
-fn ranges_intersect(a: &Range, b: &Range) -> bool {
-    // Adapted from <https://github.com/helix-editor/helix/blob/8df58b2e1779dcf0046fb51ae1893c1eebf01e7c/helix-core/src/selection.rs#L156-L163>
-    a.start == b.start || (a.end > b.start && b.end > a.start)
-}
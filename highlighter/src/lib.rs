@@ -9,7 +9,11 @@ macro_rules! profile_scope {
 }
 
 mod config;
+#[cfg(feature = "diff")]
+mod diff;
 pub mod highlighter;
+#[cfg(feature = "html")]
+pub mod html;
 mod injections_query;
 mod parse;
 #[cfg(all(test, feature = "fixtures"))]
@@ -19,20 +23,25 @@ mod tests;
 pub mod fixtures;
 pub mod locals;
 pub mod query_iter;
+pub mod tags;
 pub mod text_object;
 mod tree_cursor;
 
+use hashbrown::HashMap;
 use locals::Locals;
 use ropey::RopeSlice;
 
 use slab::Slab;
 
-use std::fmt;
+use std::fmt::{self, Write};
 use std::hash::{Hash, Hasher};
 use std::time::Duration;
-use tree_sitter::{IncompatibleGrammarError, Node, Tree};
+use tree_sitter::{IncompatibleGrammarError, InvalidRangesError, Node, Tree};
 
-pub use crate::config::{read_query, LanguageConfig, LanguageLoader};
+pub use crate::config::{
+    read_query, InvalidRegexError, LanguageConfig, LanguageConfigBuilder, LanguageLoader,
+    LanguageMatchTable, QueryError, QueryOverride, QueryOverrides, QuerySource,
+};
 pub use crate::injections_query::{InjectionLanguageMarker, InjectionsQuery};
 use crate::parse::LayerUpdateFlags;
 pub use crate::tree_cursor::TreeCursor;
@@ -49,6 +58,20 @@ impl Layer {
     }
 }
 
+/// The injection layers which were added to and removed from a [`Syntax`] by an update, see
+/// [`Syntax::update`].
+///
+/// Layer ids are reused: the id of a removed layer may be assigned to an unrelated layer which
+/// is added by a later update, so data cached for a removed layer must be dropped. The layers
+/// added and removed by the same update never share an id.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LayerChanges {
+    /// The layers created by the update, sorted by id.
+    pub added: Vec<Layer>,
+    /// The layers removed by the update, sorted by id. Their ids are no longer valid.
+    pub removed: Vec<Layer>,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Language(pub u32);
 
@@ -97,6 +120,51 @@ pub struct Syntax {
     /// is parsed, which can significantly improve performance for large files
     /// with many injections (e.g., files with doc comments, SQL strings, etc.).
     injections_enabled: bool,
+    config: SyntaxConfig,
+}
+
+/// Sizes of the allocations made up front by a [`Syntax`] and the iterators over it.
+///
+/// The defaults suit most documents. Documents which are known to contain many injections,
+/// like a markdown file with hundreds of code blocks, can avoid repeatedly growing the
+/// allocations by choosing larger capacities. See [`Syntax::new_with_config`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyntaxConfig {
+    /// The number of layers to allocate space for. Defaults to 32.
+    pub layer_capacity: usize,
+    /// The number of simultaneously entered injection layers that each
+    /// [`QueryIter`](crate::query_iter::QueryIter) allocates space for. Defaults to 8.
+    pub query_iter_capacity: usize,
+    /// The size in bytes from which documents are rejected with
+    /// [`Error::ExceededMaximumSize`]. Defaults to [`SyntaxConfig::DEFAULT_MAX_SOURCE_SIZE`].
+    ///
+    /// Tree-sitter uses signed 32-bit offsets, so limits above 2 GiB are treated as 2 GiB.
+    pub max_source_size: usize,
+}
+
+impl SyntaxConfig {
+    /// The default [`SyntaxConfig::max_source_size`] of 512 MiB. Tree-sitter becomes too slow
+    /// to be useful for larger documents.
+    pub const DEFAULT_MAX_SOURCE_SIZE: usize = 512 * 1024 * 1024;
+    /// Tree-sitter uses signed 32-bit offsets so it can't parse larger documents.
+    const MAX_SOURCE_SIZE: usize = 1 << 31;
+
+    pub(crate) fn check_source_size(&self, source: RopeSlice) -> Result<(), Error> {
+        if source.len_bytes() >= self.max_source_size.min(Self::MAX_SOURCE_SIZE) {
+            return Err(Error::ExceededMaximumSize);
+        }
+        Ok(())
+    }
+}
+
+impl Default for SyntaxConfig {
+    fn default() -> Self {
+        Self {
+            layer_capacity: 32,
+            query_iter_capacity: 8,
+            max_source_size: Self::DEFAULT_MAX_SOURCE_SIZE,
+        }
+    }
 }
 
 impl Syntax {
@@ -106,28 +174,18 @@ impl Syntax {
         timeout: Duration,
         loader: &impl LanguageLoader,
     ) -> Result<Self, Error> {
-        let root_layer = LayerData {
-            parse_tree: None,
-            language,
-            flags: LayerUpdateFlags::default(),
-            ranges: vec![tree_sitter::Range {
-                start_byte: 0,
-                end_byte: u32::MAX,
-                start_point: tree_sitter::Point::ZERO,
-                end_point: tree_sitter::Point::MAX,
-            }],
-            injections: Vec::new(),
-            parent: None,
-            locals: Locals::default(),
-        };
-        let mut layers = Slab::with_capacity(32);
-        let root = layers.insert(root_layer);
-        let mut syntax = Self {
-            root: Layer(root as u32),
-            layers,
-            injections_enabled: true,
-        };
+        Self::new_with_config(source, language, timeout, loader, SyntaxConfig::default())
+    }
 
+    /// Like [`Syntax::new`] but with the allocation sizes and size limit of `config`.
+    pub fn new_with_config(
+        source: RopeSlice,
+        language: Language,
+        timeout: Duration,
+        loader: &impl LanguageLoader,
+        config: SyntaxConfig,
+    ) -> Result<Self, Error> {
+        let mut syntax = Self::with_root(language, None, root_range(None), config);
         syntax.update(source, timeout, &[], loader).map(|_| syntax)
     }
 
@@ -140,31 +198,97 @@ impl Syntax {
         old_tree: Option<&Tree>,
         edits: &[tree_sitter::InputEdit],
     ) -> Result<Self, Error> {
+        Self::new_with_old_tree_and_config(
+            source,
+            language,
+            timeout,
+            loader,
+            old_tree,
+            edits,
+            SyntaxConfig::default(),
+        )
+    }
+
+    /// Like [`Syntax::new_with_old_tree`] but with the allocation sizes and size limit of
+    /// `config`.
+    pub fn new_with_old_tree_and_config(
+        source: RopeSlice,
+        language: Language,
+        timeout: Duration,
+        loader: &impl LanguageLoader,
+        old_tree: Option<&Tree>,
+        edits: &[tree_sitter::InputEdit],
+        config: SyntaxConfig,
+    ) -> Result<Self, Error> {
+        let mut syntax = Self::with_root(language, old_tree, root_range(None), config);
+        syntax
+            .update(source, timeout, edits, loader)
+            .map(|_| syntax)
+    }
+
+    /// Create a new Syntax which only parses the `window` byte range of the document.
+    ///
+    /// This is an experimental way to quickly parse and highlight the visible part of a very
+    /// large document. The window should include some margin around the visible region so that
+    /// nodes which start before the visible region are still parsed correctly. Nodes outside of
+    /// the window are absent from the syntax tree and injections are only discovered within the
+    /// window. The window is not adjusted by the edits passed to [`Syntax::update`], use
+    /// [`Syntax::set_window`] to move it.
+    ///
+    /// The size limit of `config` applies to the whole document, not only to the window.
+    pub fn new_windowed(
+        source: RopeSlice,
+        language: Language,
+        window: Range,
+        timeout: Duration,
+        loader: &impl LanguageLoader,
+        config: SyntaxConfig,
+    ) -> Result<Self, Error> {
+        let window = root_range(Some((source, window)));
+        let mut syntax = Self::with_root(language, None, window, config);
+        syntax.update(source, timeout, &[], loader).map(|_| syntax)
+    }
+
+    fn with_root(
+        language: Language,
+        old_tree: Option<&Tree>,
+        range: tree_sitter::Range,
+        config: SyntaxConfig,
+    ) -> Self {
         let root_layer = LayerData {
             parse_tree: old_tree.cloned(),
             language,
             flags: LayerUpdateFlags::default(),
-            ranges: vec![tree_sitter::Range {
-                start_byte: 0,
-                end_byte: u32::MAX,
-                start_point: tree_sitter::Point::ZERO,
-                end_point: tree_sitter::Point::MAX,
-            }],
+            ranges: vec![range],
             injections: Vec::new(),
             parent: None,
             locals: Locals::default(),
         };
-        let mut layers = Slab::with_capacity(32);
+        let mut layers = Slab::with_capacity(config.layer_capacity);
         let root = layers.insert(root_layer);
-        let mut syntax = Self {
+        Self {
             root: Layer(root as u32),
             layers,
             injections_enabled: true,
-        };
+            config,
+        }
+    }
 
-        syntax
-            .update(source, timeout, edits, loader)
-            .map(|_| syntax)
+    /// Restricts parsing of the root layer to the `window` byte range, or parses the whole
+    /// document if `window` is `None`. The change takes effect on the next [`Syntax::update`].
+    ///
+    /// `source` is the document the window refers to, the one passed to the next update. It's
+    /// used to compute the row and column of the window.
+    ///
+    /// See [`Syntax::new_windowed`].
+    pub fn set_window(&mut self, source: RopeSlice, window: Option<Range>) {
+        let root = self.root;
+        self.layer_mut(root).ranges = vec![root_range(window.map(|window| (source, window)))];
+    }
+
+    /// The allocation sizes and size limit this syntax tree was created with.
+    pub fn config(&self) -> SyntaxConfig {
+        self.config
     }
 
     pub fn layer(&self, layer: Layer) -> &LayerData {
@@ -219,6 +343,251 @@ impl Syntax {
             .descendant_for_byte_range(start, end)
     }
 
+    /// Finds the smallest node spanning the given row and (byte) column positions, descending
+    /// into the innermost injection layer which covers them.
+    ///
+    /// The points are converted to byte offsets with [`Syntax::point_to_byte`] since the points
+    /// of nodes in injected layers are unreliable.
+    pub fn descendant_for_point_range(
+        &self,
+        source: RopeSlice,
+        start: tree_sitter::Point,
+        end: tree_sitter::Point,
+    ) -> Option<Node<'_>> {
+        self.descendant_for_byte_range(
+            Self::point_to_byte(source, start),
+            Self::point_to_byte(source, end),
+        )
+    }
+
+    /// Finds the smallest named node spanning the given row and (byte) column positions,
+    /// descending into the innermost injection layer which covers them.
+    ///
+    /// See [`Syntax::descendant_for_point_range`].
+    pub fn named_descendant_for_point_range(
+        &self,
+        source: RopeSlice,
+        start: tree_sitter::Point,
+        end: tree_sitter::Point,
+    ) -> Option<Node<'_>> {
+        self.named_descendant_for_byte_range(
+            Self::point_to_byte(source, start),
+            Self::point_to_byte(source, end),
+        )
+    }
+
+    /// Finds the smallest node at the given byte offset, descending into the innermost injection
+    /// layer which covers the offset.
+    #[inline]
+    pub fn node_at_byte(&self, pos: u32) -> Option<Node<'_>> {
+        self.descendant_for_byte_range(pos, pos)
+    }
+
+    /// Returns the byte ranges of all `ERROR` and `MISSING` nodes, sorted by their start.
+    ///
+    /// All layers are searched so syntax errors within injections are reported as well. `MISSING`
+    /// nodes are zero-width. The descendants of an `ERROR` node are not searched.
+    pub fn errors(&self) -> impl Iterator<Item = Range> + '_ {
+        let mut errors = Vec::new();
+        for (_, layer) in &self.layers {
+            let Some(tree) = layer.tree() else {
+                continue;
+            };
+            let mut cursor = tree.root_node().walk();
+            'walk: loop {
+                let node = cursor.node();
+                if node.is_error() || node.is_missing() {
+                    errors.push(node.byte_range());
+                } else if node.has_error() && cursor.goto_first_child() {
+                    continue;
+                }
+                while !cursor.goto_next_sibling() {
+                    if !cursor.goto_parent() {
+                        break 'walk;
+                    }
+                }
+            }
+        }
+        errors.sort_unstable_by_key(|range| (range.start, range.end));
+        errors.into_iter()
+    }
+
+    /// Converts a byte offset into a row and (byte) column in `source`.
+    ///
+    /// The points of nodes in the root layer (see [`Node::start_position`]) agree with this
+    /// conversion. Injected layers are parsed with ranges starting at [`tree_sitter::Point::ZERO`]
+    /// though, so the points of their nodes are unreliable and should be converted from byte
+    /// offsets with this function instead.
+    ///
+    /// Bytes past the end of the document are clamped to the end of the document.
+    pub fn byte_to_point(source: RopeSlice, byte: u32) -> tree_sitter::Point {
+        let byte = (byte as usize).min(source.len_bytes()) as u32;
+        let row = source.byte_to_line(byte as usize);
+        let col = byte as usize - source.line_to_byte(row);
+        tree_sitter::Point {
+            row: row as u32,
+            col: col as u32,
+        }
+    }
+
+    /// Converts a row and (byte) column in `source` into a byte offset.
+    ///
+    /// Rows past the end of the document are clamped to the end of the document and columns past
+    /// the end of a line are clamped to the end of the line (including the line ending).
+    pub fn point_to_byte(source: RopeSlice, point: tree_sitter::Point) -> u32 {
+        let row = point.row as usize;
+        if row >= source.len_lines() {
+            return source.len_bytes() as u32;
+        }
+        let line_start = source.line_to_byte(row);
+        let line_end = source.line_to_byte(row + 1);
+        (line_start + (point.col as usize).min(line_end - line_start)) as u32
+    }
+
+    /// Converts a byte offset into a row and UTF-16 code unit column in `source`, as used by
+    /// the Language Server Protocol.
+    ///
+    /// Like [`Syntax::byte_to_point`] but the column counts UTF-16 code units instead of bytes.
+    /// Bytes past the end of the document are clamped to the end of the document.
+    pub fn byte_to_utf16_point(source: RopeSlice, byte: u32) -> tree_sitter::Point {
+        let byte = (byte as usize).min(source.len_bytes()) as u32;
+        let row = source.byte_to_line(byte as usize);
+        let line_start = source.char_to_utf16_cu(source.line_to_char(row));
+        let col = source.char_to_utf16_cu(source.byte_to_char(byte as usize)) - line_start;
+        tree_sitter::Point {
+            row: row as u32,
+            col: col as u32,
+        }
+    }
+
+    /// Converts a row and UTF-16 code unit column in `source` into a byte offset.
+    ///
+    /// Rows and columns past the end are clamped like in [`Syntax::point_to_byte`]. A column in
+    /// the middle of a surrogate pair is rounded down to the start of the character.
+    pub fn utf16_point_to_byte(source: RopeSlice, point: tree_sitter::Point) -> u32 {
+        let row = point.row as usize;
+        if row >= source.len_lines() {
+            return source.len_bytes() as u32;
+        }
+        let line_start = source.char_to_utf16_cu(source.line_to_char(row));
+        let line_end = source.char_to_utf16_cu(source.line_to_char(row + 1));
+        let utf16_cu = line_start + (point.col as usize).min(line_end - line_start);
+        source.char_to_byte(source.utf16_cu_to_char(utf16_cu)) as u32
+    }
+
+    /// Returns the number of UTF-16 code units in the `byte_range` of `source`.
+    ///
+    /// The range is clamped to the end of the document and a reversed range is empty.
+    pub fn utf16_len(source: RopeSlice, byte_range: Range) -> u32 {
+        let to_utf16_cu = |byte: u32| {
+            let byte = (byte as usize).min(source.len_bytes());
+            source.char_to_utf16_cu(source.byte_to_char(byte)) as u32
+        };
+        to_utf16_cu(byte_range.end).saturating_sub(to_utf16_cu(byte_range.start))
+    }
+
+    /// Finds the byte offset of the bracket matching the bracket at `pos`, using the
+    /// [`DEFAULT_BRACKET_PAIRS`].
+    ///
+    /// See [`Syntax::matching_bracket_with`].
+    #[inline]
+    pub fn matching_bracket(&self, pos: u32) -> Option<u32> {
+        self.matching_bracket_with(pos, DEFAULT_BRACKET_PAIRS)
+    }
+
+    /// Finds the byte offset of the bracket matching the bracket at `pos`.
+    ///
+    /// The bracket at `pos` must be an anonymous node whose kind is one of the opening or closing
+    /// brackets in `pairs`. The matching bracket is searched for among the node's siblings in the
+    /// innermost injection layer covering `pos`, so brackets are only matched when the tree
+    /// agrees that they belong together.
+    pub fn matching_bracket_with(&self, pos: u32, pairs: &[(&str, &str)]) -> Option<u32> {
+        let node = self
+            .tree_for_byte_range(pos, pos)
+            .root_node()
+            .descendant_for_byte_range(pos, pos.saturating_add(1))?;
+        if node.is_named() || node.start_byte() != pos {
+            return None;
+        }
+        let kind = node.kind();
+        if let Some(&(open, close)) = pairs.iter().find(|(open, _)| *open == kind) {
+            find_matching_bracket(node, open, close, true)
+        } else if let Some(&(open, close)) = pairs.iter().find(|(_, close)| *close == kind) {
+            find_matching_bracket(node, close, open, false)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the language of the root layer.
+    pub fn root_language(&self) -> Language {
+        self.layer(self.root).language
+    }
+
+    /// Returns the language of the innermost injection layer which includes the byte `pos`.
+    pub fn language_at(&self, pos: u32) -> Language {
+        self.layer(self.layer_for_byte_range(pos, pos)).language
+    }
+
+    /// Returns the name of the language at the byte `pos`, see [`Syntax::language_at`] and
+    /// [`LanguageLoader::language_name`].
+    pub fn language_name_at<'a>(
+        &self,
+        pos: u32,
+        loader: &'a impl LanguageLoader,
+    ) -> Option<&'a str> {
+        loader.language_name(self.language_at(pos))
+    }
+
+    /// Renders the layers of this syntax as an indented tree for debugging.
+    ///
+    /// Every layer is printed on its own line with its language (named by
+    /// [`LanguageLoader::language_name`] if possible) and its byte ranges, followed by the layers
+    /// injected into it with one more level of indentation. If `with_trees` is set the
+    /// S-expression of each layer's syntax tree (see [`Node::to_sexp`]) is printed below the
+    /// layer.
+    pub fn debug_tree(&self, loader: &impl LanguageLoader, with_trees: bool) -> String {
+        let mut children: HashMap<Layer, Vec<Layer>> = HashMap::new();
+        for (key, layer) in &self.layers {
+            if let Some(parent) = layer.parent {
+                children.entry(parent).or_default().push(Layer(key as u32));
+            }
+        }
+        for layers in children.values_mut() {
+            layers.sort_by_key(|&layer| self.layer(layer).ranges.first().map(|r| r.start_byte));
+        }
+
+        let mut out = String::new();
+        let mut stack = vec![(self.root, 0)];
+        while let Some((layer, depth)) = stack.pop() {
+            let data = self.layer(layer);
+            let indent = depth * 2;
+            match loader.language_name(data.language) {
+                Some(name) => write!(out, "{:indent$}{name}", ""),
+                None => write!(out, "{:indent$}{:?}", "", data.language),
+            }
+            .unwrap();
+            let ranges: Vec<_> = data
+                .ranges
+                .iter()
+                .map(|range| range.start_byte..range.end_byte)
+                .collect();
+            writeln!(out, " {layer:?} {ranges:?}").unwrap();
+            if with_trees {
+                let indent = indent + 2;
+                match data.tree() {
+                    Some(tree) => writeln!(out, "{:indent$}{}", "", tree.root_node().to_sexp()),
+                    None => writeln!(out, "{:indent$}(not parsed)", ""),
+                }
+                .unwrap();
+            }
+            if let Some(layers) = children.get(&layer) {
+                stack.extend(layers.iter().rev().map(|&child| (child, depth + 1)));
+            }
+        }
+        out
+    }
+
     /// Finds the smallest injection layer that fully includes the range `start..=end`.
     pub fn layer_for_byte_range(&self, start: u32, end: u32) -> Layer {
         self.layers_for_byte_range(start, end)
@@ -249,6 +618,96 @@ impl Syntax {
         }))
     }
 
+    /// Finds the layer whose parse tree the given node belongs to.
+    ///
+    /// Returns `None` if the node belongs to a tree which isn't part of this syntax tree, for
+    /// example a tree which was since replaced by reparsing.
+    pub fn layer_for_node(&self, node: &Node) -> Option<Layer> {
+        self.layers
+            .iter()
+            .find(|(_, layer)| layer.tree().is_some_and(|tree| tree.contains_node(node)))
+            .map(|(idx, _)| Layer(idx as u32))
+    }
+
+    /// Returns the name of the language of the layer the given node belongs to, see
+    /// [`Syntax::layer_for_node`] and [`LanguageLoader::language_name`].
+    pub fn node_language_name<'a>(
+        &self,
+        node: &Node,
+        loader: &'a impl LanguageLoader,
+    ) -> Option<&'a str> {
+        let layer = self.layer_for_node(node)?;
+        loader.language_name(self.layer(layer).language)
+    }
+
+    /// Returns the injections within the given layer, sorted by their range.
+    ///
+    /// Note that injection ranges are relative to the start of the document rather than the
+    /// start of the layer.
+    pub fn injections(&self, layer: Layer) -> &[Injection] {
+        &self.layer(layer).injections
+    }
+
+    /// Builds a tree of all injections in the document, starting at the root layer.
+    ///
+    /// Layers which are injected at multiple ranges (combined injections) appear once per
+    /// range. The children of each node are the injections of the node's layer which fall
+    /// within the node's range.
+    pub fn injection_tree(&self) -> InjectionNode {
+        self.injection_node(self.root, self.tree().root_node().byte_range())
+    }
+
+    fn injection_node(&self, layer: Layer, range: Range) -> InjectionNode {
+        let children = self
+            .injections(layer)
+            .iter()
+            .filter(|injection| {
+                injection.range.start >= range.start && injection.range.end <= range.end
+            })
+            .map(|injection| self.injection_node(injection.layer, injection.range.clone()))
+            .collect();
+        InjectionNode {
+            layer,
+            language: self.layer(layer).language,
+            range,
+            children,
+        }
+    }
+
+    /// Exports the layout of all layers so that the `Syntax` can be rebuilt with
+    /// [`Syntax::from_layout`].
+    pub fn layout(&self) -> SyntaxLayout {
+        // Layers are stored in a slab so the layer keys are not contiguous.
+        let mut indices = HashMap::with_capacity(self.layers.len());
+        indices.insert(self.root, 0);
+        for (key, _) in &self.layers {
+            let layer = Layer(key as u32);
+            if layer != self.root {
+                indices.insert(layer, indices.len());
+            }
+        }
+        let mut layers = vec![None; indices.len()];
+        for (key, layer_data) in &self.layers {
+            layers[indices[&Layer(key as u32)]] = Some(LayerLayout {
+                language: layer_data.language,
+                ranges: layer_data.ranges.clone(),
+                parent: layer_data.parent.map(|parent| indices[&parent]),
+                injections: layer_data
+                    .injections
+                    .iter()
+                    .map(|injection| InjectionLayout {
+                        range: injection.range.clone(),
+                        layer: indices[&injection.layer],
+                        matched_node_range: injection.matched_node_range.clone(),
+                    })
+                    .collect(),
+            });
+        }
+        SyntaxLayout {
+            layers: layers.into_iter().map(Option::unwrap).collect(),
+        }
+    }
+
     pub fn walk(&self) -> TreeCursor<'_> {
         TreeCursor::new(self)
     }
@@ -300,6 +759,49 @@ pub struct Injection {
     matched_node_range: Range,
 }
 
+/// The languages, included ranges and injections of all layers of a [`Syntax`].
+///
+/// The layout can be exported with [`Syntax::layout`] and used to rebuild a `Syntax` for the
+/// same source text with [`Syntax::from_layout`], for example to cache the layer structure of
+/// a document on disk. All fields are public so that the layout can be stored in any format.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SyntaxLayout {
+    /// The layers of the syntax tree. The first layer is the root layer.
+    pub layers: Vec<LayerLayout>,
+}
+
+/// The layout of a single layer within a [`SyntaxLayout`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LayerLayout {
+    pub language: Language,
+    /// The ranges included when parsing this layer.
+    pub ranges: Vec<tree_sitter::Range>,
+    /// The index of the parent layer within [`SyntaxLayout::layers`].
+    pub parent: Option<usize>,
+    /// The injections within this layer, sorted by their range.
+    pub injections: Vec<InjectionLayout>,
+}
+
+/// The layout of a single injection within a [`LayerLayout`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InjectionLayout {
+    pub range: Range,
+    /// The index of the injected layer within [`SyntaxLayout::layers`].
+    pub layer: usize,
+    /// The range of the node in the parent layer which was matched by the injection query.
+    pub matched_node_range: Range,
+}
+
+/// A node in the tree of injections returned by [`Syntax::injection_tree`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InjectionNode {
+    pub layer: Layer,
+    pub language: Language,
+    /// The byte range of the injection relative to the start of the document.
+    pub range: Range,
+    pub children: Vec<InjectionNode>,
+}
+
 #[derive(Debug, Clone)]
 pub struct LayerData {
     pub language: Language,
@@ -346,6 +848,11 @@ impl LayerData {
         self.parse_tree.as_ref()
     }
 
+    /// Returns the scopes and definitions found by the locals query in this layer.
+    pub fn locals(&self) -> &Locals {
+        &self.locals
+    }
+
     /// Returns the injection range **within this layers** that contains `idx`.
     /// This function will not descend into nested injections
     pub fn injection_at_byte_idx(&self, idx: u32) -> Option<&Injection> {
@@ -369,8 +876,16 @@ impl LayerData {
 #[derive(Debug, PartialEq, Eq)]
 pub enum Error {
     Timeout,
+    Cancelled,
+    /// The document is larger than [`SyntaxConfig::max_source_size`].
     ExceededMaximumSize,
-    InvalidRanges,
+    InvalidRanges(Language, InvalidRangesError),
+    /// The edit at the given index of the edits passed to [`Syntax::update`] is malformed.
+    ///
+    /// Edits must be sorted by their position in the old document, must not overlap and must lie
+    /// within both the old and the new document.
+    InvalidEdit(usize),
+    InvalidLayout,
     Unknown,
     NoRootConfig,
     IncompatibleGrammar(Language, IncompatibleGrammarError),
@@ -380,22 +895,54 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Timeout => f.write_str("configured timeout was exceeded"),
+            Self::Cancelled => f.write_str("the update was cancelled"),
             Self::ExceededMaximumSize => f.write_str("input text exceeds the maximum allowed size"),
-            Self::InvalidRanges => f.write_str("invalid ranges"),
+            Self::InvalidRanges(language, err) => {
+                write!(
+                    f,
+                    "invalid ranges for a layer of language {language:?}: {err}"
+                )
+            }
+            Self::InvalidEdit(idx) => write!(
+                f,
+                "edit {idx} is unsorted, overlaps another edit or lies outside of the document"
+            ),
+            Self::InvalidLayout => f.write_str("the syntax layout refers to a non-existent layer"),
             Self::Unknown => f.write_str("an unknown error occurred"),
             Self::NoRootConfig => f.write_str(
                 "`LanguageLoader::get_config` for the root layer language returned `None`",
             ),
-            Self::IncompatibleGrammar(language, IncompatibleGrammarError { abi_version }) => {
-                write!(
-                    f,
-                    "failed to load grammar for language {language:?} with ABI version {abi_version}"
-                )
+            Self::IncompatibleGrammar(language, err) => {
+                write!(f, "failed to load grammar for language {language:?}: {err}")
             }
         }
     }
 }
 
+/// The range of the root layer: the `window` of the given source, or the whole document.
+fn root_range(window: Option<(RopeSlice, Range)>) -> tree_sitter::Range {
+    let Some((source, window)) = window else {
+        return tree_sitter::Range {
+            start_byte: 0,
+            end_byte: u32::MAX,
+            start_point: tree_sitter::Point::ZERO,
+            end_point: tree_sitter::Point::MAX,
+        };
+    };
+    let len = source.len_bytes() as u32;
+    let end_point = if window.end >= len {
+        tree_sitter::Point::MAX
+    } else {
+        Syntax::byte_to_point(source, window.end)
+    };
+    tree_sitter::Range {
+        start_byte: window.start,
+        end_byte: window.end,
+        start_point: Syntax::byte_to_point(source, window.start.min(len)),
+        end_point,
+    }
+}
+
 /// The maximum number of in-progress matches a TS cursor can consider at once.
 /// This is set to a constant in order to avoid performance problems for medium to large files. Set with `set_match_limit`.
 /// Using such a limit means that we lose valid captures, so there is fundamentally a tradeoff here.
@@ -414,8 +961,57 @@ impl fmt::Display for Error {
 /// Neovim chose 64 for this value somewhat arbitrarily (<https://github.com/neovim/neovim/pull/18397>).
 /// 64 is too low for some languages though. In particular, it breaks some highlighting for record fields in Erlang record definitions.
 /// This number can be increased if new syntax highlight breakages are found, as long as the performance penalty is not too high.
+///
+/// This is the default limit. It can be overridden per language with [`LanguageConfig::with_match_limit`].
 pub const TREE_SITTER_MATCH_LIMIT: u32 = 256;
 
+/// Searches the siblings of `node` for the bracket of kind `other` which matches it, skipping
+/// over nested pairs.
+fn find_matching_bracket<'tree>(
+    node: Node<'tree>,
+    same: &str,
+    other: &str,
+    forward: bool,
+) -> Option<u32> {
+    let step = |node: &Node<'tree>| {
+        if forward {
+            node.next_sibling()
+        } else {
+            node.prev_sibling()
+        }
+    };
+    let mut depth = 0usize;
+    let mut sibling = step(&node);
+    while let Some(node) = sibling {
+        if !node.is_named() {
+            if node.kind() == same {
+                depth += 1;
+            } else if node.kind() == other {
+                if depth == 0 {
+                    return Some(node.start_byte());
+                }
+                depth -= 1;
+            }
+        }
+        sibling = step(&node);
+    }
+    None
+}
+
+/// The bracket pairs used by [`Syntax::matching_bracket`].
+pub const DEFAULT_BRACKET_PAIRS: &[(&str, &str)] =
+    &[("(", ")"), ("[", "]"), ("{", "}"), ("<", ">")];
+
+/// Returns whether the byte ranges `a` and `b` overlap.
+///
+/// Ranges are half-open so ranges which only touch do not intersect, with the exception of
+/// ranges which start at the same byte: those always intersect, even if one of them is empty.
+/// This matches [`Node::intersects`](tree_sitter::Node::intersects).
+pub fn ranges_intersect(a: &Range, b: &Range) -> bool {
+    // Adapted from <https://github.com/helix-editor/helix/blob/8df58b2e1779dcf0046fb51ae1893c1eebf01e7c/helix-core/src/selection.rs#L156-L163>
+    a.start == b.start || (a.end > b.start && b.end > a.start)
+}
+
 // use 32 bit ranges since TS doesn't support files larger than 2GiB anyway
 // and it allows us to save a lot memory/improve cache efficiency
 type Range = std::ops::Range<u32>;
@@ -1,14 +1,15 @@
 use std::{
     borrow::Cow,
+    iter,
     ops::{Index, IndexMut},
 };
 
 use hashbrown::HashMap;
 use kstring::KString;
 use ropey::RopeSlice;
-use tree_sitter::{Capture, InactiveQueryCursor};
+use tree_sitter::{Capture, InactiveQueryCursor, InputEdit};
 
-use crate::{LanguageConfig, LanguageLoader, Layer, Range, Syntax, TREE_SITTER_MATCH_LIMIT};
+use crate::{LanguageConfig, LanguageLoader, Layer, Range, Syntax};
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub struct Scope(u32);
@@ -66,6 +67,72 @@ impl Locals {
         None
     }
 
+    /// Maps the ranges of all scopes and definitions through the given edits.
+    ///
+    /// This is used for layers which were moved by edits but whose contents were not modified
+    /// so that the locals don't need to be recomputed by running the locals query again.
+    ///
+    /// The edits are validated by [`Syntax::update`](crate::Syntax::update) so shifting a range
+    /// past the start or end of the document is not expected. The arithmetic saturates instead
+    /// of wrapping anyway.
+    pub(crate) fn edit(&mut self, edits: &[InputEdit]) {
+        fn edit_range(range: &mut Range, edit: &InputEdit) {
+            if range.end < edit.start_byte {
+                return;
+            }
+            if range.start >= edit.old_end_byte {
+                range.start = range.start.saturating_add_signed(edit.offset());
+            } else if range.start > edit.start_byte {
+                range.start = edit.new_end_byte;
+            }
+            if range.end >= edit.old_end_byte {
+                range.end = range.end.saturating_add_signed(edit.offset());
+            } else {
+                range.end = edit.new_end_byte;
+            }
+        }
+
+        for (i, scope) in self.scopes.iter_mut().enumerate() {
+            // Apply the edits in reverse like `Tree::edit` so that earlier edits don't disrupt
+            // the positioning of later edits.
+            for edit in edits.iter().rev() {
+                // The root scope always covers the entire document.
+                if i != Scope::ROOT.idx() {
+                    edit_range(&mut scope.range, edit);
+                }
+                for definition in scope.definitions.values_mut() {
+                    edit_range(&mut definition.range, edit);
+                }
+            }
+        }
+    }
+
+    /// Returns the scopes which contain `pos`, starting with the root scope and ending with the
+    /// innermost scope.
+    pub fn scopes_at(&self, pos: u32) -> impl Iterator<Item = Scope> + '_ {
+        let mut next = Some(Scope::ROOT);
+        iter::from_fn(move || {
+            let scope = next?;
+            let children = &self[scope].children;
+            let child_idx = children.partition_point(|&child| self[child].range.end <= pos);
+            next = children
+                .get(child_idx)
+                .copied()
+                .filter(|&child| self[child].range.start <= pos);
+            Some(scope)
+        })
+    }
+
+    /// Returns the names and definitions defined directly in the given scope.
+    ///
+    /// Definitions of parent scopes are not included. The order is unspecified.
+    pub fn definitions_in(&self, scope: Scope) -> impl Iterator<Item = (&str, &Definition)> {
+        self[scope]
+            .definitions
+            .iter()
+            .map(|(name, definition)| (name.as_str(), definition))
+    }
+
     pub fn scope_cursor(&self, pos: u32) -> ScopeCursor<'_> {
         let mut scope = Scope::ROOT;
         let mut scope_stack = Vec::with_capacity(8);
@@ -168,6 +235,23 @@ pub struct ScopeData {
     parent: Option<Scope>,
 }
 
+impl ScopeData {
+    /// The byte range covered by this scope. The root scope covers the entire document.
+    pub fn range(&self) -> Range {
+        self.range.clone()
+    }
+
+    /// The scope containing this scope or `None` for the root scope.
+    pub fn parent(&self) -> Option<Scope> {
+        self.parent
+    }
+
+    /// Whether references in this scope may resolve to definitions in the parent scope.
+    pub fn inherits(&self) -> bool {
+        self.inherit
+    }
+}
+
 impl Syntax {
     pub(crate) fn run_local_query(
         &mut self,
@@ -179,6 +263,7 @@ impl Syntax {
         let layer_data = &mut self.layer_mut(layer);
         let Some(LanguageConfig {
             ref injection_query,
+            ref match_limit,
             ..
         }) = loader.get_config(layer_data.language)
         else {
@@ -190,8 +275,11 @@ impl Syntax {
         }
 
         let root = layer_data.parse_tree.as_ref().unwrap().root_node();
-        let mut cursor = InactiveQueryCursor::new(0..u32::MAX, TREE_SITTER_MATCH_LIMIT)
-            .execute_query(&injection_query.local_query, &root, source);
+        let mut cursor = InactiveQueryCursor::new(0..u32::MAX, *match_limit).execute_query(
+            &injection_query.local_query,
+            &root,
+            source,
+        );
         let mut locals = Locals::default();
         let mut scope = Scope::ROOT;
 
@@ -215,10 +303,7 @@ impl Syntax {
                     parent: Some(scope),
                 });
             } else if definition_captures.contains_key(&capture) {
-                let text = match source
-                    .byte_slice(range.start as usize..range.end as usize)
-                    .into()
-                {
+                let text = match matched_node.node.text(source).into() {
                     Cow::Borrowed(inner) => KString::from_ref(inner),
                     Cow::Owned(inner) => KString::from_string(inner),
                 };
@@ -279,4 +364,34 @@ mod test {
         assert_eq!(cursor.advance(100), scope1);
         assert_eq!(cursor.advance(110), Scope::ROOT);
     }
+
+    #[test]
+    fn scopes_at() {
+        let mut locals = Locals::default();
+        let scope1 = locals.push(ScopeData {
+            definitions: Default::default(),
+            range: 5..105,
+            inherit: true,
+            children: Default::default(),
+            parent: Some(Scope::ROOT),
+        });
+        let scope2 = locals.push(ScopeData {
+            definitions: Default::default(),
+            range: 10..100,
+            inherit: false,
+            children: Default::default(),
+            parent: Some(scope1),
+        });
+
+        let scopes_at = |pos| locals.scopes_at(pos).collect::<Vec<_>>();
+        assert_eq!(scopes_at(0), [Scope::ROOT]);
+        assert_eq!(scopes_at(5), [Scope::ROOT, scope1]);
+        assert_eq!(scopes_at(50), [Scope::ROOT, scope1, scope2]);
+        assert_eq!(scopes_at(100), [Scope::ROOT, scope1]);
+        assert_eq!(scopes_at(105), [Scope::ROOT]);
+
+        assert_eq!(locals[scope2].range(), 10..100);
+        assert_eq!(locals[scope2].parent(), Some(scope1));
+        assert!(!locals[scope2].inherits());
+    }
 }
@@ -1,27 +1,208 @@
 use std::mem::take;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 
 use ropey::RopeSlice;
+use slab::Slab;
 use tree_sitter::Parser;
 
 use crate::config::LanguageLoader;
-use crate::{Error, LayerData, Syntax};
+use crate::locals::Locals;
+use crate::{Error, Injection, Layer, LayerChanges, LayerData, Syntax, SyntaxConfig, SyntaxLayout};
 
 impl Syntax {
+    /// Rebuilds a syntax tree from a layout previously exported with [`Syntax::layout`].
+    ///
+    /// Every layer is still parsed but injection discovery is skipped: the layers and their
+    /// injections are taken from the layout as-is instead of running the injection queries. The
+    /// layout must have been exported for the same `source`. Later calls to [`Syntax::update`]
+    /// run the injection queries as usual for the layers affected by edits.
+    ///
+    /// The layout doesn't include the [`SyntaxConfig`] of the exported syntax tree, so it is
+    /// passed separately, for example from [`Syntax::config`].
+    pub fn from_layout(
+        source: RopeSlice,
+        layout: &SyntaxLayout,
+        timeout: Duration,
+        loader: &impl LanguageLoader,
+        config: SyntaxConfig,
+    ) -> Result<Self, Error> {
+        profile_scope!("Syntax::from_layout");
+        config.check_source_size(source)?;
+        let len = layout.layers.len();
+        let valid = len != 0
+            && layout.layers.iter().all(|layer| {
+                layer.parent.is_none_or(|parent| parent < len)
+                    && layer
+                        .injections
+                        .iter()
+                        .all(|injection| injection.layer < len)
+            });
+        if !valid {
+            return Err(Error::InvalidLayout);
+        }
+
+        let mut layers = Slab::with_capacity(len.max(config.layer_capacity));
+        for layer in &layout.layers {
+            layers.insert(LayerData {
+                language: layer.language,
+                parse_tree: None,
+                ranges: layer.ranges.clone(),
+                injections: layer
+                    .injections
+                    .iter()
+                    .map(|injection| Injection {
+                        range: injection.range.clone(),
+                        layer: Layer(injection.layer as u32),
+                        matched_node_range: injection.matched_node_range.clone(),
+                    })
+                    .collect(),
+                flags: LayerUpdateFlags::default(),
+                parent: layer.parent.map(|parent| Layer(parent as u32)),
+                locals: Locals::default(),
+            });
+        }
+        let mut syntax = Self {
+            layers,
+            // The root is always the first layer in the layout and the slab is empty so the keys
+            // match the indices in the layout.
+            root: Layer(0),
+            injections_enabled: true,
+            config,
+        };
+
+        let mut parser = Parser::new();
+        parser.set_timeout(timeout);
+        for idx in 0..len {
+            let layer = Layer(idx as u32);
+            let layer_data = syntax.layer_mut(layer);
+            if layer_data.ranges.is_empty() {
+                continue;
+            }
+            layer_data.parse(&mut parser, source, loader, None)?;
+            syntax.run_local_query(layer, source, loader);
+        }
+        if syntax.layer(syntax.root).parse_tree.is_none() {
+            return Err(Error::NoRootConfig);
+        }
+        Ok(syntax)
+    }
+
+    /// Updates the syntax tree after `edits` were applied to the document, which is now
+    /// `source`.
+    ///
+    /// The edits must be sorted by their position in the old document and must not overlap.
+    /// Malformed edits are rejected with [`Error::InvalidEdit`] before the syntax tree is
+    /// changed.
+    ///
+    /// Only the layers whose content was edited run their injection query again: the injections
+    /// of the other layers are moved along with the edits.
+    ///
+    /// Returns the injection layers which were added and removed by the update.
     pub fn update(
         &mut self,
         source: RopeSlice,
         timeout: Duration,
         edits: &[tree_sitter::InputEdit],
         loader: &impl LanguageLoader,
-    ) -> Result<(), Error> {
-        profile_scope!("Syntax::update");
-        // size limit of 512MiB, TS just cannot handle files this big (too
-        // slow). Furthermore, TS uses 32 (signed) bit indices so this limit
-        // must never be raised above 2GiB
-        if source.len_bytes() >= 512 * 1024 * 1024 {
-            return Err(Error::ExceededMaximumSize);
+    ) -> Result<LayerChanges, Error> {
+        self.update_impl(source, timeout, edits, loader, None)
+    }
+
+    /// Parses `source` from scratch, for example after the document was rewritten by an
+    /// external tool and no edits are available.
+    ///
+    /// The result is the same as creating a new `Syntax` for the root layer's language but the
+    /// allocations of the existing layers are reused: injected layers which are found again with
+    /// the same language keep their storage and layers which are no longer injected are removed.
+    /// All trees are discarded so nothing from the previous source is reused while parsing. The
+    /// window set with [`Syntax::set_window`] is kept.
+    ///
+    /// If an error is returned the syntax tree is left as it was before the call, so it still
+    /// describes the previous source. Like [`Syntax::update`] the added and removed layers are
+    /// returned.
+    pub fn reparse(
+        &mut self,
+        source: RopeSlice,
+        timeout: Duration,
+        loader: &impl LanguageLoader,
+    ) -> Result<LayerChanges, Error> {
+        self.config.check_source_size(source)?;
+        // Parsing can still fail part way through, after some layers were already parsed and
+        // their injections replaced, so keep the old layers around to restore them.
+        let old_layers = self.layers.clone();
+        for (_, layer) in &mut self.layers {
+            layer.parse_tree = None;
+            layer.flags = LayerUpdateFlags::default();
+        }
+        let res = self.update_impl(source, timeout, &[], loader, None);
+        if res.is_err() {
+            self.layers = old_layers;
         }
+        res
+    }
+
+    /// Updates the syntax tree like [`Syntax::update`] but stops early with
+    /// [`Error::Cancelled`] once `cancel` is set.
+    ///
+    /// The flag may be set from another thread. It is checked periodically while parsing each
+    /// layer in addition to the `timeout`.
+    pub fn update_with_cancel(
+        &mut self,
+        source: RopeSlice,
+        timeout: Duration,
+        edits: &[tree_sitter::InputEdit],
+        loader: &impl LanguageLoader,
+        cancel: &AtomicBool,
+    ) -> Result<LayerChanges, Error> {
+        self.update_impl(
+            source,
+            timeout,
+            edits,
+            loader,
+            Some(&mut |_| cancel.load(Ordering::Relaxed)),
+        )
+    }
+
+    /// Updates the syntax tree like [`Syntax::update`] while periodically reporting progress.
+    ///
+    /// `on_progress` is called with the byte offset the parser has reached. Each layer is parsed
+    /// separately, so the offset belongs to whichever layer is currently being parsed and only
+    /// moves through that layer's included ranges. It starts over for every parsed layer. The
+    /// callback is not called for layers which are small enough to parse in one go.
+    pub fn update_with_progress(
+        &mut self,
+        source: RopeSlice,
+        timeout: Duration,
+        edits: &[tree_sitter::InputEdit],
+        loader: &impl LanguageLoader,
+        mut on_progress: impl FnMut(u32),
+    ) -> Result<LayerChanges, Error> {
+        self.update_impl(
+            source,
+            timeout,
+            edits,
+            loader,
+            Some(&mut |byte_offset| {
+                on_progress(byte_offset);
+                false
+            }),
+        )
+    }
+
+    fn update_impl(
+        &mut self,
+        source: RopeSlice,
+        timeout: Duration,
+        edits: &[tree_sitter::InputEdit],
+        loader: &impl LanguageLoader,
+        mut progress: Option<&mut dyn FnMut(u32) -> bool>,
+    ) -> Result<LayerChanges, Error> {
+        profile_scope!("Syntax::update");
+        self.config.check_source_size(source)?;
+        // The injection ranges are mapped through the edits without any bounds checks, so
+        // malformed edits must be rejected before they corrupt the layers.
+        validate_edits(edits, source.len_bytes() as u32)?;
 
         let mut queue = Vec::with_capacity(32);
         let root_flags = &mut self.layer_mut(self.root).flags;
@@ -41,7 +222,7 @@ impl Syntax {
                 continue;
             }
 
-            if let Some(tree) = &mut layer_data.parse_tree {
+            let reparsed = if let Some(tree) = &mut layer_data.parse_tree {
                 if layer_data.flags.moved || layer_data.flags.modified {
                     for edit in edits.iter().rev() {
                         // Apply the edits in reverse.
@@ -52,40 +233,58 @@ impl Syntax {
                 }
                 if layer_data.flags.modified {
                     profile_scope!("layer_parse");
-                    layer_data.parse(&mut parser, source, loader)?;
+                    layer_data.parse(&mut parser, source, loader, progress.as_deref_mut())?;
+                } else if layer_data.flags.moved {
+                    // The contents of the layer are unchanged so the locals only need to be
+                    // shifted rather than recomputed.
+                    layer_data.locals.edit(edits);
                 }
+                layer_data.flags.modified
             } else {
                 // always parse if this layer has never been parsed before
-                layer_data.parse(&mut parser, source, loader)?;
-            }
-            {
+                layer_data.parse(&mut parser, source, loader, progress.as_deref_mut())?;
+                true
+            };
+            if reparsed {
                 profile_scope!("run_injection_query");
                 self.run_injection_query(layer, edits, source, loader, |layer| queue.push(layer));
+                self.run_local_query(layer, source, loader);
+            } else {
+                self.move_injections(layer, edits, |layer| queue.push(layer));
             }
-            self.run_local_query(layer, source, loader);
         }
 
         if self.layer(self.root).parse_tree.is_none() {
             return Err(Error::NoRootConfig);
         }
 
-        self.prune_dead_layers();
-        Ok(())
+        Ok(self.prune_dead_layers())
     }
 
     /// Reset all `LayerUpdateFlags` and remove all untouched layers
-    fn prune_dead_layers(&mut self) {
-        self.layers
-            .retain(|_, layer| take(&mut layer.flags).touched);
+    fn prune_dead_layers(&mut self) -> LayerChanges {
+        let mut changes = LayerChanges::default();
+        self.layers.retain(|idx, layer| {
+            let flags = take(&mut layer.flags);
+            let layer = Layer(idx as u32);
+            match (flags.created, flags.touched) {
+                (true, true) => changes.added.push(layer),
+                (false, false) => changes.removed.push(layer),
+                _ => (),
+            }
+            flags.touched
+        });
+        changes
     }
 }
 
 impl LayerData {
-    fn parse(
+    fn parse<'p>(
         &mut self,
         parser: &mut Parser,
         source: RopeSlice,
         loader: &impl LanguageLoader,
+        progress: Option<&mut (dyn FnMut(u32) -> bool + 'p)>,
     ) -> Result<(), Error> {
         let Some(config) = loader.get_config(self.language) else {
             return Ok(());
@@ -95,7 +294,7 @@ impl LayerData {
         }
         parser
             .set_included_ranges(&self.ranges)
-            .map_err(|_| Error::InvalidRanges)?;
+            .map_err(|err| Error::InvalidRanges(self.language, err))?;
 
         // HACK:
         // This is a workaround for a bug within the lexer (in the C library) or maybe within
@@ -120,7 +319,22 @@ impl LayerData {
                 && tree_range.end >= included_ranges_range.end
         });
 
-        let tree = parser.parse(source, tree).ok_or(Error::Timeout)?;
+        let tree = match progress {
+            Some(progress) => {
+                let mut cancelled = false;
+                parser
+                    .parse_with_progress(source, tree, |state| {
+                        cancelled = progress(state.current_byte_offset());
+                        cancelled
+                    })
+                    .ok_or(if cancelled {
+                        Error::Cancelled
+                    } else {
+                        Error::Timeout
+                    })?
+            }
+            None => parser.parse(source, tree).ok_or(Error::Timeout)?,
+        };
         self.parse_tree = Some(tree);
         Ok(())
     }
@@ -128,8 +342,38 @@ impl LayerData {
 
 #[derive(Debug, PartialEq, Eq, Default, Clone)]
 pub(crate) struct LayerUpdateFlags {
+    /// Whether the layer was created since the layers were last pruned.
+    pub created: bool,
     pub reused: bool,
     pub modified: bool,
     pub moved: bool,
     pub touched: bool,
 }
+
+/// Checks that the edits are sorted, don't overlap and lie within the old and new document,
+/// where `len` is the length of the new document.
+fn validate_edits(edits: &[tree_sitter::InputEdit], len: u32) -> Result<(), Error> {
+    let len = i64::from(len);
+    let old_len = len
+        - edits
+            .iter()
+            .map(|edit| i64::from(edit.offset()))
+            .sum::<i64>();
+    // The sum of the offsets of the previous edits, which maps positions after these edits
+    // from the old document to the new document.
+    let mut offset = 0;
+    let mut prev_end = 0;
+    for (idx, edit) in edits.iter().enumerate() {
+        let valid = prev_end <= edit.start_byte
+            && edit.start_byte <= edit.old_end_byte
+            && edit.start_byte <= edit.new_end_byte
+            && i64::from(edit.old_end_byte) <= old_len
+            && i64::from(edit.new_end_byte) + offset <= len;
+        if !valid {
+            return Err(Error::InvalidEdit(idx));
+        }
+        offset += i64::from(edit.offset());
+        prev_end = edit.old_end_byte;
+    }
+    Ok(())
+}
@@ -28,6 +28,8 @@ struct LayerQueryIter<'a, 'tree> {
     peeked: Option<MatchedNode<'tree>>,
     language: Language,
     scope_cursor: ScopeCursor<'tree>,
+    /// Whether each capture of the query is yielded, see [`QueryIter::filter_captures`].
+    enabled_captures: Option<Box<[bool]>>,
 }
 
 impl<'a, 'tree> LayerQueryIter<'a, 'tree> {
@@ -43,6 +45,14 @@ impl<'a, 'tree> LayerQueryIter<'a, 'tree> {
                 let mut cursor = self.cursor.take()?;
                 let (query_match, node_idx) = cursor.next_matched_node()?;
                 let node = query_match.matched_node(node_idx);
+                if self
+                    .enabled_captures
+                    .as_ref()
+                    .is_some_and(|enabled| !enabled[node.capture.idx()])
+                {
+                    self.cursor = Some(cursor);
+                    continue;
+                }
                 let match_id = query_match.id();
                 let pattern = query_match.pattern();
                 let range = node.node.byte_range();
@@ -77,6 +87,25 @@ impl<'a, 'tree> LayerQueryIter<'a, 'tree> {
     fn consume(&mut self) -> MatchedNode<'tree> {
         self.peeked.take().unwrap()
     }
+
+    /// Narrows the cursor to `range` and drops the matches which end before its start.
+    fn seek<Loader: QueryLoader<'a>>(
+        &mut self,
+        range: Range,
+        source: RopeSlice<'_>,
+        loader: &Loader,
+    ) {
+        let start = range.start;
+        if let Some(cursor) = &mut self.cursor {
+            cursor.set_byte_range(range);
+        }
+        while self
+            .peek(source, loader)
+            .is_some_and(|matched_node| matched_node.node.end_byte() <= start)
+        {
+            self.consume();
+        }
+    }
 }
 
 struct ActiveLayer<'a, 'tree, S> {
@@ -85,6 +114,23 @@ struct ActiveLayer<'a, 'tree, S> {
     injections: Peekable<slice::Iter<'a, Injection>>,
 }
 
+impl<'a, S> ActiveLayer<'a, '_, S> {
+    fn seek<Loader: QueryLoader<'a>>(
+        &mut self,
+        range: Range,
+        source: RopeSlice<'_>,
+        loader: &Loader,
+    ) {
+        let start = range.start;
+        self.query_iter.seek(range, source, loader);
+        while self
+            .injections
+            .next_if(|injection| injection.range.end < start)
+            .is_some()
+        {}
+    }
+}
+
 // data only needed when entering and exiting injections
 // separate struck to keep the QueryIter reasonably small
 struct QueryIterLayerManager<'a, 'tree, Loader, S> {
@@ -96,6 +142,7 @@ struct QueryIterLayerManager<'a, 'tree, Loader, S> {
     active_injections: Vec<Injection>,
     /// Layers which are known to have no more captures.
     finished_layers: HashSet<Layer>,
+    capture_filter: Option<Box<CaptureFilter<'a>>>,
 }
 
 impl<'a, 'tree: 'a, Loader, S> QueryIterLayerManager<'a, 'tree, Loader, S>
@@ -112,18 +159,25 @@ where
                 let injection_start = layer
                     .injections
                     .partition_point(|child| child.range.end < start_point);
-                let cursor = if self.finished_layers.contains(&injection.layer) {
+                let match_limit = self.loader.match_limit(layer.language);
+                let query = if self.finished_layers.contains(&injection.layer) {
                     // If the layer has no more captures, skip creating a cursor.
                     None
                 } else {
-                    self.loader
-                        .get_query(layer.language)
-                        .and_then(|query| Some((query, layer.tree()?.root_node())))
-                        .map(|(query, node)| {
-                            InactiveQueryCursor::new(self.range.clone(), TREE_SITTER_MATCH_LIMIT)
-                                .execute_query(query, &node, RopeInput::new(self.src))
-                        })
+                    self.loader.get_query(layer.language)
                 };
+                let enabled_captures = query
+                    .zip(self.capture_filter.as_deref())
+                    .map(|(query, filter)| enabled_captures(query, filter));
+                let cursor = query
+                    .and_then(|query| Some((query, layer.tree()?.root_node())))
+                    .map(|(query, node)| {
+                        InactiveQueryCursor::new(self.range.clone(), match_limit).execute_query(
+                            query,
+                            &node,
+                            RopeInput::new(self.src),
+                        )
+                    });
                 Box::new(ActiveLayer {
                     state: S::default(),
                     query_iter: LayerQueryIter {
@@ -131,6 +185,7 @@ where
                         cursor,
                         peeked: None,
                         scope_cursor: layer.locals.scope_cursor(self.range.start),
+                        enabled_captures,
                     },
                     injections: layer.injections[injection_start..].iter().peekable(),
                 })
@@ -138,6 +193,14 @@ where
     }
 }
 
+/// The predicate of [`QueryIter::filter_captures`].
+type CaptureFilter<'a> = dyn Fn(&str) -> bool + 'a;
+
+/// Evaluates the filter of [`QueryIter::filter_captures`] for each capture of `query`.
+fn enabled_captures(query: &Query, filter: &CaptureFilter) -> Box<[bool]> {
+    query.captures().map(|(_, name)| filter(name)).collect()
+}
+
 pub struct QueryIter<'a, 'tree, Loader: QueryLoader<'a>, LayerState = ()> {
     layer_manager: Box<QueryIterLayerManager<'a, 'tree, Loader, LayerState>>,
     current_layer: Box<ActiveLayer<'a, 'tree, LayerState>>,
@@ -157,11 +220,11 @@ where
     ) -> Self {
         let start = match range.start_bound() {
             std::ops::Bound::Included(&i) => i,
-            std::ops::Bound::Excluded(&i) => i + 1,
+            std::ops::Bound::Excluded(&i) => i.saturating_add(1),
             std::ops::Bound::Unbounded => 0,
         };
         let end = match range.end_bound() {
-            std::ops::Bound::Included(&i) => i + 1,
+            std::ops::Bound::Included(&i) => i.saturating_add(1),
             std::ops::Bound::Excluded(&i) => i,
             std::ops::Bound::Unbounded => src.len_bytes() as u32,
         };
@@ -173,15 +236,17 @@ where
             layer: syntax.root,
             matched_node_range: node.byte_range(),
         };
+        let capacity = syntax.config.query_iter_capacity;
         let mut layer_manager = Box::new(QueryIterLayerManager {
             range,
             loader,
             src,
             syntax,
             // TODO: reuse allocations with an allocation pool
-            active_layers: HashMap::with_capacity(8),
-            active_injections: Vec::with_capacity(8),
-            finished_layers: HashSet::with_capacity(8),
+            active_layers: HashMap::with_capacity(capacity),
+            active_injections: Vec::with_capacity(capacity),
+            finished_layers: HashSet::with_capacity(capacity),
+            capture_filter: None,
         });
         Self {
             current_layer: layer_manager.init_layer(injection.clone()),
@@ -239,6 +304,65 @@ where
         }
     }
 
+    /// Only yields the captures whose name satisfies `predicate`, for example
+    /// `|name| name.starts_with("fold")`.
+    ///
+    /// Other captures are skipped before the predicates of their match are checked, which makes
+    /// iterating over a few captures of a large query cheaper than filtering the events. The
+    /// query cursors still match the skipped patterns though, so queries which are only used
+    /// for some captures should rather be split. Captures are filtered by name since the same
+    /// capture has a different index in the query of each language. `predicate` is called
+    /// once per capture when a layer is entered.
+    ///
+    /// This should be called before the iterator is advanced: matches which were already
+    /// peeked are still yielded.
+    pub fn filter_captures(&mut self, predicate: impl Fn(&str) -> bool + 'a) {
+        let manager = &mut *self.layer_manager;
+        let layers = manager
+            .active_layers
+            .values_mut()
+            .chain([&mut self.current_layer]);
+        for layer in layers {
+            let query_iter = &mut layer.query_iter;
+            query_iter.enabled_captures = manager
+                .loader
+                .get_query(query_iter.language)
+                .map(|query| enabled_captures(query, &predicate));
+        }
+        manager.capture_filter = Some(Box::new(predicate));
+    }
+
+    /// Skips ahead to `byte`, dropping the remaining events before it.
+    ///
+    /// Afterwards the iterator continues like a `QueryIter` created for a range starting at
+    /// `byte`: matches and injections which end before `byte` are skipped while the ones
+    /// spanning `byte` are kept. Instead of recreating the query cursors of the layers which were
+    /// already entered, their byte ranges are narrowed. Injections which are left while seeking
+    /// don't produce [`QueryIterEvent::ExitInjection`] events, so their layer state is dropped.
+    ///
+    /// Seeking backwards is not supported: seeking to a byte before the next event has no
+    /// effect.
+    pub fn seek_to(&mut self, byte: u32) {
+        let byte = byte.min(self.layer_manager.range.end);
+        if byte <= self.layer_manager.range.start {
+            return;
+        }
+        self.layer_manager.range.start = byte;
+        let range = self.layer_manager.range.clone();
+        loop {
+            let manager = &*self.layer_manager;
+            self.current_layer
+                .seek(range.clone(), manager.src, &manager.loader);
+            if self.current_injection.range.end >= byte || self.exit_injection().is_none() {
+                break;
+            }
+        }
+        let manager = &mut *self.layer_manager;
+        for layer in manager.active_layers.values_mut() {
+            layer.seek(range.clone(), manager.src, &manager.loader);
+        }
+    }
+
     fn enter_injection(&mut self, injection: Injection) {
         let active_layer = self.layer_manager.init_layer(injection.clone());
         let old_injection = replace(&mut self.current_injection, injection);
@@ -357,6 +481,16 @@ impl<S> QueryIterEvent<'_, S> {
 pub trait QueryLoader<'a> {
     fn get_query(&mut self, lang: Language) -> Option<&'a Query>;
 
+    /// The maximum number of in-progress matches for the query cursor of the given language.
+    fn match_limit(&self, _lang: Language) -> u32 {
+        TREE_SITTER_MATCH_LIMIT
+    }
+
+    /// Checks any predicates of the match which the query cursor does not evaluate itself.
+    ///
+    /// The standard text predicates like `#eq?`, `#match?` and `#any-of?` are already checked
+    /// before a match is yielded (see [`tree_sitter::query::predicates::Predicates`]) so
+    /// implementations only need to handle predicates specific to the query type.
     fn are_predicates_satisfied(
         &self,
         _lang: Language,
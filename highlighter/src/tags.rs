@@ -0,0 +1,190 @@
+use std::ops::Range;
+
+use hashbrown::{HashMap, HashSet};
+use ropey::RopeSlice;
+use tree_sitter::query::{self, InvalidPredicateError, UserPredicate};
+use tree_sitter::{Capture, Grammar, Query};
+
+use crate::config::clamp_match_limit;
+use crate::query_iter::{QueryIter, QueryIterEvent, QueryLoader};
+use crate::{Language, Layer, Syntax, TREE_SITTER_MATCH_LIMIT};
+
+/// The kind of a [`Tag`], for example `function` for `@definition.function` or `call` for
+/// `@reference.call`.
+///
+/// Use [`TagsQuery::kind_name`] to get the name of the kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TagKind(u32);
+
+impl TagKind {
+    pub fn idx(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A definition or reference of a symbol found by a [`TagsQuery`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub kind: TagKind,
+    /// The range of the node captured with `@name`.
+    pub name_range: Range<u32>,
+    /// The range of the node captured with `@definition.<kind>` or `@reference.<kind>`.
+    pub node_range: Range<u32>,
+    pub is_definition: bool,
+}
+
+/// A query following the `tags.scm` conventions used for code navigation.
+///
+/// Each pattern captures the whole definition or reference with a `@definition.<kind>` or
+/// `@reference.<kind>` capture and the symbol's name with a `@name` capture. Other captures like
+/// `@doc` are ignored.
+///
+/// The `#strip!` and `#select-adjacent!` predicates, which are used to post-process `@doc`
+/// captures, are accepted but ignored.
+#[derive(Debug)]
+pub struct TagsQuery {
+    pub query: Query,
+    grammar: Grammar,
+    name_capture: Option<Capture>,
+    /// Maps captures of the query to the kind of tag they define and whether the tag is a
+    /// definition.
+    tag_captures: HashMap<Capture, (TagKind, bool)>,
+    kinds: Vec<Box<str>>,
+    match_limit: u32,
+}
+
+impl TagsQuery {
+    pub fn new(grammar: Grammar, source: &str) -> Result<Self, query::ParseError> {
+        let query = Query::new(grammar, source, |_pattern, predicate| match predicate {
+            UserPredicate::Other(predicate)
+                if matches!(predicate.name(), "strip!" | "select-adjacent!") =>
+            {
+                Ok(())
+            }
+            _ => Err(InvalidPredicateError::unknown(predicate)),
+        })?;
+
+        let mut kinds: Vec<Box<str>> = Vec::new();
+        let mut tag_captures = HashMap::new();
+        for (capture, name) in query.captures() {
+            let (kind, is_definition) = if let Some(kind) = name.strip_prefix("definition.") {
+                (kind, true)
+            } else if let Some(kind) = name.strip_prefix("reference.") {
+                (kind, false)
+            } else {
+                continue;
+            };
+            let idx = kinds
+                .iter()
+                .position(|existing| &**existing == kind)
+                .unwrap_or_else(|| {
+                    kinds.push(kind.into());
+                    kinds.len() - 1
+                });
+            tag_captures.insert(capture, (TagKind(idx as u32), is_definition));
+        }
+
+        Ok(Self {
+            name_capture: query.get_capture("name"),
+            query,
+            grammar,
+            tag_captures,
+            kinds,
+            match_limit: TREE_SITTER_MATCH_LIMIT,
+        })
+    }
+
+    /// Sets the match limit of the query cursors running this query, see
+    /// [`LanguageConfig::with_match_limit`](crate::config::LanguageConfig::with_match_limit).
+    pub fn with_match_limit(mut self, match_limit: u32) -> Self {
+        self.match_limit = clamp_match_limit(match_limit);
+        self
+    }
+
+    pub fn match_limit(&self) -> u32 {
+        self.match_limit
+    }
+
+    /// Returns the name of the given kind, for example `function`.
+    pub fn kind_name(&self, kind: TagKind) -> &str {
+        &self.kinds[kind.idx()]
+    }
+
+    /// Finds all tags in the syntax tree, sorted by the start of their `node_range`.
+    ///
+    /// The query is run on every layer parsed with the same grammar as this query, including
+    /// injected layers.
+    pub fn tags(&self, syntax: &Syntax, src: RopeSlice<'_>) -> Vec<Tag> {
+        let Some(name_capture) = self.name_capture else {
+            return Vec::new();
+        };
+        let languages: HashSet<Language> = syntax
+            .layers
+            .iter()
+            .filter(|(_, layer)| {
+                layer
+                    .tree()
+                    .is_some_and(|tree| tree.root_node().grammar() == self.grammar)
+            })
+            .map(|(_, layer)| layer.language)
+            .collect();
+
+        // A pattern's `@name` and `@definition.<kind>` captures are yielded as separate
+        // events so they are collected per match and combined afterwards.
+        #[derive(Default)]
+        struct PartialTag {
+            name_range: Option<Range<u32>>,
+            node: Option<(TagKind, bool, Range<u32>)>,
+        }
+        let mut matches: HashMap<(Layer, u32), PartialTag> = HashMap::new();
+        let loader = TagsQueryLoader {
+            query: self,
+            languages: &languages,
+        };
+        let mut iter = QueryIter::<_, ()>::new(syntax, src, loader, ..);
+        while let Some(event) = iter.next() {
+            let QueryIterEvent::Match(node) = event else {
+                continue;
+            };
+            let layer = iter.current_layer();
+            if node.capture == name_capture {
+                let tag = matches.entry((layer, node.match_id)).or_default();
+                tag.name_range = Some(node.node.byte_range());
+            } else if let Some(&(kind, is_definition)) = self.tag_captures.get(&node.capture) {
+                let tag = matches.entry((layer, node.match_id)).or_default();
+                tag.node = Some((kind, is_definition, node.node.byte_range()));
+            }
+        }
+
+        let mut tags: Vec<_> = matches
+            .into_values()
+            .filter_map(|tag| {
+                let (kind, is_definition, node_range) = tag.node?;
+                Some(Tag {
+                    kind,
+                    name_range: tag.name_range?,
+                    node_range,
+                    is_definition,
+                })
+            })
+            .collect();
+        tags.sort_by_key(|tag| (tag.node_range.start, tag.name_range.start));
+        tags
+    }
+}
+
+struct TagsQueryLoader<'a> {
+    query: &'a TagsQuery,
+    /// The languages which are parsed with the grammar of the query.
+    languages: &'a HashSet<Language>,
+}
+
+impl<'a> QueryLoader<'a> for TagsQueryLoader<'a> {
+    fn get_query(&mut self, lang: Language) -> Option<&'a Query> {
+        self.languages.contains(&lang).then_some(&self.query.query)
+    }
+
+    fn match_limit(&self, _lang: Language) -> u32 {
+        self.query.match_limit
+    }
+}
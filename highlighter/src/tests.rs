@@ -1,19 +1,35 @@
 use std::borrow::Cow;
 use std::cell::RefCell;
 use std::fs;
+use std::iter;
 use std::path::{Path, PathBuf};
 
 use indexmap::{IndexMap, IndexSet};
 use once_cell::sync::Lazy;
 use once_cell::unsync::OnceCell;
 use skidder::Repo;
-use tree_sitter::{Grammar, InputEdit, Point};
+use tree_sitter::query::predicates::Predicates;
+use tree_sitter::query::{InvalidPredicateError, QueryErrorKind};
+use tree_sitter::{
+    GrammarCache, InactiveQueryCursor, InputEdit, InvalidRangeKind, InvalidRangesError,
+    LoadedGrammar, Parser, Point, Query,
+};
 
-use crate::config::{LanguageConfig, LanguageLoader};
-use crate::fixtures::{check_highlighter_fixture, check_injection_fixture};
-use crate::highlighter::Highlight;
+use crate::config::{
+    read_query, LanguageConfig, LanguageLoader, QueryOverride, QueryOverrides, QuerySource,
+};
+use crate::fixtures::{
+    check_highlighter_fixture, check_injection_fixture, check_text_object_fixture,
+};
+use crate::highlighter::{
+    highlight_to_lines, Highlight, HighlightEvent, HighlightQuery, Highlighter,
+};
 use crate::injections_query::InjectionLanguageMarker;
-use crate::{Language, Layer, Syntax};
+use crate::query_iter::{QueryIter, QueryIterEvent};
+use crate::text_object::{CapturedNode, TextObjectQuery};
+use crate::{
+    ranges_intersect, Language, LanguageMatchTable, Layer, LayerChanges, Syntax, SyntaxConfig,
+};
 
 const PARSE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
 
@@ -26,6 +42,8 @@ static GRAMMARS: Lazy<Vec<PathBuf>> = Lazy::new(|| {
     grammars
 });
 
+static GRAMMAR_CACHE: Lazy<GrammarCache> = Lazy::new(GrammarCache::new);
+
 fn skidder_config() -> skidder::Config {
     skidder::Config {
         repos: vec![Repo::Local {
@@ -34,21 +52,22 @@ fn skidder_config() -> skidder::Config {
         }],
         index: PathBuf::new(),
         verbose: true,
+        verify_checksums: true,
+        queries_path: None,
     }
 }
 
-#[derive(Debug, Clone, Default)]
-struct Overwrites {
-    highlights: Option<String>,
-    locals: Option<String>,
-    injections: Option<String>,
-}
-
-fn get_grammar(lang_name: &str, overwrites: &Overwrites) -> LanguageConfig {
+fn get_grammar(lang_name: &str, overrides: &QueryOverrides) -> LanguageConfig {
     let skidder_config = skidder_config();
     let grammar_dir = skidder_config.grammar_dir(lang_name).unwrap();
     let parser_path = skidder::build_grammar(&skidder_config, lang_name, false).unwrap();
-    let grammar = unsafe { Grammar::new(lang_name, &parser_path).unwrap() };
+    // The cache is never dropped so the grammar can't outlive the library.
+    let grammar = unsafe {
+        GRAMMAR_CACHE
+            .load(lang_name, &parser_path)
+            .unwrap()
+            .grammar()
+    };
     let highlights_query_path = grammar_dir.join("highlights.scm");
     let injections_query_path = grammar_dir.join("injections.scm");
     if !injections_query_path.exists() {
@@ -58,35 +77,36 @@ fn get_grammar(lang_name: &str, overwrites: &Overwrites) -> LanguageConfig {
     if !locals_query_path.exists() {
         println!("\x1b[36mskipping loading of locals for {lang_name:?} since {locals_query_path:?} does not exist\x1b[0m");
     }
-    LanguageConfig::new(
+    let highlights = match &overrides.highlights {
+        Some(QueryOverride::Replace(_)) => String::new(),
+        _ => fs::read_to_string(&highlights_query_path)
+            .map_err(|err| {
+                format!(
+                    "failed to read highlights in {}: {err}",
+                    highlights_query_path.display()
+                )
+            })
+            .unwrap(),
+    };
+    let mut config = LanguageConfig::new_with_overrides(
         grammar,
-        &overwrites.highlights.clone().unwrap_or_else(|| {
-            fs::read_to_string(&highlights_query_path)
-                .map_err(|err| {
-                    format!(
-                        "failed to read highlights in {}: {err}",
-                        highlights_query_path.display()
-                    )
-                })
-                .unwrap()
-        }),
-        &overwrites
-            .injections
-            .clone()
-            .unwrap_or_else(|| fs::read_to_string(&injections_query_path).unwrap_or_default()),
-        &overwrites
-            .locals
-            .clone()
-            .unwrap_or_else(|| fs::read_to_string(&locals_query_path).unwrap_or_default()),
+        &highlights,
+        &fs::read_to_string(&injections_query_path).unwrap_or_default(),
+        &fs::read_to_string(&locals_query_path).unwrap_or_default(),
+        overrides,
     )
-    .unwrap()
+    .unwrap();
+    if let Ok(text_objects) = fs::read_to_string(grammar_dir.join("textobjects.scm")) {
+        config.text_object_query = Some(TextObjectQuery::new(grammar, &text_objects).unwrap());
+    }
+    config
 }
 
 #[derive(Debug)]
 struct TestLanguageLoader {
     languages: IndexMap<String, Language>,
     lang_config: Box<[OnceCell<LanguageConfig>]>,
-    overwrites: Box<[Overwrites]>,
+    overrides: Box<[QueryOverrides]>,
     test_theme: RefCell<IndexSet<String>>,
 }
 
@@ -96,7 +116,7 @@ impl TestLanguageLoader {
 
         Self {
             lang_config: (0..grammars.len()).map(|_| OnceCell::new()).collect(),
-            overwrites: vec![Overwrites::default(); grammars.len()].into_boxed_slice(),
+            overrides: vec![QueryOverrides::default(); grammars.len()].into_boxed_slice(),
             test_theme: RefCell::default(),
             languages: grammars
                 .iter()
@@ -119,38 +139,25 @@ impl TestLanguageLoader {
     #[allow(dead_code)]
     fn overwrite_injections(&mut self, lang: &str, content: String) {
         let lang = self.get(lang);
-        self.overwrites[lang.idx()].injections = Some(content);
+        self.overrides[lang.idx()].injections = Some(QueryOverride::Replace(content));
         self.lang_config[lang.idx()] = OnceCell::new();
     }
 
     fn overwrite_highlights(&mut self, lang: &str, content: String) {
         let lang = self.get(lang);
-        self.overwrites[lang.idx()].highlights = Some(content);
+        self.overrides[lang.idx()].highlights = Some(QueryOverride::Replace(content));
         self.lang_config[lang.idx()] = OnceCell::new();
     }
 
     fn shadow_injections(&mut self, lang: &str, content: &str) {
         let lang = self.get(lang);
-        let skidder_config = skidder_config();
-        let grammar = self.languages.get_index(lang.idx()).unwrap().0;
-        let grammar_dir = skidder_config.grammar_dir(grammar).unwrap();
-        let mut injections =
-            fs::read_to_string(grammar_dir.join("injections.scm")).unwrap_or_default();
-        injections.push('\n');
-        injections.push_str(content);
-        self.overwrites[lang.idx()].injections = Some(injections);
+        self.overrides[lang.idx()].injections = Some(QueryOverride::Append(content.to_owned()));
         self.lang_config[lang.idx()] = OnceCell::new();
     }
 
     fn shadow_highlights(&mut self, lang: &str, content: &str) {
         let lang = self.get(lang);
-        let skidder_config = skidder_config();
-        let grammar = self.languages.get_index(lang.idx()).unwrap().0;
-        let grammar_dir = skidder_config.grammar_dir(grammar).unwrap();
-        let mut highlights = fs::read_to_string(grammar_dir.join("highlights.scm")).unwrap();
-        highlights.push('\n');
-        highlights.push_str(content);
-        self.overwrites[lang.idx()].highlights = Some(highlights);
+        self.overrides[lang.idx()].highlights = Some(QueryOverride::Append(content.to_owned()));
         self.lang_config[lang.idx()] = OnceCell::new();
     }
 }
@@ -163,15 +170,31 @@ impl LanguageLoader for TestLanguageLoader {
                 let name: Cow<str> = text.into();
                 self.languages.get(name.as_ref()).copied()
             }
-            _ => unimplemented!(),
+            InjectionLanguageMarker::Filename(_) | InjectionLanguageMarker::Shebang(_) => {
+                static MATCH_TABLE: Lazy<LanguageMatchTable> = Lazy::new(|| {
+                    let loader = TestLanguageLoader::new();
+                    LanguageMatchTable::new([
+                        (r"\.rs$", loader.get("rust")),
+                        ("^escript$", loader.get("erlang")),
+                    ])
+                    .unwrap()
+                });
+                MATCH_TABLE.language_for_marker(marker, |_| None)
+            }
         }
     }
 
+    fn language_name(&self, lang: Language) -> Option<&str> {
+        self.languages
+            .get_index(lang.idx())
+            .map(|(name, _)| name.as_str())
+    }
+
     fn get_config(&self, lang: Language) -> Option<&LanguageConfig> {
         let config = self.lang_config[lang.idx()].get_or_init(|| {
             let config = get_grammar(
                 self.languages.get_index(lang.idx()).unwrap().0,
-                &self.overwrites[lang.idx()],
+                &self.overrides[lang.idx()],
             );
             let mut theme = self.test_theme.borrow_mut();
             config.configure(|scope| {
@@ -214,14 +237,13 @@ fn highlight_fixture(loader: &TestLanguageLoader, fixture: impl AsRef<Path>) {
 fn injection_fixture(loader: &TestLanguageLoader, fixture: impl AsRef<Path>) {
     let path = Path::new("../fixtures").join(fixture);
     let lang = lang_for_path(&path, loader);
-    check_injection_fixture(
-        path,
-        "// ",
-        lang,
-        loader,
-        |lang| loader.languages.get_index(lang.idx()).unwrap().0.clone(),
-        |_| ..,
-    )
+    check_injection_fixture(path, "// ", lang, loader, |_| ..)
+}
+
+fn text_object_fixture(loader: &TestLanguageLoader, fixture: impl AsRef<Path>, capture: &str) {
+    let path = Path::new("../fixtures").join(fixture);
+    let lang = lang_for_path(&path, loader);
+    check_text_object_fixture(path, "// ", capture, lang, loader)
 }
 
 #[test]
@@ -453,6 +475,12 @@ fn rust_nested_vec() {
     injection_fixture(&loader, "injections/nested_vec.rs");
 }
 
+#[test]
+fn shebang_injection() {
+    let loader = TestLanguageLoader::new();
+    injection_fixture(&loader, "injections/shebang_codefence.md");
+}
+
 #[test]
 fn edit_remove_and_add_injection_layer() {
     let loader = TestLanguageLoader::new();
@@ -546,6 +574,40 @@ fn css_parent_child_highlight_precedence() {
     highlight_fixture(&loader, "highlighter/parent_child_highlight_precedence.css");
 }
 
+#[test]
+fn highlight_priority() {
+    let mut loader = TestLanguageLoader::new();
+    // Without the priorities the later pattern of each pair would win.
+    loader.overwrite_highlights(
+        "rust",
+        r#"
+((identifier) @function
+ (#set! priority 110))
+(identifier) @variable
+
+(primitive_type) @type
+((primitive_type) @type.builtin
+ (#set! priority 90))
+"#
+        .to_string(),
+    );
+    highlight_fixture(&loader, "highlighter/priority.rs");
+
+    // When all highlights are kept the highest priority is innermost.
+    let source = "fn add() {}";
+    let syntax = Syntax::new(source.into(), loader.get("rust"), PARSE_TIMEOUT, &loader).unwrap();
+    let mut highlighter = Highlighter::new(&syntax, source.into(), &loader, ..);
+    highlighter.set_keep_all_highlights(true);
+    while highlighter.next_event_offset() <= 3 {
+        highlighter.advance();
+    }
+    let highlights: Vec<_> = highlighter
+        .active_highlights()
+        .map(|highlight| loader.test_theme.borrow()[highlight.idx()].clone())
+        .collect();
+    assert_eq!(highlights, ["variable", "function"]);
+}
+
 #[test]
 fn edoc_code_combined_injection() {
     let loader = TestLanguageLoader::new();
@@ -563,3 +625,2596 @@ fn edoc_code_combined_injection_in_markdown() {
         "highlighter/edoc_code_combined_injection_in_markdown.md",
     );
 }
+
+#[test]
+fn node_edit() {
+    let loader = TestLanguageLoader::new();
+    let before_text = "fn a() {}\nfn b() {}";
+    let after_text = "fn a() { 1 }\nfn b() {}";
+    let edit = InputEdit {
+        start_byte: 8,
+        old_end_byte: 8,
+        new_end_byte: 11,
+        start_point: Point { row: 0, col: 8 },
+        old_end_point: Point { row: 0, col: 8 },
+        new_end_point: Point { row: 0, col: 11 },
+    };
+    let mut syntax = Syntax::new(
+        before_text.into(),
+        loader.get("rust"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    // Hold onto a node from a copy of the old tree across the edit.
+    let old_tree = syntax.tree().clone();
+    let mut node = old_tree.root_node().named_child(1).unwrap();
+    assert_eq!(node.byte_range(), 10..19);
+
+    syntax
+        .update(after_text.into(), PARSE_TIMEOUT, &[edit], &loader)
+        .unwrap();
+    node.edit(&edit);
+
+    let new_node = syntax.tree().root_node().named_child(1).unwrap();
+    assert_eq!(new_node.kind(), "function_item");
+    assert_eq!(node.byte_range(), new_node.byte_range());
+}
+
+#[test]
+fn text_predicates() {
+    let loader = TestLanguageLoader::new();
+    let source = "let foo = 1; let bar = 2; let baz = 3;";
+    // Same length as `source` so that the node ranges stay valid.
+    let other_source = "let goo = 1; let qux = 2; let bad = 3;";
+    let syntax = Syntax::new(source.into(), loader.get("rust"), PARSE_TIMEOUT, &loader).unwrap();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let query = Query::new(
+        grammar,
+        r#"
+        ((identifier) @match (#match? @match "^f"))
+        ((identifier) @any-of (#any-of? @any-of "bar" "qux"))
+        ((identifier) @not-match (#not-match? @not-match "z$"))
+        "#,
+        |_, _| Ok(()),
+    )
+    .unwrap();
+    let predicates = Predicates::new(&query);
+
+    let mut cursor = InactiveQueryCursor::new(0..u32::MAX, 16).execute_query(
+        &query,
+        &syntax.tree().root_node(),
+        ropey::RopeSlice::from(source),
+    );
+    let mut matches = Vec::new();
+    while let Some(mat) = cursor.next_match() {
+        assert!(predicates.has_predicates(mat.pattern()));
+        // Matches yielded by the cursor always satisfy the predicates for the cursor's input.
+        assert!(predicates.is_satisfied(&mat, ropey::RopeSlice::from(source)));
+        assert_eq!(mat.capture_count(), 1);
+        assert_eq!(
+            mat.satisfies_text_predicates(ropey::RopeSlice::from(other_source)),
+            predicates.is_satisfied(&mat, ropey::RopeSlice::from(other_source))
+        );
+        let (capture, node) = mat
+            .matched_nodes()
+            .map(|matched| (matched.capture, matched.node.clone()))
+            .next()
+            .unwrap();
+        matches.push((
+            query.capture_name(capture).to_owned(),
+            &source[node.byte_range().start as usize..node.byte_range().end as usize],
+            predicates.is_satisfied(&mat, ropey::RopeSlice::from(other_source)),
+        ));
+    }
+    assert_eq!(
+        matches,
+        [
+            ("match".to_owned(), "foo", false),
+            ("not-match".to_owned(), "foo", true),
+            ("any-of".to_owned(), "bar", true),
+            ("not-match".to_owned(), "bar", true),
+        ]
+    );
+}
+
+#[test]
+fn query_cursor_limits() {
+    let loader = TestLanguageLoader::new();
+    let source = "fn a() { let b = 1; }\nfn c() {}";
+    let syntax = Syntax::new(source.into(), loader.get("rust"), PARSE_TIMEOUT, &loader).unwrap();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let query = Query::new(grammar, "(identifier) @id", |_, _| Ok(())).unwrap();
+    let identifiers = |cursor: &mut tree_sitter::QueryCursor<'_, '_, _>| {
+        let mut identifiers = Vec::new();
+        while let Some(mat) = cursor.next_match() {
+            let range = mat.matched_node(0).node.byte_range();
+            identifiers.push(&source[range.start as usize..range.end as usize]);
+        }
+        identifiers
+    };
+
+    // Function names are children of the `function_item`s so they start at depth 2.
+    let mut cursor = InactiveQueryCursor::new(0..u32::MAX, 16);
+    cursor.set_max_start_depth(2);
+    let mut cursor = cursor.execute_query(
+        &query,
+        &syntax.tree().root_node(),
+        ropey::RopeSlice::from(source),
+    );
+    assert_eq!(identifiers(&mut cursor), ["a", "c"]);
+    drop(cursor);
+
+    // The depth limit is reset when the cursor is reused.
+    let mut cursor = InactiveQueryCursor::new(0..u32::MAX, 16).execute_query(
+        &query,
+        &syntax.tree().root_node(),
+        ropey::RopeSlice::from(source),
+    );
+    assert!(cursor.next_match().is_some());
+    // Narrow the range of the active cursor to the first function.
+    cursor.set_byte_range(0..21);
+    assert_eq!(identifiers(&mut cursor), ["b"]);
+}
+
+#[test]
+fn pattern_start_bytes() {
+    let loader = TestLanguageLoader::new();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let source = "(identifier) @a\n\n((string_literal) @b (#match? @b \"x\"))\n";
+    let query = Query::new(grammar, source, |_, _| Ok(())).unwrap();
+    assert_eq!(query.pattern_count(), 2);
+    let starts: Vec<_> = query
+        .patterns()
+        .map(|pattern| query.start_byte_for_pattern(pattern))
+        .collect();
+    assert_eq!(starts, [0, 17]);
+    assert!(source[starts[1] as usize..].starts_with("((string_literal)"));
+}
+
+#[test]
+fn pattern_rootedness() {
+    let loader = TestLanguageLoader::new();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let source = r#"
+        (function_item name: (identifier) @function)
+        ((line_comment)+ @comment)
+        ((attribute_item) @attr . (function_item) @function)
+    "#;
+    let query = Query::new(grammar, source, |_, _| Ok(())).unwrap();
+    let patterns: Vec<_> = query
+        .patterns()
+        .map(|pattern| {
+            (
+                query.is_pattern_rooted(pattern),
+                query.is_pattern_non_local(pattern),
+            )
+        })
+        .collect();
+    // Repetitions and sibling sequences have multiple root nodes.
+    assert_eq!(patterns, [(true, false), (false, true), (false, true)]);
+}
+
+#[test]
+fn property_settings() {
+    let loader = TestLanguageLoader::new();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let source = r#"
+        ((line_comment) @injection.content
+         (#set! injection.language "comment")
+         (#set! injection.combined))
+        (function_item name: (identifier) @function)
+    "#;
+    let query = Query::new(grammar, source, |_, _| Ok(())).unwrap();
+    let properties: Vec<Vec<_>> = query
+        .patterns()
+        .map(|pattern| query.property_settings(pattern).collect())
+        .collect();
+    assert_eq!(
+        properties,
+        [
+            vec![
+                ("injection.language", Some("comment")),
+                ("injection.combined", None)
+            ],
+            vec![],
+        ]
+    );
+    let function = query.get_capture("function").unwrap();
+    assert_eq!(query.capture_name(function), "function");
+}
+
+#[test]
+fn query_errors() {
+    let loader = TestLanguageLoader::new();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let error = |source: &str| Query::new(grammar, source, |_, _| Ok(())).unwrap_err();
+
+    let err = error("(identifier) @a\n(not_a_node) @b\n");
+    assert_eq!(err.kind(), QueryErrorKind::NodeType);
+    let location = err.location().unwrap();
+    assert_eq!(
+        (
+            location.byte_offset,
+            location.line,
+            location.column,
+            location.len
+        ),
+        (17, 1, 1, 10)
+    );
+    assert_eq!(
+        err.to_string(),
+        "invalid node type \"not_a_node\"\n  --> 2:2\n   |\n 1 | (identifier) @a\n 2 | (not_a_node) @b\n   |  ^^^^^^^^^^\n   |\n"
+    );
+
+    assert_eq!(
+        error("(function_item nme: (identifier))").kind(),
+        QueryErrorKind::Field
+    );
+    assert_eq!(error("(identifier").kind(), QueryErrorKind::Syntax);
+    assert_eq!(
+        error("((identifier) @a (#eq? @b \"x\"))").kind(),
+        QueryErrorKind::Capture
+    );
+    assert_eq!(
+        Query::new(grammar, "((identifier) @a (#foo! @a))", |_, predicate| {
+            Err(InvalidPredicateError::unknown(predicate))
+        })
+        .unwrap_err()
+        .kind(),
+        QueryErrorKind::Predicate
+    );
+}
+
+#[test]
+fn query_error_sources() {
+    let loader = TestLanguageLoader::new();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let highlights = "(identifier) @variable\n";
+    let error = |highlights: &str, injections: &str, locals: &str| {
+        LanguageConfig::new(grammar, highlights, injections, locals).unwrap_err()
+    };
+
+    let err = error("(not_a_node) @variable", "", "");
+    assert_eq!(err.source, QuerySource::Highlights);
+    assert_eq!(err.error.kind(), QueryErrorKind::NodeType);
+    let err = error(highlights, "(not_a_node) @injection.content", "");
+    assert_eq!(err.source, QuerySource::Injections);
+    let err = error(
+        highlights,
+        "",
+        "(block) @local.scope\n(not_a_node) @local.scope",
+    );
+    assert_eq!(err.source, QuerySource::Locals);
+    assert_eq!(err.error.location().unwrap().line, 1);
+
+    // The highlights query is concatenated with the locals query. Errors in the locals part are
+    // relative to the locals query.
+    let err = HighlightQuery::new(
+        grammar,
+        highlights,
+        "(block) @local.scope\n(not_a_node) @local.scope",
+        false,
+    )
+    .unwrap_err();
+    assert_eq!(err.source, QuerySource::Locals);
+    let location = err.error.location().unwrap();
+    assert_eq!(
+        (location.byte_offset, location.line, location.column),
+        (22, 1, 1)
+    );
+    assert!(err
+        .to_string()
+        .starts_with("invalid locals query: invalid node type"));
+}
+
+#[test]
+fn reconfigure() {
+    let config = get_grammar("rust", &QueryOverrides::default());
+    let theme = |parameter: u32| {
+        move |scope: &str| match scope {
+            "keyword" => Some(Highlight::new(0)),
+            "variable.parameter" => Some(Highlight::new(parameter)),
+            _ => None,
+        }
+    };
+    assert!(config.configure(theme(1)));
+    // Configuring the same theme again is a no-op.
+    assert!(!config.configure(theme(1)));
+    // `variable.parameter` is used by both the highlights and the locals query.
+    assert!(config.configure(theme(2)));
+    assert!(!config.configure(theme(2)));
+    assert!(config.configure(|_| None));
+    assert!(!config.configure(|_| None));
+}
+
+#[cfg(feature = "diff")]
+#[test]
+fn update_from_diff() {
+    // The replaced range of `old` and the inserted text of each edit.
+    let edits = |old: &str, new: &str| {
+        let mut offset = 0;
+        crate::diff::compute_edits(old.into(), new.into())
+            .into_iter()
+            .map(|edit| {
+                let start = (edit.start_byte as i32 + offset) as usize;
+                let end = (edit.new_end_byte as i32 + offset) as usize;
+                offset += edit.new_end_byte as i32 - edit.old_end_byte as i32;
+                (
+                    edit.start_byte..edit.old_end_byte,
+                    new[start..end].to_owned(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(edits("a\nb\nc\n", "a\nb\nc\n"), []);
+    assert_eq!(
+        edits("a\nb\nc\n", "a\nxy\nc\n"),
+        [(2..4, "xy\n".to_owned())]
+    );
+    // Both changed lines are separate edits.
+    assert_eq!(
+        edits("a\nb\nc\nd\n", "x\nyz\nb\nc\nd!\n"),
+        [(0..2, "x\nyz\n".to_owned()), (6..8, "d!\n".to_owned())]
+    );
+
+    let loader = TestLanguageLoader::new();
+    let old = "# Title\n\n```rust\nfn a() {}\n```\n\ntext\n\n```rust\nlet x = 1;\n```\n";
+    let new = "# Title\n\n```rust\nfn b() -> u8 { 1 }\n```\n\ntext\n\n```rust\nlet x = \"s\";\n```\n\n```rust\nstruct S;\n```\n";
+    let mut syntax =
+        Syntax::new(old.into(), loader.get("markdown"), PARSE_TIMEOUT, &loader).unwrap();
+    syntax
+        .update_from_diff(old.into(), new.into(), PARSE_TIMEOUT, &loader)
+        .unwrap();
+    let fresh = Syntax::new(new.into(), loader.get("markdown"), PARSE_TIMEOUT, &loader).unwrap();
+    assert_eq!(
+        highlight_to_lines(&syntax, new.into(), &loader, ..),
+        highlight_to_lines(&fresh, new.into(), &loader, ..)
+    );
+    assert_eq!(syntax.layout().layers.len(), fresh.layout().layers.len());
+}
+
+#[test]
+fn reparse() {
+    let loader = TestLanguageLoader::new();
+    let old = "```rust\nfn a() {}\n```\n\n```css\na { color: red; }\n```\n";
+    let new = "# Rewritten\n\n```rust\nlet x = \"s\";\n```\n\n```rust\nstruct S;\n```\n";
+    let mut syntax =
+        Syntax::new(old.into(), loader.get("markdown"), PARSE_TIMEOUT, &loader).unwrap();
+    syntax.reparse(new.into(), PARSE_TIMEOUT, &loader).unwrap();
+    let fresh = Syntax::new(new.into(), loader.get("markdown"), PARSE_TIMEOUT, &loader).unwrap();
+    assert_eq!(
+        highlight_to_lines(&syntax, new.into(), &loader, ..),
+        highlight_to_lines(&fresh, new.into(), &loader, ..)
+    );
+    let languages = |syntax: &Syntax| {
+        let mut languages: Vec<_> = syntax
+            .layout()
+            .layers
+            .iter()
+            .map(|layer| layer.language)
+            .collect();
+        languages.sort_by_key(|language| language.idx());
+        languages
+    };
+    // The css layer is no longer injected.
+    assert_eq!(languages(&syntax), languages(&fresh));
+
+    // A failed reparse leaves the syntax tree of the previous source in place.
+    let before = syntax.debug_tree(&loader, true);
+    let large = "fn main() { let x = [1, 2, 3]; }\n".repeat(20_000);
+    let err = syntax
+        .reparse(
+            large.as_str().into(),
+            std::time::Duration::from_micros(1),
+            &loader,
+        )
+        .unwrap_err();
+    assert_eq!(err, crate::Error::Timeout);
+    assert_eq!(syntax.debug_tree(&loader, true), before);
+}
+
+#[test]
+fn language_at() {
+    let loader = TestLanguageLoader::new();
+    let source = "# Title\n\n```rust\nfn a() {}\n```\n";
+    let syntax = Syntax::new(
+        source.into(),
+        loader.get("markdown"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    assert_eq!(syntax.root_language(), loader.get("markdown"));
+    let code = source.find("fn").unwrap() as u32;
+    assert_eq!(syntax.language_at(code), loader.get("rust"));
+    assert_eq!(syntax.language_name_at(code, &loader), Some("rust"));
+    // The heading's text is injected but its marker is not.
+    assert_eq!(syntax.language_name_at(2, &loader), Some("markdown-inline"));
+    assert_eq!(syntax.language_name_at(0, &loader), Some("markdown"));
+}
+
+#[test]
+fn highlight_lines() {
+    let loader = TestLanguageLoader::new();
+    let source = "fn a() {}\n/* multi\nline */\nlet x = 1;";
+    let syntax = Syntax::new(source.into(), loader.get("rust"), PARSE_TIMEOUT, &loader).unwrap();
+    let lines = highlight_to_lines(&syntax, source.into(), &loader, ..);
+    let lines: Vec<Vec<_>> = lines
+        .into_iter()
+        .map(|line| {
+            line.into_iter()
+                .map(|(range, highlight)| {
+                    (
+                        &source[range.start as usize..range.end as usize],
+                        loader.test_theme.borrow()[highlight.idx()].clone(),
+                    )
+                })
+                .collect()
+        })
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            vec![
+                ("fn", "keyword.function".to_owned()),
+                ("a", "function".to_owned()),
+                // Adjacent spans with the same highlight are merged.
+                ("()", "punctuation.bracket".to_owned()),
+                ("{}", "punctuation.bracket".to_owned()),
+            ],
+            vec![("/* multi\n", "comment".to_owned())],
+            vec![("line */", "comment".to_owned())],
+            vec![
+                ("let", "keyword.storage".to_owned()),
+                ("x", "variable".to_owned()),
+                ("=", "operator".to_owned()),
+                ("1", "constant.numeric.integer".to_owned()),
+                (";", "punctuation.delimiter".to_owned()),
+            ],
+        ]
+    );
+
+    // Only the lines intersecting the range are returned.
+    let lines = highlight_to_lines(&syntax, source.into(), &loader, 13..20);
+    assert_eq!(lines.len(), 2);
+    assert_eq!(
+        lines[0].first().map(|(range, _)| range.clone()),
+        Some(13..19)
+    );
+
+    // An inclusive range ending at `u32::MAX` covers the rest of the document.
+    let lines = highlight_to_lines(&syntax, source.into(), &loader, 20..=u32::MAX);
+    assert_eq!(lines.len(), 2);
+    assert_eq!(
+        lines[1].first().map(|(range, _)| range.clone()),
+        Some(27..30)
+    );
+}
+
+#[test]
+fn highlights_at() {
+    let loader = TestLanguageLoader::new();
+    let source = "fn a() {}\nlet s = \"x\\n\";";
+    let syntax = Syntax::new(source.into(), loader.get("rust"), PARSE_TIMEOUT, &loader).unwrap();
+    let names = |highlights: Vec<Highlight>| -> Vec<String> {
+        highlights
+            .into_iter()
+            .map(|highlight| loader.test_theme.borrow()[highlight.idx()].clone())
+            .collect()
+    };
+
+    assert_eq!(
+        names(syntax.highlights_at(source.into(), &loader, 0)),
+        ["keyword.function"]
+    );
+    // The escape sequence is highlighted on top of the string.
+    let escape = source.find('\\').unwrap() as u32;
+    assert_eq!(
+        names(syntax.highlights_at(source.into(), &loader, escape)),
+        ["string", "constant.character.escape"]
+    );
+    assert!(syntax
+        .highlights_at(source.into(), &loader, source.len() as u32)
+        .is_empty());
+
+    // The stack matches the one reached by driving a highlighter over the whole document.
+    let mut highlighter = Highlighter::new(&syntax, source.into(), &loader, ..);
+    for pos in 0..source.len() as u32 {
+        while highlighter.next_event_offset() <= pos {
+            highlighter.advance();
+        }
+        let expected: Vec<_> = highlighter.active_highlights().collect();
+        assert_eq!(
+            syntax.highlights_at(source.into(), &loader, pos),
+            expected,
+            "highlights at byte {pos}"
+        );
+    }
+}
+
+#[test]
+fn highlights_in_range() {
+    let loader = TestLanguageLoader::new();
+    let source = "fn a() {}\nlet s = \"x\\n\";\n";
+    let syntax = Syntax::new(source.into(), loader.get("rust"), PARSE_TIMEOUT, &loader).unwrap();
+    let range = 3..source.len() as u32 - 3;
+    let spans = syntax.highlights_in_range(source.into(), &loader, range.clone());
+    assert!(spans
+        .windows(2)
+        .all(|spans| spans[0].0.end <= spans[1].0.start));
+    assert!(spans
+        .iter()
+        .all(|(span, _)| range.start <= span.start && span.end <= range.end));
+    for pos in range {
+        let expected = syntax.highlights_at(source.into(), &loader, pos);
+        let highlights = spans
+            .iter()
+            .find(|(span, _)| span.contains(&pos))
+            .map_or(&[][..], |(_, highlights)| highlights);
+        assert_eq!(highlights, expected, "highlights at byte {pos}");
+    }
+
+    let highlights = Highlighter::new(&syntax, source.into(), &loader, ..).collect_highlights();
+    assert!(highlights
+        .windows(2)
+        .all(|highlights| highlights[0].1.start <= highlights[1].1.start));
+}
+
+#[test]
+fn default_highlight() {
+    let loader = TestLanguageLoader::new();
+    let source = "# Title\n\n```rust\nfn a() { 1 }\n```\n\nSome text.\n";
+    let syntax = Syntax::new(
+        source.into(),
+        loader.get("markdown"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    let default = Highlight::new(1000);
+    let mut highlighter = Highlighter::new(&syntax, source.into(), &loader, ..);
+    let mut with_default =
+        Highlighter::new_with_default(&syntax, source.into(), &loader, .., default);
+    assert_eq!(
+        with_default.active_highlights().collect::<Vec<_>>(),
+        [default]
+    );
+    for pos in 0..source.len() as u32 {
+        while highlighter.next_event_offset() <= pos {
+            highlighter.advance();
+        }
+        while with_default.next_event_offset() <= pos {
+            let (event, highlights) = with_default.advance();
+            if event == HighlightEvent::Refresh {
+                assert_eq!(highlights.into_iter().next(), Some(default));
+            }
+        }
+        // The default highlight is at the base of the same stack.
+        let expected: Vec<_> = iter::once(default)
+            .chain(highlighter.active_highlights())
+            .collect();
+        assert_eq!(
+            with_default.active_highlights().collect::<Vec<_>>(),
+            expected,
+            "highlights at byte {pos}"
+        );
+    }
+    assert_eq!(with_default.next_event_offset(), u32::MAX);
+
+    let highlights = Highlighter::new_with_default(&syntax, source.into(), &loader, .., default)
+        .collect_highlights();
+    assert!(highlights.contains(&(default, 0..source.len() as u32)));
+}
+
+#[test]
+fn highlight_constructors() {
+    assert_eq!(Highlight::try_new(3), Some(Highlight::new(3)));
+    assert_eq!(
+        Highlight::try_new(Highlight::MAX).unwrap().get(),
+        Highlight::MAX
+    );
+    assert_eq!(Highlight::try_new(u32::MAX), None);
+    assert_eq!(Highlight::saturating_new(3).get(), 3);
+    assert_eq!(Highlight::saturating_new(u32::MAX).get(), Highlight::MAX);
+    assert_eq!(Highlight::try_from(3), Ok(Highlight::new(3)));
+    assert!(Highlight::try_from(u32::MAX).is_err());
+}
+
+#[test]
+fn keep_all_highlights() {
+    let loader = TestLanguageLoader::new();
+    let source = "let Foo = 1;";
+    let syntax = Syntax::new(source.into(), loader.get("rust"), PARSE_TIMEOUT, &loader).unwrap();
+    let highlights_at = |keep_all_highlights: bool, pos: u32| -> Vec<String> {
+        let mut highlighter = Highlighter::new(&syntax, source.into(), &loader, ..);
+        highlighter.set_keep_all_highlights(keep_all_highlights);
+        while highlighter.next_event_offset() <= pos {
+            highlighter.advance();
+        }
+        highlighter
+            .active_highlights()
+            .map(|highlight| loader.test_theme.borrow()[highlight.idx()].clone())
+            .collect()
+    };
+
+    // `Foo` is captured as `@variable` and then as `@type` by a later pattern.
+    assert_eq!(highlights_at(false, 4), ["type"]);
+    assert_eq!(highlights_at(true, 4), ["variable", "type"]);
+    assert_eq!(highlights_at(true, 0), ["keyword.storage"]);
+    assert!(highlights_at(true, 7).is_empty());
+}
+
+#[test]
+fn self_injection() {
+    let mut loader = TestLanguageLoader::new();
+    // Injecting a language into the whole document of the same language would otherwise inject
+    // the same layer again and again.
+    loader.shadow_injections(
+        "markdown",
+        r#"
+((document) @injection.content
+ (#set! injection.language "markdown")
+ (#set! injection.include-children))"#,
+    );
+    let source = "# Title\n\nSome *text*.\n";
+    let syntax = Syntax::new(
+        source.into(),
+        loader.get("markdown"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    assert_eq!(
+        syntax.debug_tree(&loader, false),
+        "\
+markdown Layer(0) [0..4294967295]
+  markdown-inline Layer(1) [2..7]
+  markdown-inline Layer(2) [9..21]
+"
+    );
+}
+
+#[test]
+fn injection_exclude() {
+    let mut loader = TestLanguageLoader::new();
+    // Inject the whole code block, including children, except for the fence delimiters and the
+    // info string.
+    loader.overwrite_injections(
+        "markdown",
+        r#"
+((fenced_code_block
+  (fenced_code_block_delimiter) @injection.exclude
+  (info_string) @injection.exclude
+  (fenced_code_block_delimiter) @injection.exclude) @injection.content
+ (#set! injection.language "rust")
+ (#set! injection.include-children))"#
+            .to_owned(),
+    );
+    let source = "```rust\nfn a() {}\n```\n";
+    let syntax = Syntax::new(
+        source.into(),
+        loader.get("markdown"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    assert_eq!(
+        syntax.debug_tree(&loader, false),
+        "\
+markdown Layer(0) [0..4294967295]
+  rust Layer(1) [7..18, 21..22]
+"
+    );
+}
+
+#[test]
+fn debug_tree() {
+    let loader = TestLanguageLoader::new();
+    let source = "# Title\n\n```rust\nfn a() {}\n```\n";
+    let syntax = Syntax::new(
+        source.into(),
+        loader.get("markdown"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    assert_eq!(
+        syntax.debug_tree(&loader, false),
+        "\
+markdown Layer(0) [0..4294967295]
+  markdown-inline Layer(1) [2..7]
+  rust Layer(2) [17..27]
+"
+    );
+    let with_trees = syntax.debug_tree(&loader, true);
+    assert!(with_trees.contains(
+        "  rust Layer(2) [17..27]\n    (source_file (function_item name: (identifier) \
+         parameters: (parameters) body: (block)))\n"
+    ));
+}
+
+#[test]
+fn syntax_config() {
+    let loader = TestLanguageLoader::new();
+    let source = "```rust\nfn a() {}\n```\n```toml\na = 1\n```\n```rust\nfn b() {}\n```\n";
+    let syntax = Syntax::new(
+        source.into(),
+        loader.get("markdown"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    // Capacities smaller than the number of layers only cost reallocations.
+    let config = SyntaxConfig {
+        layer_capacity: 1,
+        query_iter_capacity: 1,
+        ..SyntaxConfig::default()
+    };
+    let small = Syntax::new_with_config(
+        source.into(),
+        loader.get("markdown"),
+        PARSE_TIMEOUT,
+        &loader,
+        config,
+    )
+    .unwrap();
+    assert_eq!(
+        small.debug_tree(&loader, false),
+        syntax.debug_tree(&loader, false)
+    );
+
+    // Every constructor allocates the configured number of layers up front.
+    let config = SyntaxConfig {
+        layer_capacity: 100,
+        ..SyntaxConfig::default()
+    };
+    let markdown = loader.get("markdown");
+    let large = [
+        Syntax::new_with_config(source.into(), markdown, PARSE_TIMEOUT, &loader, config),
+        Syntax::new_with_old_tree_and_config(
+            source.into(),
+            markdown,
+            PARSE_TIMEOUT,
+            &loader,
+            None,
+            &[],
+            config,
+        ),
+        Syntax::new_windowed(
+            source.into(),
+            markdown,
+            0..u32::MAX,
+            PARSE_TIMEOUT,
+            &loader,
+            config,
+        ),
+        Syntax::from_layout(
+            source.into(),
+            &syntax.layout(),
+            PARSE_TIMEOUT,
+            &loader,
+            config,
+        ),
+    ];
+    for large in large {
+        let large = large.unwrap();
+        assert_eq!(large.config(), config);
+        assert!(large.layers.capacity() >= 100);
+        assert_eq!(
+            large.debug_tree(&loader, false),
+            syntax.debug_tree(&loader, false)
+        );
+    }
+}
+
+#[test]
+fn max_source_size() {
+    let loader = TestLanguageLoader::new();
+    let config = SyntaxConfig {
+        max_source_size: 16,
+        ..SyntaxConfig::default()
+    };
+    let new = |source: &str| {
+        Syntax::new_with_config(
+            source.into(),
+            loader.get("rust"),
+            PARSE_TIMEOUT,
+            &loader,
+            config,
+        )
+    };
+    assert_eq!(
+        new("fn main() {}\nfn a() {}\n").unwrap_err(),
+        crate::Error::ExceededMaximumSize
+    );
+
+    // The limit also applies to updates.
+    let mut syntax = new("fn main() {}\n").unwrap();
+    let edit = InputEdit {
+        start_byte: 13,
+        old_end_byte: 13,
+        new_end_byte: 23,
+        start_point: Point::ZERO,
+        old_end_point: Point::ZERO,
+        new_end_point: Point::ZERO,
+    };
+    assert_eq!(
+        syntax
+            .update(
+                "fn main() {}\nfn a() {}\n".into(),
+                PARSE_TIMEOUT,
+                &[edit],
+                &loader
+            )
+            .unwrap_err(),
+        crate::Error::ExceededMaximumSize
+    );
+}
+
+#[test]
+fn node_range_predicates() {
+    let loader = TestLanguageLoader::new();
+    let source = "fn a() {}\n";
+    let syntax = Syntax::new(source.into(), loader.get("rust"), PARSE_TIMEOUT, &loader).unwrap();
+    let body = syntax.named_descendant_for_byte_range(7, 8).unwrap();
+    assert_eq!(body.kind(), "block");
+    assert_eq!(body.byte_range(), 7..9);
+
+    assert!(!body.contains(6));
+    assert!(body.contains(7));
+    assert!(body.contains(8));
+    assert!(!body.contains(9));
+
+    assert!(body.intersects(0..8));
+    assert!(body.intersects(8..20));
+    assert!(body.intersects(7..7));
+    assert!(!body.intersects(0..7));
+    assert!(!body.intersects(9..20));
+    assert!(!body.intersects(9..9));
+
+    assert!(ranges_intersect(&(0..8), &(7..9)));
+    assert!(ranges_intersect(&(7..7), &(7..9)));
+    assert!(!ranges_intersect(&(0..7), &(7..9)));
+    assert!(!ranges_intersect(&(9..9), &(7..9)));
+}
+
+#[test]
+fn tree_changed_ranges() {
+    // The changed ranges and the S-expression are allocated by tree-sitter and must be freed
+    // with its allocator rather than with whichever `free` the Rust binary links against.
+    let loader = TestLanguageLoader::new();
+    let before_text = "fn a() {}\n";
+    let after_text = "fn a() {}\nfn b() {}\n";
+    let edit = InputEdit {
+        start_byte: 10,
+        old_end_byte: 10,
+        new_end_byte: 20,
+        start_point: Point { row: 1, col: 0 },
+        old_end_point: Point { row: 1, col: 0 },
+        new_end_point: Point { row: 2, col: 0 },
+    };
+    let mut syntax = Syntax::new(
+        before_text.into(),
+        loader.get("rust"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    let mut old_tree = syntax.tree().clone();
+    syntax
+        .update(after_text.into(), PARSE_TIMEOUT, &[edit], &loader)
+        .unwrap();
+    old_tree.edit(&edit);
+    let changed: Vec<_> = old_tree
+        .changed_ranges(syntax.tree())
+        .map(|range| range.start_byte..range.end_byte)
+        .collect();
+    assert_eq!(changed.len(), 1);
+    assert_eq!(changed[0], 10..19);
+    // The ranges are freed whether or not they were consumed.
+    assert_eq!(old_tree.changed_ranges(syntax.tree()).len(), 1);
+    assert!(old_tree.changed_ranges(&old_tree.clone()).is_empty());
+    assert_eq!(
+        syntax.tree().root_node().to_sexp(),
+        "(source_file (function_item name: (identifier) parameters: (parameters) body: (block)) \
+         (function_item name: (identifier) parameters: (parameters) body: (block)))"
+    );
+}
+
+#[test]
+fn set_allocator_after_use() {
+    // Other tests may run first so only replacing the allocator after use is deterministic.
+    let _parser = Parser::new();
+    assert_eq!(
+        tree_sitter::set_allocator(tree_sitter::Allocator::rust()),
+        Err(tree_sitter::AllocatorInUseError)
+    );
+}
+
+#[test]
+fn parser_included_ranges() {
+    let range = |start_byte, end_byte| tree_sitter::Range {
+        start_point: Point::ZERO,
+        end_point: Point::MAX,
+        start_byte,
+        end_byte,
+    };
+    let mut parser = Parser::new();
+    // Without included ranges the whole document is parsed.
+    parser.set_included_ranges(&[]).unwrap();
+    assert_eq!(parser.included_ranges(), [range(0, u32::MAX)]);
+
+    let ranges = [range(0, 5), range(5, 10), range(20, 30)];
+    parser.set_included_ranges(&ranges).unwrap();
+    assert_eq!(parser.included_ranges(), ranges);
+
+    for (invalid, index, kind) in [
+        (vec![range(10, 5)], 0, InvalidRangeKind::Reversed),
+        (
+            vec![range(0, 5), range(10, 20), range(5, 8)],
+            2,
+            InvalidRangeKind::OutOfOrder,
+        ),
+        (
+            vec![range(0, 10), range(5, 20)],
+            1,
+            InvalidRangeKind::Overlap,
+        ),
+    ] {
+        assert_eq!(
+            parser.set_included_ranges(&invalid),
+            Err(InvalidRangesError { index, kind })
+        );
+        // Invalid ranges are not applied.
+        assert_eq!(parser.included_ranges(), ranges);
+    }
+    assert_eq!(
+        InvalidRangesError {
+            index: 1,
+            kind: InvalidRangeKind::Overlap
+        }
+        .to_string(),
+        "included range 1 overlaps the previous range"
+    );
+}
+
+#[test]
+fn query_iter_seek() {
+    let loader = TestLanguageLoader::new();
+    let source = "# Title\n\n```rust\nfn a() {}\n```\n\nSome *text*.\n\n```rust\nfn b() {\n    let x = 1;\n}\n```\n";
+    let syntax = Syntax::new(
+        source.into(),
+        loader.get("markdown"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    let query_loader = |lang| {
+        loader
+            .get_config(lang)
+            .and_then(|config| config.highlight_query.as_ref())
+            .map(|highlight_query| &highlight_query.query)
+    };
+    // Match IDs depend on the cursor so only compare the ranges and captures of the events.
+    let event = |event: QueryIterEvent<'_>| match event {
+        QueryIterEvent::EnterInjection(injection) => format!("enter {:?}", injection.range),
+        QueryIterEvent::Match(mat) => {
+            format!("match {:?} {:?}", mat.node.byte_range(), mat.capture)
+        }
+        QueryIterEvent::ExitInjection { injection, .. } => format!("exit {:?}", injection.range),
+    };
+
+    for target in ["Title", "text", "fn b", "x = 1", "}\n```\n"] {
+        let pos = source.find(target).unwrap() as u32;
+        let expected: Vec<_> = QueryIter::new(&syntax, source.into(), query_loader, pos..)
+            .map(event)
+            .collect();
+        for skip in [0, 1, 3] {
+            let mut iter = QueryIter::<_, ()>::new(&syntax, source.into(), query_loader, ..);
+            // Events which were yielded before seeking are not yielded again.
+            let mut expected = expected.clone();
+            for skipped in iter.by_ref().take(skip).map(event) {
+                if let Some(i) = expected.iter().position(|event| *event == skipped) {
+                    expected.remove(i);
+                }
+            }
+            iter.seek_to(pos);
+            let events: Vec<_> = iter.map(event).collect();
+            assert_eq!(events, expected, "seeking to {pos} after {skip} events");
+        }
+    }
+}
+
+#[test]
+fn filter_captures() {
+    let loader = TestLanguageLoader::new();
+    let source = "# Title\n\n```rust\nfn a() { let x = 1; }\n```\n";
+    let syntax = Syntax::new(
+        source.into(),
+        loader.get("markdown"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    let query_loader = |lang| {
+        loader
+            .get_config(lang)
+            .and_then(|config| config.highlight_query.as_ref())
+            .map(|highlight_query| &highlight_query.query)
+    };
+    let captures = |filter: bool| {
+        let mut iter = QueryIter::<_, ()>::new(&syntax, source.into(), query_loader, ..);
+        if filter {
+            iter.filter_captures(|name| name.starts_with("keyword"));
+        }
+        let mut captures = Vec::new();
+        while let Some(event) = iter.next() {
+            if let QueryIterEvent::Match(mat) = event {
+                let query = query_loader(iter.current_language()).unwrap();
+                let name = query.capture_name(mat.capture).to_owned();
+                captures.push((mat.node.byte_range(), name));
+            }
+        }
+        captures
+    };
+    let all = captures(false);
+    let expected: Vec<_> = all
+        .iter()
+        .filter(|(_, name)| name.starts_with("keyword"))
+        .cloned()
+        .collect();
+    assert!(!expected.is_empty() && expected.len() < all.len());
+    assert_eq!(captures(true), expected);
+}
+
+#[test]
+fn missing_highlight_query() {
+    let mut loader = TestLanguageLoader::new();
+    loader.overwrite_highlights("markdown", String::new());
+    let markdown = loader.get("markdown");
+    assert!(loader
+        .get_config(markdown)
+        .unwrap()
+        .highlight_query
+        .is_none());
+    let source = "# Title\n\n```rust\nfn main() {}\n```\n";
+    let syntax = Syntax::new(source.into(), markdown, PARSE_TIMEOUT, &loader).unwrap();
+    let highlights_at = |pos: u32| -> Vec<String> {
+        syntax
+            .highlights_at(source.into(), &loader, pos)
+            .into_iter()
+            .map(|highlight| loader.test_theme.borrow()[highlight.idx()].clone())
+            .collect()
+    };
+
+    // The markdown layer itself is not highlighted but the injected rust layer is.
+    assert!(highlights_at(0).is_empty());
+    assert_eq!(highlights_at(18), ["keyword.function"]);
+}
+
+#[cfg(feature = "html")]
+#[test]
+fn render_html() {
+    let loader = TestLanguageLoader::new();
+    let source = "let s = \"<a\nb>\";";
+    let syntax = Syntax::new(source.into(), loader.get("rust"), PARSE_TIMEOUT, &loader).unwrap();
+    let theme = loader.test_theme.borrow().clone();
+    let html = crate::html::render_html(&syntax, source.into(), &loader, |highlight| {
+        theme[highlight.idx()].as_str()
+    });
+    assert_eq!(
+        html,
+        "<span class=\"keyword.storage\">let</span> <span class=\"variable\">s</span> \
+         <span class=\"operator\">=</span> <span class=\"string\">&quot;&lt;a</span>\n\
+         <span class=\"string\">b&gt;&quot;</span><span class=\"punctuation.delimiter\">;</span>"
+    );
+}
+
+#[test]
+fn cancel_update() {
+    let loader = TestLanguageLoader::new();
+    let mut syntax = Syntax::new("".into(), loader.get("rust"), PARSE_TIMEOUT, &loader).unwrap();
+    let source = "fn a() { let x = [1, 2, 3]; }\n".repeat(1000);
+    let edit = InputEdit {
+        start_byte: 0,
+        old_end_byte: 0,
+        new_end_byte: source.len() as u32,
+        start_point: Point::ZERO,
+        old_end_point: Point::ZERO,
+        new_end_point: Point { row: 1000, col: 0 },
+    };
+    let cancel = std::sync::atomic::AtomicBool::new(true);
+    let res = syntax.update_with_cancel(
+        source.as_str().into(),
+        PARSE_TIMEOUT,
+        &[edit],
+        &loader,
+        &cancel,
+    );
+    assert_eq!(res, Err(crate::Error::Cancelled));
+
+    cancel.store(false, std::sync::atomic::Ordering::Relaxed);
+    syntax
+        .update_with_cancel(
+            source.as_str().into(),
+            PARSE_TIMEOUT,
+            &[edit],
+            &loader,
+            &cancel,
+        )
+        .unwrap();
+    assert_eq!(syntax.tree().root_node().named_child_count(), 1000);
+}
+
+#[test]
+fn update_progress() {
+    let loader = TestLanguageLoader::new();
+    let mut syntax = Syntax::new("".into(), loader.get("rust"), PARSE_TIMEOUT, &loader).unwrap();
+    let source = "fn a() { let x = [1, 2, 3]; }\n".repeat(1000);
+    let edit = InputEdit {
+        start_byte: 0,
+        old_end_byte: 0,
+        new_end_byte: source.len() as u32,
+        start_point: Point::ZERO,
+        old_end_point: Point::ZERO,
+        new_end_point: Point { row: 1000, col: 0 },
+    };
+    let mut offsets = Vec::new();
+    syntax
+        .update_with_progress(
+            source.as_str().into(),
+            PARSE_TIMEOUT,
+            &[edit],
+            &loader,
+            |offset| offsets.push(offset),
+        )
+        .unwrap();
+    assert!(!offsets.is_empty());
+    assert!(offsets.iter().all(|&offset| offset <= source.len() as u32));
+    assert!(offsets.last() > offsets.first());
+}
+
+#[test]
+fn node_at_byte_and_cursor_reset() {
+    let loader = TestLanguageLoader::new();
+    let source = "# Title\n\n```rust\nfn main() {}\n```\n";
+    let syntax = Syntax::new(
+        source.into(),
+        loader.get("markdown"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+
+    let main = source.find("main").unwrap() as u32;
+    let node = syntax.node_at_byte(main).unwrap();
+    assert_eq!(node.kind(), "identifier");
+    assert_eq!(node.byte_range(), main..main + 4);
+    let heading = syntax.node_at_byte(0).unwrap();
+    assert_eq!(heading.kind(), "atx_h1_marker");
+
+    let mut cursor = syntax.walk();
+    assert!(cursor.goto_first_child());
+    assert_ne!(cursor.node(), syntax.tree().root_node());
+    cursor.reset(&syntax);
+    assert_eq!(cursor.node(), syntax.tree().root_node());
+}
+
+#[test]
+fn matching_bracket() {
+    let loader = TestLanguageLoader::new();
+    let source = "fn a(x: Vec<u8>) { if b { (1) } }";
+    let syntax = Syntax::new(source.into(), loader.get("rust"), PARSE_TIMEOUT, &loader).unwrap();
+    let find = |needle: &str| source.find(needle).unwrap() as u32;
+    let rfind = |needle: &str| source.rfind(needle).unwrap() as u32;
+    assert_eq!(syntax.matching_bracket(find("(")), Some(find(")")));
+    assert_eq!(syntax.matching_bracket(find(")")), Some(find("(")));
+    assert_eq!(syntax.matching_bracket(find("<")), Some(find(">")));
+    assert_eq!(syntax.matching_bracket(find("{")), Some(rfind("}")));
+    assert_eq!(syntax.matching_bracket(rfind("}")), Some(find("{")));
+    assert_eq!(syntax.matching_bracket(find("(1")), Some(find("1)") + 1));
+    // Not a bracket.
+    assert_eq!(syntax.matching_bracket(find("if")), None);
+    assert_eq!(syntax.matching_bracket(u32::MAX), None);
+
+    // Brackets within an injection are resolved within the injected layer.
+    let source = "# Title\n\n```rust\nfn main() { {} }\n```\n";
+    let syntax = Syntax::new(
+        source.into(),
+        loader.get("markdown"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    let open = source.find("{ {").unwrap() as u32;
+    let close = source.rfind('}').unwrap() as u32;
+    assert_eq!(syntax.matching_bracket(open), Some(close));
+    assert_eq!(syntax.matching_bracket(close), Some(open));
+}
+
+#[test]
+fn tags() {
+    let loader = TestLanguageLoader::new();
+    let rust = loader.get("rust");
+    let grammar = loader.get_config(rust).unwrap().grammar;
+    let query = crate::tags::TagsQuery::new(
+        grammar,
+        r#"
+        (function_item name: (identifier) @name) @definition.function
+        (call_expression function: (identifier) @name) @reference.call
+        "#,
+    )
+    .unwrap();
+
+    let source = "# Title\n\n```rust\nfn a() { b(); }\nfn b() {}\n```\n";
+    let syntax = Syntax::new(
+        source.into(),
+        loader.get("markdown"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    let tags: Vec<_> = query
+        .tags(&syntax, source.into())
+        .into_iter()
+        .map(|tag| {
+            (
+                query.kind_name(tag.kind),
+                &source[tag.name_range.start as usize..tag.name_range.end as usize],
+                tag.is_definition,
+            )
+        })
+        .collect();
+    assert_eq!(
+        tags,
+        [
+            ("function", "a", true),
+            ("call", "b", false),
+            ("function", "b", true),
+        ]
+    );
+}
+
+#[test]
+fn read_query_inherits() {
+    let queries = |language: &str| -> String {
+        match language {
+            "a" => "(a)\n; inherits: b\n",
+            "b" => "(b)\n; inherits: a,c\n",
+            "c" => "(c)\n; inherits: c,unknown\n",
+            "diamond" => "; inherits: c,c\n",
+            _ => "",
+        }
+        .to_owned()
+    };
+    let normalize = |query: String| {
+        query
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    // Cycles are cut where a language would inherit itself and unknown languages are empty.
+    assert_eq!(
+        normalize(read_query("a", queries)),
+        "(a)\n(b)\n; skipped cyclic inheritance of a\n(c)\n; skipped cyclic inheritance of c"
+    );
+    assert_eq!(
+        normalize(read_query("c", queries)),
+        "(c)\n; skipped cyclic inheritance of c"
+    );
+    // A language may be inherited more than once as long as it doesn't inherit itself.
+    assert_eq!(
+        normalize(read_query("diamond", queries)),
+        "(c)\n; skipped cyclic inheritance of c\n(c)\n; skipped cyclic inheritance of c"
+    );
+
+    // Only comments consisting of the directive are directives.
+    let query = r#"; License header
+  ;; inherits: b
+((identifier) @x (#match? @x "; inherits: a"))
+; This query inherits: nothing
+(a) ; inherits: a
+"#;
+    let read = |language: &str| match language {
+        "main" => query.to_owned(),
+        "b" => "(b)".to_owned(),
+        _ => panic!("unexpected inheritance of {language}"),
+    };
+    assert_eq!(
+        normalize(read_query("main", read)),
+        r#"; License header
+(b)
+((identifier) @x (#match? @x "; inherits: a"))
+; This query inherits: nothing
+(a) ; inherits: a"#
+    );
+}
+
+#[test]
+fn language_config_builder() {
+    let loader = TestLanguageLoader::new();
+    let rust = loader.get("rust");
+    let grammar = loader.get_config(rust).unwrap().grammar;
+    let grammar_dir = skidder_config().grammar_dir("rust").unwrap();
+    let read = |file: &str| fs::read_to_string(grammar_dir.join(file)).unwrap();
+    let (highlights, text_objects) = (read("highlights.scm"), read("textobjects.scm"));
+    let tags = "(function_item name: (identifier) @name) @definition.function";
+
+    let config = LanguageConfig::builder(grammar)
+        .highlights(&highlights)
+        .text_objects(&text_objects)
+        .tags(tags)
+        .match_limit(64)
+        .build()
+        .unwrap();
+    assert!(config.highlight_query.is_some());
+    assert!(config.tags_query.is_some());
+    assert_eq!(config.match_limit, 64);
+    assert_eq!(config.tags_query.as_ref().unwrap().match_limit(), 64);
+    let text_object_query = config.text_object_query.as_ref().unwrap();
+    assert_eq!(text_object_query.match_limit(), 64);
+    assert!(text_object_query
+        .query
+        .get_capture("function.inside")
+        .is_some());
+    // Limits which tree-sitter doesn't support are clamped.
+    let config = config.with_match_limit(0);
+    assert_eq!(config.match_limit, 1);
+    assert_eq!(config.with_match_limit(u32::MAX).match_limit, 65536);
+
+    // Queries which are not set are absent.
+    let config = LanguageConfig::builder(grammar).build().unwrap();
+    assert!(config.highlight_query.is_none());
+    assert!(config.text_object_query.is_none());
+    assert!(config.tags_query.is_none());
+
+    let err = LanguageConfig::builder(grammar)
+        .highlights(&highlights)
+        .tags("(not_a_node) @name")
+        .build()
+        .unwrap_err();
+    assert_eq!(err.source, QuerySource::Tags);
+    let err = LanguageConfig::builder(grammar)
+        .text_objects("(function_item")
+        .build()
+        .unwrap_err();
+    assert_eq!(err.source, QuerySource::TextObjects);
+}
+
+#[test]
+fn custom_properties() {
+    let loader = TestLanguageLoader::new();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let highlights = r#"
+        ((identifier) @variable (#set! spell))
+        ((line_comment) @comment (#set! conceal ""))
+    "#;
+    let injections = r#"
+        ((doc_comment) @injection.content
+         (#set! injection.language "markdown")
+         (#set! priority 110))
+    "#;
+
+    let err = LanguageConfig::builder(grammar)
+        .highlights(highlights)
+        .build()
+        .unwrap_err();
+    assert_eq!(err.source, QuerySource::Highlights);
+    assert_eq!(err.error.kind(), QueryErrorKind::Predicate);
+
+    let config = LanguageConfig::builder(grammar)
+        .highlights(highlights)
+        .injections(injections)
+        .custom_properties(true)
+        .build()
+        .unwrap();
+    let query = &config.highlight_query.as_ref().unwrap().query;
+    let properties: Vec<Vec<_>> = query
+        .patterns()
+        .map(|pattern| query.property_settings(pattern).collect())
+        .collect();
+    assert_eq!(
+        properties,
+        [vec![("spell", None)], vec![("conceal", Some(""))]]
+    );
+    let query = config.injection_query.query();
+    let pattern = query.patterns().next().unwrap();
+    assert_eq!(
+        query.property_settings(pattern).last(),
+        Some(("priority", Some("110")))
+    );
+
+    // Typos in the properties used by this crate are still rejected.
+    let err = LanguageConfig::builder(grammar)
+        .injections(r#"((line_comment) @injection.content (#set! injection.langauge "comment"))"#)
+        .custom_properties(true)
+        .build()
+        .unwrap_err();
+    assert_eq!(err.source, QuerySource::Injections);
+}
+
+#[test]
+fn text_object_fixtures() {
+    let loader = TestLanguageLoader::new();
+    text_object_fixture(
+        &loader,
+        "text_objects/function_inside.rs",
+        "function.inside",
+    );
+    text_object_fixture(&loader, "text_objects/comment_around.rs", "comment.around");
+    // Text objects within injections are captured with the query of the injected language.
+    text_object_fixture(
+        &loader,
+        "text_objects/function_around.md",
+        "function.around",
+    );
+    // Quantified captures in injections are grouped as well.
+    text_object_fixture(&loader, "text_objects/comment_around.md", "comment.around");
+}
+
+#[test]
+fn text_objects_in_injections() {
+    let loader = TestLanguageLoader::new();
+    let rust = loader.get("rust");
+    let grammar_dir = skidder_config().grammar_dir("rust").unwrap();
+    let query = TextObjectQuery::new(
+        loader.get_config(rust).unwrap().grammar,
+        &fs::read_to_string(grammar_dir.join("textobjects.scm")).unwrap(),
+    )
+    .unwrap();
+    let source = "# Title\n\n```rust\n// a\n// b\nfn main() { 1 }\n```\n";
+    let syntax = Syntax::new(
+        source.into(),
+        loader.get("markdown"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    let text = |captured: &CapturedNode| &source[captured.start_byte()..captured.end_byte()];
+    let captures = |name: &str| {
+        TextObjectQuery::capture_nodes(name, &syntax, source.into(), .., |lang| {
+            (lang == rust).then_some(&query)
+        })
+    };
+
+    let functions = captures("function.inside");
+    assert_eq!(functions.len(), 1);
+    assert_eq!(text(&functions[0]), "{ 1 }");
+    let comments = captures("comment.around");
+    assert_eq!(comments.len(), 1);
+    assert!(matches!(comments[0], CapturedNode::Grouped(ref nodes) if nodes.len() == 2));
+    assert_eq!(text(&comments[0]), "// a\n// b");
+    assert!(captures("class.around").is_empty());
+}
+
+#[test]
+fn query_match_captures() {
+    let loader = TestLanguageLoader::new();
+    let source = "// x\n// y\nfn a() {}";
+    let syntax = Syntax::new(source.into(), loader.get("rust"), PARSE_TIMEOUT, &loader).unwrap();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let query = Query::new(
+        grammar,
+        "((line_comment)+ @comment . (function_item name: (identifier) @name))",
+        |_, _| Ok(()),
+    )
+    .unwrap();
+    let text = |node: &tree_sitter::Node| {
+        &source[node.byte_range().start as usize..node.byte_range().end as usize]
+    };
+
+    let mut cursor = InactiveQueryCursor::new(0..u32::MAX, 16).execute_query(
+        &query,
+        &syntax.tree().root_node(),
+        ropey::RopeSlice::from(source),
+    );
+    let mut comments = Vec::new();
+    while let Some(mat) = cursor.next_match() {
+        let name = mat
+            .capture_node(query.get_capture("name").unwrap())
+            .unwrap();
+        assert_eq!(text(&name.node), "a");
+        let comment = mat
+            .capture_node(query.get_capture("comment").unwrap())
+            .unwrap();
+        assert_eq!(text(&comment.node), "// x");
+        assert_eq!(mat.captures_named("missing").count(), 0);
+        comments.push(mat.captures_named("comment").map(text).collect::<Vec<_>>());
+    }
+    assert_eq!(comments, [["// x", "// y"]]);
+}
+
+#[test]
+fn language_match_table() {
+    let markdown = Language::new(0);
+    let rust = Language::new(1);
+    let other = Language::new(2);
+    let table = LanguageMatchTable::new([
+        ("md|markdown", markdown),
+        ("rust|rs", rust),
+        ("mark", other),
+    ])
+    .unwrap();
+    let resolve = |text: &str| table.longest_match(text.into());
+    assert_eq!(resolve("markdown"), Some(markdown));
+    assert_eq!(resolve("md"), Some(markdown));
+    assert_eq!(resolve("rs"), Some(rust));
+    assert_eq!(resolve("marker"), Some(other));
+    assert_eq!(resolve("python"), None);
+
+    let by_name = |name: &str| (name == "rust").then_some(rust);
+    assert_eq!(
+        table.language_for_marker(InjectionLanguageMarker::Name("rust"), by_name),
+        Some(rust)
+    );
+    assert_eq!(
+        table.language_for_marker(InjectionLanguageMarker::Name("rs"), by_name),
+        None
+    );
+    assert_eq!(
+        table.language_for_marker(
+            InjectionLanguageMarker::Shebang("rust-script".into()),
+            by_name
+        ),
+        Some(rust)
+    );
+    let err = LanguageMatchTable::new([("md|(markdown", markdown)]).unwrap_err();
+    assert_eq!(err.regex, "md|(markdown");
+
+    // The default `LanguageLoader::language_for_marker` resolves names exactly and everything
+    // else with the match table.
+    struct MatchTableLoader {
+        rust: Language,
+        table: LanguageMatchTable,
+    }
+    impl LanguageLoader for MatchTableLoader {
+        fn language_for_name(&self, name: &str) -> Option<Language> {
+            (name == "rust").then_some(self.rust)
+        }
+
+        fn language_match_table(&self) -> Option<&LanguageMatchTable> {
+            Some(&self.table)
+        }
+
+        fn get_config(&self, _lang: Language) -> Option<&LanguageConfig> {
+            None
+        }
+    }
+    let loader = MatchTableLoader { rust, table };
+    assert_eq!(
+        loader.language_for_marker(InjectionLanguageMarker::Name("rust")),
+        Some(rust)
+    );
+    assert_eq!(
+        loader.language_for_marker(InjectionLanguageMarker::Name("rs")),
+        None
+    );
+    assert_eq!(
+        loader.language_for_marker(InjectionLanguageMarker::Match("md".into())),
+        Some(markdown)
+    );
+    assert_eq!(
+        loader.language_for_marker(InjectionLanguageMarker::Filename("main.rs".into())),
+        Some(rust)
+    );
+}
+
+#[test]
+fn static_injection_languages_are_cached() {
+    struct CountingLoader<'a> {
+        loader: &'a TestLanguageLoader,
+        name_lookups: std::cell::Cell<u32>,
+        without_comment: bool,
+    }
+
+    impl LanguageLoader for CountingLoader<'_> {
+        fn language_for_marker(&self, marker: InjectionLanguageMarker) -> Option<Language> {
+            if let InjectionLanguageMarker::Name(name) = marker {
+                self.name_lookups.set(self.name_lookups.get() + 1);
+                if self.without_comment && name == "comment" {
+                    return None;
+                }
+            }
+            self.loader.language_for_marker(marker)
+        }
+
+        fn get_config(&self, lang: Language) -> Option<&LanguageConfig> {
+            self.loader.get_config(lang)
+        }
+    }
+
+    let test_loader = TestLanguageLoader::new();
+    let comment = test_loader.get("comment");
+    let comment_layers = |comments: usize, loader: &CountingLoader| {
+        let source = "// comment\n".repeat(comments) + "fn main() { x!(); y!(); }\n";
+        let syntax = Syntax::new(
+            source.as_str().into(),
+            test_loader.get("rust"),
+            PARSE_TIMEOUT,
+            loader,
+        )
+        .unwrap();
+        syntax
+            .layers
+            .iter()
+            .filter(|(_, layer)| layer.language == comment)
+            .count()
+    };
+    let loader = CountingLoader {
+        loader: &test_loader,
+        name_lookups: Default::default(),
+        without_comment: false,
+    };
+    assert_eq!(comment_layers(10, &loader), 10);
+    // Names are resolved once per pattern rather than once per match so more injections don't
+    // cause more lookups.
+    let lookups = loader.name_lookups.get();
+    assert_eq!(comment_layers(20, &loader), 20);
+    assert_eq!(loader.name_lookups.get(), 2 * lookups);
+
+    // The cache doesn't outlive the execution of the query, so another loader using the same
+    // queries resolves the names again.
+    let loader = CountingLoader {
+        without_comment: true,
+        ..loader
+    };
+    assert_eq!(comment_layers(10, &loader), 0);
+}
+
+#[test]
+fn injection_tree() {
+    let loader = TestLanguageLoader::new();
+    let source = "# Title\n\n```rust\n// a\nfn main() {}\n```\n";
+    let syntax = Syntax::new(
+        source.into(),
+        loader.get("markdown"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+
+    fn render(
+        node: &crate::InjectionNode,
+        loader: &TestLanguageLoader,
+        source: &str,
+        depth: usize,
+        out: &mut Vec<String>,
+    ) {
+        let name = loader.languages.get_index(node.language.idx()).unwrap().0;
+        let text = &source[node.range.start as usize..node.range.end as usize];
+        out.push(format!("{}{name} {text:?}", "  ".repeat(depth)));
+        for child in &node.children {
+            render(child, loader, source, depth + 1, out);
+        }
+    }
+    let tree = syntax.injection_tree();
+    assert_eq!(tree.layer, syntax.root());
+    let mut lines = Vec::new();
+    render(&tree, &loader, source, 0, &mut lines);
+    assert_eq!(
+        lines,
+        [
+            r##"markdown "# Title\n\n```rust\n// a\nfn main() {}\n```\n""##,
+            r#"  markdown-inline "Title""#,
+            r#"  rust "// a\nfn main() {}\n""#,
+            r#"    comment " a""#,
+        ]
+    );
+    assert_eq!(syntax.injections(syntax.root()).len(), 2);
+}
+
+#[test]
+fn disable_injection() {
+    let mut loader = TestLanguageLoader::new();
+    let rust = loader.get("rust");
+    let comment = loader.get("comment");
+    let source = "// a\nfn main() {}\n";
+    let count_comment_layers = |loader: &TestLanguageLoader| {
+        let syntax = Syntax::new(source.into(), rust, PARSE_TIMEOUT, loader).unwrap();
+        syntax
+            .layers
+            .iter()
+            .filter(|(_, layer)| layer.language == comment)
+            .count()
+    };
+    assert_eq!(count_comment_layers(&loader), 1);
+
+    let config = loader.lang_config[rust.idx()].get_mut().unwrap();
+    // The first pattern in the rust injections query injects the comment language.
+    let pattern = config.injection_query.query().patterns().next().unwrap();
+    config.injection_query.disable_injection(pattern);
+    assert_eq!(count_comment_layers(&loader), 0);
+}
+
+#[test]
+fn capture_quantifiers() {
+    use tree_sitter::Quantifier;
+
+    let loader = TestLanguageLoader::new();
+    let rust = loader.get("rust");
+    let query = TextObjectQuery::new(
+        loader.get_config(rust).unwrap().grammar,
+        "(line_comment)+ @comment\n(function_item name: (identifier)? @name) @function",
+    )
+    .unwrap();
+    let mut patterns = query.query.patterns();
+    let (comments, functions) = (patterns.next().unwrap(), patterns.next().unwrap());
+    let capture = |name| query.query.get_capture(name).unwrap();
+    assert_eq!(
+        query.query.capture_quantifier(comments, capture("comment")),
+        Quantifier::OneOrMore
+    );
+    assert_eq!(
+        query
+            .query
+            .capture_quantifier(comments, capture("function")),
+        Quantifier::Zero
+    );
+    assert_eq!(
+        query
+            .query
+            .capture_quantifier(functions, capture("function")),
+        Quantifier::One
+    );
+    assert_eq!(
+        query.query.capture_quantifier(functions, capture("name")),
+        Quantifier::ZeroOrOne
+    );
+
+    let source = "// a\n// b\nfn f() {}\n\n// c\nfn g() {}\n";
+    let syntax = Syntax::new(source.into(), rust, PARSE_TIMEOUT, &loader).unwrap();
+    let captures = |name: &str| {
+        TextObjectQuery::capture_nodes(name, &syntax, source.into(), .., |_| Some(&query))
+            .into_iter()
+            .map(|captured| match captured {
+                CapturedNode::Single(node) => (false, node.byte_range()),
+                CapturedNode::Grouped(nodes) => (
+                    true,
+                    nodes[0].start_byte()..nodes.last().unwrap().end_byte(),
+                ),
+            })
+            .map(|(grouped, range)| (grouped, &source[range.start as usize..range.end as usize]))
+            .collect::<Vec<_>>()
+    };
+    // Quantified captures are always grouped, even when only a single node was captured.
+    assert_eq!(captures("comment"), [(true, "// a\n// b"), (true, "// c")]);
+    assert_eq!(
+        captures("function"),
+        [(false, "fn f() {}"), (false, "fn g() {}")]
+    );
+}
+
+#[test]
+fn moved_layer_locals() {
+    let loader = TestLanguageLoader::new();
+    let markdown = loader.get("markdown");
+    let code = "```rust\nfn add(left: usize) -> usize { left }\n```\n";
+    let before_text = format!("# Title\n\n{code}");
+    let inserted = " with a long suffix which moves the code block by a lot";
+    let after_text = format!("# Title{inserted}\n\n{code}");
+    let edit = InputEdit {
+        start_byte: 7,
+        old_end_byte: 7,
+        new_end_byte: 7 + inserted.len() as u32,
+        start_point: Point { row: 0, col: 7 },
+        old_end_point: Point { row: 0, col: 7 },
+        new_end_point: Point {
+            row: 0,
+            col: 7 + inserted.len() as u32,
+        },
+    };
+    let mut syntax = Syntax::new(
+        before_text.as_str().into(),
+        markdown,
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    syntax
+        .update(after_text.as_str().into(), PARSE_TIMEOUT, &[edit], &loader)
+        .unwrap();
+    let fresh = Syntax::new(after_text.as_str().into(), markdown, PARSE_TIMEOUT, &loader).unwrap();
+
+    // The rust layer was only moved by the edit so its locals are shifted rather than
+    // recomputed. The result must be the same as recomputing them.
+    let highlights = |syntax: &Syntax| {
+        highlight_to_lines(syntax, after_text.as_str().into(), &loader, ..)
+            .into_iter()
+            .flatten()
+            .map(|(range, highlight)| {
+                (
+                    after_text[range.start as usize..range.end as usize].to_owned(),
+                    loader.test_theme.borrow()[highlight.idx()].clone(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let highlights_after_update = highlights(&syntax);
+    assert_eq!(highlights_after_update, highlights(&fresh));
+    assert_eq!(
+        highlights_after_update
+            .iter()
+            .filter(|(text, highlight)| text == "left" && highlight == "variable.parameter")
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn definitions_in_scope() {
+    let loader = TestLanguageLoader::new();
+    let source = "fn add(left: usize, right: usize) -> usize { left + right }";
+    let syntax = Syntax::new(source.into(), loader.get("rust"), PARSE_TIMEOUT, &loader).unwrap();
+    let locals = syntax.layer(syntax.root()).locals();
+    let body = source.find("left +").unwrap() as u32;
+    let scopes: Vec<_> = locals.scopes_at(body).collect();
+    assert!(scopes.len() > 1);
+    let mut definitions: Vec<_> = scopes
+        .iter()
+        .flat_map(|&scope| locals.definitions_in(scope))
+        .map(|(name, definition)| {
+            let range = definition.range.start as usize..definition.range.end as usize;
+            assert_eq!(&source[range], name);
+            name
+        })
+        .collect();
+    definitions.sort_unstable();
+    assert_eq!(definitions, ["left", "right"]);
+    assert!(locals.definitions_in(scopes[0]).next().is_none());
+}
+
+#[test]
+fn syntax_layout_roundtrip() {
+    let loader = TestLanguageLoader::new();
+    let markdown = loader.get("markdown");
+    let source = "# Title\n\n```rust\n// a\nfn add(left: usize) -> usize { left }\n```\n";
+    let syntax = Syntax::new(source.into(), markdown, PARSE_TIMEOUT, &loader).unwrap();
+    let layout = syntax.layout();
+    assert_eq!(layout.layers.len(), syntax.layers.len());
+    assert_eq!(layout.layers[0].language, markdown);
+    assert_eq!(layout.layers[0].parent, None);
+
+    let rebuilt = Syntax::from_layout(
+        source.into(),
+        &layout,
+        PARSE_TIMEOUT,
+        &loader,
+        syntax.config(),
+    )
+    .unwrap();
+    assert_eq!(rebuilt.layout(), layout);
+    assert_eq!(rebuilt.injection_tree().children.len(), 2);
+    let highlights = |syntax: &Syntax| highlight_to_lines(syntax, source.into(), &loader, ..);
+    assert_eq!(highlights(&rebuilt), highlights(&syntax));
+
+    let mut invalid = layout.clone();
+    invalid.layers[0].injections[0].layer = 100;
+    assert_eq!(
+        Syntax::from_layout(
+            source.into(),
+            &invalid,
+            PARSE_TIMEOUT,
+            &loader,
+            SyntaxConfig::default()
+        )
+        .unwrap_err(),
+        crate::Error::InvalidLayout
+    );
+}
+
+#[test]
+fn grammar_cache() {
+    let skidder_config = skidder_config();
+    let parser_path = skidder::build_grammar(&skidder_config, "rust", false).unwrap();
+    let cache = GrammarCache::new();
+    let grammar = unsafe { cache.load("rust", &parser_path).unwrap() };
+    assert_eq!(
+        unsafe { cache.load("rust", &parser_path).unwrap() },
+        grammar
+    );
+    assert!(unsafe { cache.load("not-rust", &parser_path) }.is_err());
+    drop(cache);
+    // The grammar keeps the library open after the cache is dropped.
+    let mut parser = tree_sitter::Parser::new();
+    parser.set_grammar(unsafe { grammar.grammar() }).unwrap();
+    let tree = parser
+        .parse(ropey::RopeSlice::from("fn main() {}"), None)
+        .unwrap();
+    assert_eq!(tree.root_node().kind(), "source_file");
+    drop((tree, parser));
+    drop(grammar);
+
+    // Loading the same library from many threads opens it once.
+    let cache = GrammarCache::new();
+    let grammars: Vec<_> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..8)
+            .map(|_| scope.spawn(|| unsafe { cache.load("rust", &parser_path).unwrap() }))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    assert!(grammars.iter().all(|grammar| *grammar == grammars[0]));
+}
+
+#[test]
+fn loaded_grammar() {
+    let skidder_config = skidder_config();
+    let parser_path = skidder::build_grammar(&skidder_config, "rust", false).unwrap();
+    let grammar = unsafe { LoadedGrammar::new("rust", &parser_path).unwrap() };
+    let mut parser = tree_sitter::Parser::new();
+    parser.set_grammar(unsafe { grammar.grammar() }).unwrap();
+    let tree = parser
+        .parse(ropey::RopeSlice::from("fn main() {}"), None)
+        .unwrap();
+    assert_eq!(tree.root_node().kind(), "source_file");
+    // Everything created with the grammar must be dropped before the library is closed.
+    drop((tree, parser));
+    drop(grammar);
+    assert!(unsafe { LoadedGrammar::new("not-rust", &parser_path) }.is_err());
+    assert!(unsafe { tree_sitter::Grammar::from_raw(std::ptr::null()) }.is_err());
+}
+
+#[test]
+fn parse_timeout_resumes() {
+    let loader = TestLanguageLoader::new();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let mut parser = Parser::new();
+    parser.set_grammar(grammar).unwrap();
+    let mut old_tree = parser
+        .parse(ropey::RopeSlice::from("fn main() {}"), None)
+        .unwrap();
+
+    let source = "fn main() { let x = [1, 2, 3]; }\n".repeat(20_000);
+    old_tree.edit(&InputEdit {
+        start_byte: 0,
+        old_end_byte: 12,
+        new_end_byte: source.len() as u32,
+        start_point: Point::ZERO,
+        old_end_point: Point { row: 0, col: 12 },
+        new_end_point: Point {
+            row: 20_000,
+            col: 0,
+        },
+    });
+    parser.set_timeout(std::time::Duration::from_micros(1));
+    assert!(parser
+        .parse(ropey::RopeSlice::from(source.as_str()), Some(&old_tree))
+        .is_none());
+    // The parser resumes the parse which timed out.
+    parser.set_timeout(std::time::Duration::ZERO);
+    let tree = parser
+        .parse(ropey::RopeSlice::from(source.as_str()), Some(&old_tree))
+        .unwrap();
+    assert_eq!(tree.root_node().byte_range(), 0..source.len() as u32);
+    assert!(!tree.root_node().has_error());
+}
+
+#[test]
+fn windowed_syntax() {
+    let loader = TestLanguageLoader::new();
+    let rust = loader.get("rust");
+    let source = "fn a() {}\nfn b() {}\nfn c() {}\n";
+    let mut syntax = Syntax::new_windowed(
+        source.into(),
+        rust,
+        10..20,
+        PARSE_TIMEOUT,
+        &loader,
+        SyntaxConfig::default(),
+    )
+    .unwrap();
+    let functions = |syntax: &Syntax| {
+        let root = syntax.tree().root_node();
+        (0..root.named_child_count())
+            .map(|i| root.named_child(i).unwrap().byte_range())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(functions(&syntax), vec![10..19]);
+    // Nodes within the window have the rows and columns of the whole document.
+    let function = syntax.tree().root_node().named_child(0).unwrap();
+    assert_eq!(function.start_position(), Point { row: 1, col: 0 });
+
+    syntax.set_window(source.into(), Some(0..9));
+    syntax
+        .update(source.into(), PARSE_TIMEOUT, &[], &loader)
+        .unwrap();
+    assert_eq!(functions(&syntax), vec![0..9]);
+
+    syntax.set_window(source.into(), None);
+    syntax
+        .update(source.into(), PARSE_TIMEOUT, &[], &loader)
+        .unwrap();
+    assert_eq!(functions(&syntax), vec![0..9, 10..19, 20..29]);
+
+    // The size limit applies to the whole document rather than the window.
+    let config = SyntaxConfig {
+        max_source_size: 32,
+        ..SyntaxConfig::default()
+    };
+    let mut syntax =
+        Syntax::new_windowed(source.into(), rust, 10..20, PARSE_TIMEOUT, &loader, config).unwrap();
+    let edit = InputEdit {
+        start_byte: 30,
+        old_end_byte: 30,
+        new_end_byte: 40,
+        start_point: Point::ZERO,
+        old_end_point: Point::ZERO,
+        new_end_point: Point::ZERO,
+    };
+    assert_eq!(
+        syntax
+            .update(
+                "fn a() {}\nfn b() {}\nfn c() {}\nfn d() {}\n".into(),
+                PARSE_TIMEOUT,
+                &[edit],
+                &loader
+            )
+            .unwrap_err(),
+        crate::Error::ExceededMaximumSize
+    );
+}
+
+#[test]
+fn point_conversion() {
+    let loader = TestLanguageLoader::new();
+    let rust = loader.get("rust");
+    let source = ropey::Rope::from_str("fn a() {}\n\nfn b() {\n    let ü = x;\n}\n");
+    let source = source.slice(..);
+    let syntax = Syntax::new(source, rust, PARSE_TIMEOUT, &loader).unwrap();
+    let node = syntax
+        .node_at_byte(source.to_string().find('x').unwrap() as u32)
+        .unwrap();
+    // Columns are measured in bytes.
+    assert_eq!(node.start_position(), Point { row: 3, col: 13 });
+    for byte in [
+        node.start_byte(),
+        node.end_byte(),
+        0,
+        source.len_bytes() as u32,
+    ] {
+        let point = Syntax::byte_to_point(source, byte);
+        assert_eq!(Syntax::point_to_byte(source, point), byte);
+    }
+    assert_eq!(
+        Syntax::byte_to_point(source, node.start_byte()),
+        node.start_position()
+    );
+    assert_eq!(
+        Syntax::point_to_byte(source, Point { row: 0, col: 100 }),
+        10
+    );
+    assert_eq!(
+        Syntax::point_to_byte(source, Point { row: 100, col: 0 }),
+        source.len_bytes() as u32
+    );
+    assert_eq!(
+        Syntax::byte_to_point(source, u32::MAX),
+        Syntax::byte_to_point(source, source.len_bytes() as u32)
+    );
+}
+
+#[test]
+fn utf16_conversion() {
+    // `é` is two bytes and one UTF-16 code unit, `😀` is four bytes and two code units.
+    let source = ropey::Rope::from_str("aé😀b\nx😀\n");
+    let source = source.slice(..);
+    let b = "aé😀".len() as u32;
+    assert_eq!(Syntax::utf16_len(source, 0..b), 4);
+    assert_eq!(Syntax::utf16_len(source, b..0), 0);
+    assert_eq!(Syntax::utf16_len(source, b..u32::MAX), 6);
+    assert_eq!(
+        Syntax::byte_to_utf16_point(source, b),
+        Point { row: 0, col: 4 }
+    );
+    assert_eq!(
+        Syntax::utf16_point_to_byte(source, Point { row: 0, col: 4 }),
+        b
+    );
+    let x = b + "b\n".len() as u32;
+    assert_eq!(
+        Syntax::byte_to_utf16_point(source, x + 1),
+        Point { row: 1, col: 1 }
+    );
+    // Columns in the middle of a surrogate pair round down to the start of the character.
+    assert_eq!(
+        Syntax::utf16_point_to_byte(source, Point { row: 1, col: 2 }),
+        x + 1
+    );
+    assert_eq!(
+        Syntax::utf16_point_to_byte(source, Point { row: 0, col: 100 }),
+        x
+    );
+    assert_eq!(
+        Syntax::utf16_point_to_byte(source, Point { row: 100, col: 0 }),
+        source.len_bytes() as u32
+    );
+    assert_eq!(
+        Syntax::byte_to_utf16_point(source, u32::MAX),
+        Point { row: 2, col: 0 }
+    );
+}
+
+#[test]
+fn node_positions() {
+    let loader = TestLanguageLoader::new();
+    let rust = loader.get("rust");
+    let source = "// header\nfn main() {\n    call(\n        1,\n    );\n}\n";
+    let syntax = Syntax::new(source.into(), rust, PARSE_TIMEOUT, &loader).unwrap();
+    let call = syntax
+        .named_descendant_for_byte_range(
+            source.find("call").unwrap() as u32,
+            source.find(");").unwrap() as u32 + 1,
+        )
+        .unwrap();
+    assert_eq!(call.kind(), "call_expression");
+    assert_eq!(call.start_position(), Point { row: 2, col: 4 });
+    assert_eq!(call.end_position(), Point { row: 4, col: 5 });
+    let function = call.parent().unwrap().parent().unwrap().parent().unwrap();
+    assert_eq!(function.kind(), "function_item");
+    assert_eq!(function.start_position(), Point { row: 1, col: 0 });
+    assert_eq!(function.end_position(), Point { row: 5, col: 1 });
+}
+
+#[test]
+fn syntax_errors() {
+    let loader = TestLanguageLoader::new();
+    let markdown = loader.get("markdown");
+    let source =
+        "# Title\n\n```rust\nfn main() {\n    let x = 1\n}\n```\n\n```rust\nfn ok() {}\n```\n";
+    let syntax = Syntax::new(source.into(), markdown, PARSE_TIMEOUT, &loader).unwrap();
+    assert!(!syntax.tree().root_node().has_error());
+    let errors: Vec<_> = syntax.errors().collect();
+    assert_eq!(errors.len(), 1, "{errors:?}");
+    let line_end = source.find("1\n").unwrap() as u32 + 1;
+    // The missing semicolon is reported as a zero width `MISSING` node.
+    assert_eq!(errors[0], line_end..line_end);
+}
+
+#[test]
+fn descendants_within_depth() {
+    let loader = TestLanguageLoader::new();
+    let rust = loader.get("rust");
+    let source = "fn a() { b(c(d)); }\nfn e() {}\n";
+    let syntax = Syntax::new(source.into(), rust, PARSE_TIMEOUT, &loader).unwrap();
+    let root = syntax.tree().root_node();
+    let all: Vec<_> = root.descendants_within_depth(u32::MAX).collect();
+    assert_eq!(all.len() as u32, root.descendant_count());
+    assert_eq!(all[0], root);
+    assert_eq!(root.descendants_within_depth(0).count(), 1);
+    let kinds: Vec<_> = root
+        .descendants_within_depth(1)
+        .map(|node| node.kind())
+        .collect();
+    assert_eq!(kinds, ["source_file", "function_item", "function_item"]);
+    // Walks are relative to the node the iterator was created from.
+    let function = root.child(1).unwrap();
+    let kinds: Vec<_> = function
+        .descendants_within_depth(1)
+        .map(|node| node.kind())
+        .collect();
+    assert_eq!(
+        kinds,
+        ["function_item", "fn", "identifier", "parameters", "block"]
+    );
+}
+
+#[test]
+fn descendant_for_point_range() {
+    let loader = TestLanguageLoader::new();
+    let markdown = loader.get("markdown");
+    let source = "# Title\n\n```rust\nfn main() {\n    let x = 1;\n}\n```\n";
+    let syntax = Syntax::new(source.into(), markdown, PARSE_TIMEOUT, &loader).unwrap();
+    let start = Point { row: 4, col: 8 };
+    let end = Point { row: 4, col: 9 };
+    let node = syntax
+        .named_descendant_for_point_range(source.into(), start, end)
+        .unwrap();
+    assert_eq!(node.kind(), "identifier");
+    assert_eq!(
+        &source[node.start_byte() as usize..node.end_byte() as usize],
+        "x"
+    );
+    let node = syntax
+        .descendant_for_point_range(source.into(), Point { row: 0, col: 0 }, Point::ZERO)
+        .unwrap();
+    assert_eq!(node.kind(), "atx_h1_marker");
+    // The points of the root layer are reliable so they can be used with the node directly.
+    let heading = syntax
+        .tree()
+        .root_node()
+        .named_descendant_for_point_range(Point::ZERO, Point { row: 0, col: 7 })
+        .unwrap();
+    assert_eq!(heading.kind(), "atx_heading");
+}
+
+#[test]
+fn layer_for_node() {
+    let loader = TestLanguageLoader::new();
+    let source = "# Title\n\n```rust\nfn main() {}\n```\n";
+    let syntax = Syntax::new(
+        source.into(),
+        loader.get("markdown"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    let root = syntax.tree().root_node();
+    assert_eq!(syntax.layer_for_node(&root), Some(syntax.root()));
+    let start = source.find("main").unwrap() as u32;
+    let node = syntax.descendant_for_byte_range(start, start + 4).unwrap();
+    assert_eq!(node.kind(), "identifier");
+    assert_eq!(node.text(source.into()), "main");
+    assert_eq!(node.text_str(source), "main");
+    let layer = syntax.layer_for_node(&node).unwrap();
+    assert_eq!(layer, syntax.layer_for_byte_range(start, start + 4));
+    assert_eq!(syntax.layer(layer).language, loader.get("rust"));
+    assert_eq!(syntax.node_language_name(&node, &loader), Some("rust"));
+    assert_eq!(syntax.node_language_name(&root, &loader), Some("markdown"));
+    // Clones have their own identity so their nodes aren't part of the syntax tree.
+    let tree = syntax.tree().clone();
+    assert_eq!(syntax.layer_for_node(&tree.root_node()), None);
+    assert_eq!(syntax.node_language_name(&tree.root_node(), &loader), None);
+}
+
+#[test]
+fn tree_identity() {
+    let loader = TestLanguageLoader::new();
+    let source = "fn main() {}\n";
+    let syntax = Syntax::new(source.into(), loader.get("rust"), PARSE_TIMEOUT, &loader).unwrap();
+    let tree = syntax.tree();
+    assert!(tree.ptr_eq(syntax.tree()));
+    assert_eq!(tree.root_node().tree_id(), tree.id());
+    let clone = tree.clone();
+    assert!(!tree.ptr_eq(&clone));
+    assert_ne!(tree.id(), clone.id());
+    assert!(!clone.contains_node(&tree.root_node()));
+    assert_eq!(clone.root_node().to_sexp(), tree.root_node().to_sexp());
+}
+
+#[test]
+fn injection_include_gaps() {
+    let mut loader = TestLanguageLoader::new();
+    loader.overwrite_injections(
+        "rust",
+        r#"
+((doc_comment) @injection.content
+ (#set! injection.language "markdown")
+ (#set! injection.combined)
+ (#set! injection.combined.include-gaps))"#
+            .to_owned(),
+    );
+    let before_text = "/// a\n/// b\nfn f() {\n\n}\n/// c\n";
+    let mut syntax = Syntax::new(
+        before_text.into(),
+        loader.get("rust"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    let debug_tree = syntax.debug_tree(&loader, true);
+    assert!(
+        debug_tree.contains("markdown Layer(1) [3..9, 9..27, 27..30]"),
+        "{debug_tree}"
+    );
+    assert!(
+        debug_tree.contains("(document (section (paragraph (inline)) (paragraph (inline))))"),
+        "{debug_tree}"
+    );
+
+    // Replace the empty line in the function body with an expression, which is part of a gap, so
+    // that the markdown layer has a single paragraph. The edit doesn't touch any injection so the
+    // layer must be reparsed because of the gap.
+    let after_text = "/// a\n/// b\nfn f() {\nx\n}\n/// c\n";
+    let edit = InputEdit {
+        start_byte: 21,
+        old_end_byte: 21,
+        new_end_byte: 22,
+        start_point: Point::ZERO,
+        old_end_point: Point::ZERO,
+        new_end_point: Point::ZERO,
+    };
+    syntax
+        .update(after_text.into(), PARSE_TIMEOUT, &[edit], &loader)
+        .unwrap();
+    let expected = Syntax::new(
+        after_text.into(),
+        loader.get("rust"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap()
+    .debug_tree(&loader, true);
+    assert_eq!(syntax.debug_tree(&loader, true), expected);
+
+    // With multiple edits the gap must be compared against the position of each edit after the
+    // edits before it were applied. The first edit shifts the gap far enough that the second edit
+    // would not overlap it in the coordinates of the original document.
+    let before_text = "fn g() {}\n/// a\n/// b\nfn f() {\nx\n}\n/// c\n";
+    let mut syntax = Syntax::new(
+        before_text.into(),
+        loader.get("rust"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    let after_text = "fn g_with_a_long_name() {}\n/// a\n/// b\nfn f() {\n\n}\n/// c\n";
+    let edits = [
+        InputEdit {
+            start_byte: 4,
+            old_end_byte: 4,
+            new_end_byte: 21,
+            start_point: Point::ZERO,
+            old_end_point: Point::ZERO,
+            new_end_point: Point::ZERO,
+        },
+        InputEdit {
+            start_byte: 31,
+            old_end_byte: 32,
+            new_end_byte: 31,
+            start_point: Point::ZERO,
+            old_end_point: Point::ZERO,
+            new_end_point: Point::ZERO,
+        },
+    ];
+    syntax
+        .update(after_text.into(), PARSE_TIMEOUT, &edits, &loader)
+        .unwrap();
+    let expected = Syntax::new(
+        after_text.into(),
+        loader.get("rust"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap()
+    .debug_tree(&loader, true);
+    assert_eq!(syntax.debug_tree(&loader, true), expected);
+}
+
+#[test]
+fn unmodified_layer_keeps_injections() {
+    let loader = TestLanguageLoader::new();
+    let before_text = "# Title\n\n```rust\n/// doc\nfn a() {}\n```\n";
+    let mut syntax = Syntax::new(
+        before_text.into(),
+        loader.get("markdown"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    assert!(syntax
+        .debug_tree(&loader, false)
+        .contains("  rust Layer(2) [17..35]\n    markdown Layer(3) [20..25]"));
+
+    // The injection query of rust no longer matches anything, so the doc comment would lose its
+    // markdown injection if the query was run again for the rust layer. The edit only touches
+    // the heading so the rust layer is moved but not modified.
+    let mut stale_loader = TestLanguageLoader::new();
+    stale_loader.overwrite_injections("rust", "; no injections".to_owned());
+    let after_text = "# Title!\n\n```rust\n/// doc\nfn a() {}\n```\n";
+    let edit = InputEdit {
+        start_byte: 7,
+        old_end_byte: 7,
+        new_end_byte: 8,
+        start_point: Point::ZERO,
+        old_end_point: Point::ZERO,
+        new_end_point: Point::ZERO,
+    };
+    syntax
+        .update(after_text.into(), PARSE_TIMEOUT, &[edit], &stale_loader)
+        .unwrap();
+    let expected = Syntax::new(
+        after_text.into(),
+        loader.get("markdown"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap()
+    .debug_tree(&loader, true);
+    assert_eq!(syntax.debug_tree(&loader, true), expected);
+}
+
+#[test]
+fn layer_changes() {
+    let loader = TestLanguageLoader::new();
+    let before_text = "```rust\nfn a() {}\n```\n";
+    let mut syntax = Syntax::new(
+        before_text.into(),
+        loader.get("markdown"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    let rust = syntax.layer_for_byte_range(8, 8);
+    assert_eq!(syntax.layer(rust).language, loader.get("rust"));
+
+    // Adding a code block adds a layer.
+    let block = "\n```css\na {}\n```\n";
+    let after_text = format!("{before_text}{block}");
+    let end = before_text.len() as u32;
+    let edit = InputEdit {
+        start_byte: end,
+        old_end_byte: end,
+        new_end_byte: end + block.len() as u32,
+        start_point: Point::ZERO,
+        old_end_point: Point::ZERO,
+        new_end_point: Point::ZERO,
+    };
+    let changes = syntax
+        .update(after_text.as_str().into(), PARSE_TIMEOUT, &[edit], &loader)
+        .unwrap();
+    let css_start = after_text.find("a {").unwrap() as u32;
+    let css = syntax.layer_for_byte_range(css_start, css_start);
+    assert_eq!(syntax.layer(css).language, loader.get("css"));
+    assert_eq!(
+        changes,
+        LayerChanges {
+            added: vec![css],
+            removed: Vec::new()
+        }
+    );
+
+    // Removing the rust code block removes its layer. The css layer is only moved.
+    let edit = InputEdit {
+        start_byte: 0,
+        old_end_byte: end,
+        new_end_byte: 0,
+        start_point: Point::ZERO,
+        old_end_point: Point::ZERO,
+        new_end_point: Point::ZERO,
+    };
+    let changes = syntax
+        .update(block.into(), PARSE_TIMEOUT, &[edit], &loader)
+        .unwrap();
+    assert_eq!(
+        changes,
+        LayerChanges {
+            added: Vec::new(),
+            removed: vec![rust]
+        }
+    );
+}
+
+#[test]
+fn invalid_edits() {
+    let loader = TestLanguageLoader::new();
+    let before_text = "fn a() {}\nfn b() {}\n";
+    let after_text = "fn ab() {}\nfn b() {}\n";
+    let mut syntax = Syntax::new(
+        before_text.into(),
+        loader.get("rust"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    let edit = |start_byte, old_end_byte, new_end_byte| InputEdit {
+        start_byte,
+        old_end_byte,
+        new_end_byte,
+        start_point: Point::ZERO,
+        old_end_point: Point::ZERO,
+        new_end_point: Point::ZERO,
+    };
+    let mut update =
+        |edits: &[InputEdit]| syntax.update(after_text.into(), PARSE_TIMEOUT, edits, &loader);
+    // The start is after the old end.
+    assert_eq!(update(&[edit(5, 4, 5)]), Err(crate::Error::InvalidEdit(0)));
+    // The old end lies outside of the old document.
+    assert_eq!(
+        update(&[edit(4, 40, 41)]),
+        Err(crate::Error::InvalidEdit(0))
+    );
+    // The new end lies outside of the new document.
+    assert_eq!(
+        update(&[edit(4, 4, 5), edit(20, 20, 21)]),
+        Err(crate::Error::InvalidEdit(1))
+    );
+    // The edits are unsorted.
+    assert_eq!(
+        update(&[edit(10, 10, 10), edit(4, 4, 5)]),
+        Err(crate::Error::InvalidEdit(1))
+    );
+    // The edits overlap.
+    assert_eq!(
+        update(&[edit(2, 6, 6), edit(4, 4, 5)]),
+        Err(crate::Error::InvalidEdit(1))
+    );
+
+    // The rejected edits didn't change the syntax tree.
+    update(&[edit(4, 4, 5)]).unwrap();
+    let expected = Syntax::new(
+        after_text.into(),
+        loader.get("rust"),
+        PARSE_TIMEOUT,
+        &loader,
+    )
+    .unwrap();
+    assert_eq!(
+        syntax.tree().root_node().to_sexp(),
+        expected.tree().root_node().to_sexp()
+    );
+}
+
+#[test]
+fn parse_states() {
+    let loader = TestLanguageLoader::new();
+    let source = "fn a() { let x = 1; }\n";
+    let syntax = Syntax::new(source.into(), loader.get("rust"), PARSE_TIMEOUT, &loader).unwrap();
+    let root = syntax.tree().root_node();
+    let grammar = root.grammar();
+    let start = source.find("let").unwrap() as u32;
+    let keyword = root.descendant_for_byte_range(start, start + 3).unwrap();
+    assert_eq!(keyword.kind(), "let");
+    assert_eq!(
+        grammar.next_state(keyword.parse_state(), keyword.grammar_kind_id()),
+        keyword.next_parse_state()
+    );
+    // The parser continues with the next token in the state reached after the keyword.
+    let pattern = keyword.next_sibling().unwrap();
+    assert_eq!(pattern.kind(), "identifier");
+    assert_eq!(keyword.next_parse_state(), pattern.parse_state());
+}
+
+#[test]
+fn highlight_scopes() {
+    let loader = TestLanguageLoader::new();
+    let config = loader.get_config(loader.get("rust")).unwrap();
+    let scopes = config.highlight_scopes();
+    assert!(scopes.windows(2).all(|scopes| scopes[0] < scopes[1]));
+    assert!(scopes.contains(&"keyword.function"));
+    // Local definitions are highlighted without their `local.definition.` prefix.
+    assert!(scopes.contains(&"variable.parameter"));
+    assert!(!scopes.iter().any(|scope| scope.starts_with("local.")));
+
+    // These are exactly the highlights which the config can be configured with.
+    let mut configured = Vec::new();
+    config.configure(|scope| {
+        if !scope.starts_with("local.") && !scope.starts_with('_') {
+            configured.push(scope.to_owned());
+        }
+        None
+    });
+    configured.sort_unstable();
+    configured.dedup();
+    assert_eq!(scopes, configured);
+}
+
+#[test]
+fn injection_marker_precedence() {
+    let mut loader = TestLanguageLoader::new();
+    loader.overwrite_injections(
+        "rust",
+        r#"
+(call_expression
+  function: (identifier) @_function
+  arguments: (arguments
+    (string_literal (string_content) @injection.language)
+    (string_literal (string_content) @injection.filename)) @injection.content
+  (#eq? @_function "language_then_filename"))
+
+(call_expression
+  function: (identifier) @_function
+  arguments: (arguments
+    (string_literal (string_content) @injection.filename)
+    (string_literal (string_content) @injection.language)) @injection.content
+  (#eq? @_function "filename_then_language"))"#
+            .to_owned(),
+    );
+    // The marker is taken from whichever capture comes last in the match.
+    for (source, language) in [
+        (r#"language_then_filename("markdown", "a.rs");"#, "rust"),
+        (r#"filename_then_language("a.rs", "markdown");"#, "markdown"),
+    ] {
+        let syntax =
+            Syntax::new(source.into(), loader.get("rust"), PARSE_TIMEOUT, &loader).unwrap();
+        let debug_tree = syntax.debug_tree(&loader, false);
+        assert!(
+            debug_tree.contains(&format!("{language} Layer(1)")),
+            "{debug_tree}"
+        );
+    }
+}
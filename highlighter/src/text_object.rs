@@ -1,11 +1,13 @@
-// TODO: rework using query iter
-
-use std::iter;
+use std::ops::RangeBounds;
 
+use hashbrown::HashMap;
 use ropey::RopeSlice;
 
-use crate::TREE_SITTER_MATCH_LIMIT;
-use tree_sitter::{InactiveQueryCursor, Node, Query, RopeInput};
+use crate::config::clamp_match_limit;
+use crate::query_iter::{QueryIter, QueryIterEvent, QueryLoader};
+use crate::{Language, Layer, Syntax, TREE_SITTER_MATCH_LIMIT};
+use tree_sitter::query;
+use tree_sitter::{Capture, Grammar, Node, Query};
 
 #[derive(Debug)]
 pub enum CapturedNode<'a> {
@@ -33,14 +35,40 @@ impl CapturedNode<'_> {
 #[derive(Debug)]
 pub struct TextObjectQuery {
     pub query: Query,
+    match_limit: u32,
 }
 
 impl TextObjectQuery {
-    /// Run the query on the given node and return sub nodes which match given
-    /// capture ("function.inside", "class.around", etc).
+    /// Parses a text object query. Unknown predicates are ignored.
+    pub fn new(grammar: Grammar, source: &str) -> Result<Self, query::ParseError> {
+        let query = Query::new(grammar, source, |_, _| Ok(()))?;
+        Ok(Self {
+            query,
+            match_limit: TREE_SITTER_MATCH_LIMIT,
+        })
+    }
+
+    /// Sets the match limit of the query cursors running this query, see
+    /// [`LanguageConfig::with_match_limit`](crate::config::LanguageConfig::with_match_limit).
+    pub fn with_match_limit(mut self, match_limit: u32) -> Self {
+        self.match_limit = clamp_match_limit(match_limit);
+        self
+    }
+
+    pub fn match_limit(&self) -> u32 {
+        self.match_limit
+    }
+
+    /// Run the text object queries on all layers of the syntax tree within `range` and return
+    /// nodes which match the given capture ("function.inside", "class.around", etc).
     ///
-    /// Captures may contain multiple nodes by using quantifiers (+, *, etc),
-    /// and support for this is partial and could use improvement.
+    /// The query for each layer is obtained from `loader` so that text objects within injections
+    /// are captured with the query of the injected language. All nodes have byte ranges relative
+    /// to the start of the document.
+    ///
+    /// Captures may contain multiple nodes by using quantifiers (+, *, etc).
+    /// Nodes of captures quantified with `+` or `*` are returned as a single
+    /// [`CapturedNode::Grouped`], even if the match only captured one node.
     ///
     /// ```query
     /// (comment)+ @capture
@@ -53,41 +81,82 @@ impl TextObjectQuery {
     /// ) @capture
     /// ```
     pub fn capture_nodes<'a>(
-        &'a self,
         capture_name: &str,
-        node: Node<'a>,
+        syntax: &'a Syntax,
         slice: RopeSlice<'a>,
-        cursor: InactiveQueryCursor,
-    ) -> Option<impl Iterator<Item = CapturedNode<'a>>> {
-        self.capture_nodes_any(&[capture_name], node, slice, cursor)
+        range: impl RangeBounds<u32>,
+        loader: impl Fn(Language) -> Option<&'a TextObjectQuery>,
+    ) -> Vec<CapturedNode<'a>> {
+        Self::capture_nodes_any(&[capture_name], syntax, slice, range, loader)
     }
 
     /// Find the first capture that exists out of all given `capture_names`
     /// and return sub nodes that match this capture.
+    ///
+    /// The capture is resolved separately for the query of each layer.
     pub fn capture_nodes_any<'a>(
-        &'a self,
         capture_names: &[&str],
-        node: Node<'a>,
+        syntax: &'a Syntax,
         slice: RopeSlice<'a>,
-        mut cursor: InactiveQueryCursor,
-    ) -> Option<impl Iterator<Item = CapturedNode<'a>>> {
-        let capture = capture_names
-            .iter()
-            .find_map(|cap| self.query.get_capture(cap))?;
-
-        cursor.set_match_limit(TREE_SITTER_MATCH_LIMIT);
-        let mut cursor = cursor.execute_query(&self.query, &node, RopeInput::new(slice));
-        let capture_node = iter::from_fn(move || {
-            let (mat, _) = cursor.next_matched_node()?;
-            Some(mat.nodes_for_capture(capture).cloned().collect())
-        })
-        .filter_map(move |nodes: Vec<_>| {
-            if nodes.len() > 1 {
-                Some(CapturedNode::Grouped(nodes))
-            } else {
-                nodes.into_iter().map(CapturedNode::Single).next()
+        range: impl RangeBounds<u32>,
+        loader: impl Fn(Language) -> Option<&'a TextObjectQuery>,
+    ) -> Vec<CapturedNode<'a>> {
+        let loader = &loader;
+        let mut captures: HashMap<Language, Option<Capture>> = HashMap::new();
+        let mut captured_nodes = Vec::new();
+        // The nodes of a quantified capture may be interleaved with nodes of other matches so
+        // they are grouped by match. Groups are ordered by the first node in the group.
+        let mut group_for_match: HashMap<(Layer, u32), usize> = HashMap::new();
+        let mut iter = QueryIter::<_, ()>::new(syntax, slice, TextObjectQueryLoader(loader), range);
+        while let Some(event) = iter.next() {
+            let QueryIterEvent::Match(node) = event else {
+                continue;
+            };
+            let language = iter.current_language();
+            let Some(query) = loader(language).map(|query| &query.query) else {
+                continue;
+            };
+            let capture = *captures.entry(language).or_insert_with(|| {
+                capture_names
+                    .iter()
+                    .find_map(|name| query.get_capture(name))
+            });
+            if capture != Some(node.capture) {
+                continue;
+            }
+            if !query
+                .capture_quantifier(node.pattern, node.capture)
+                .is_repeated()
+            {
+                captured_nodes.push(CapturedNode::Single(node.node));
+                continue;
             }
-        });
-        Some(capture_node)
+            let group = *group_for_match
+                .entry((iter.current_layer(), node.match_id))
+                .or_insert_with(|| {
+                    captured_nodes.push(CapturedNode::Grouped(Vec::new()));
+                    captured_nodes.len() - 1
+                });
+            let CapturedNode::Grouped(nodes) = &mut captured_nodes[group] else {
+                unreachable!()
+            };
+            nodes.push(node.node);
+        }
+        captured_nodes
+    }
+}
+
+struct TextObjectQueryLoader<F>(F);
+
+impl<'a, F> QueryLoader<'a> for TextObjectQueryLoader<&F>
+where
+    F: Fn(Language) -> Option<&'a TextObjectQuery>,
+{
+    fn get_query(&mut self, lang: Language) -> Option<&'a Query> {
+        (self.0)(lang).map(|query| &query.query)
+    }
+
+    fn match_limit(&self, lang: Language) -> u32 {
+        (self.0)(lang).map_or(TREE_SITTER_MATCH_LIMIT, |query| query.match_limit)
     }
 }
@@ -20,6 +20,16 @@ impl<'tree> TreeCursor<'tree> {
         }
     }
 
+    /// Moves the cursor back to the root node of the given syntax tree's root layer.
+    ///
+    /// This reuses the cursor's allocation rather than creating a new cursor with
+    /// [`Syntax::walk`].
+    pub fn reset(&mut self, syntax: &'tree Syntax) {
+        self.syntax = syntax;
+        self.current = syntax.root;
+        self.cursor.reset(&syntax.tree().root_node());
+    }
+
     pub fn node(&self) -> Node<'tree> {
         self.cursor.node()
     }
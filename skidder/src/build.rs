@@ -1,18 +1,75 @@
+use std::fmt;
 use std::fs::{self, File};
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync::OnceLock;
 
 use anyhow::{bail, ensure, Context, Result};
 use sha1::{Digest, Sha1};
+use sha2::Sha256;
 use tempfile::TempDir;
 use walkdir::WalkDir;
 
-use crate::{decompress, Metadata, LIB_EXTENSION};
+use crate::{decompress, Metadata, SkidderError, LIB_EXTENSION};
 
 type Checksum = [u8; 20];
-fn is_fresh(grammar_dir: &Path, force: bool) -> Result<(Checksum, bool)> {
+
+/// Why a grammar needs to be (re)compiled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FreshnessReason {
+    /// A rebuild was explicitly requested.
+    Forced,
+    /// The grammar has not been built before (there is no `.BUILD_COOKIE`).
+    NotBuilt,
+    /// The sources changed since the grammar was last built.
+    SourcesChanged,
+    /// The compiled library does not exist.
+    MissingLibrary,
+    /// The compiled library does not match the checksum written when it was built.
+    ChecksumMismatch,
+}
+
+impl fmt::Display for FreshnessReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            FreshnessReason::Forced => "rebuild forced",
+            FreshnessReason::NotBuilt => "not built yet",
+            FreshnessReason::SourcesChanged => "sources changed",
+            FreshnessReason::MissingLibrary => "compiled library is missing",
+            FreshnessReason::ChecksumMismatch => "checksum of compiled library does not match",
+        };
+        f.write_str(reason)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScannerKind {
+    /// A `scanner.c` file.
+    C,
+    /// A `scanner.cc` file.
+    Cpp,
+}
+
+/// What [`crate::build_grammar`] would do for a grammar, see [`crate::build_grammar_plan`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildPlan {
+    /// Whether the compiled library is up to date and would not be rebuilt.
+    pub fresh: bool,
+    /// Why the grammar would be rebuilt, `None` if it is fresh.
+    pub reason: Option<FreshnessReason>,
+    /// The external scanner that would be compiled along with the parser.
+    pub scanner: Option<ScannerKind>,
+    /// Whether the `parser.c` file is compressed and would be decompressed before compiling.
+    pub parser_compressed: bool,
+}
+
+fn is_fresh(
+    grammar_dir: &Path,
+    library: &Path,
+    force: bool,
+    verify_checksum: bool,
+) -> Result<(Checksum, Option<FreshnessReason>)> {
     let src_dir = grammar_dir.join("src");
     let cookie = grammar_dir.join(".BUILD_COOKIE");
     let mut hasher = Sha1::new();
@@ -30,7 +87,7 @@ fn is_fresh(grammar_dir: &Path, force: bool) -> Result<(Checksum, bool)> {
         let Some((_, extension)) = file_name.rsplit_once('.') else {
             continue;
         };
-        if matches!(extension, "h" | "c" | "cc") {
+        if !matches!(extension, "h" | "c" | "cc") {
             continue;
         }
         let path = file.path();
@@ -43,14 +100,83 @@ fn is_fresh(grammar_dir: &Path, force: bool) -> Result<(Checksum, bool)> {
         hasher.update([0, 0, 0, 0]);
     }
 
-    let checksum = hasher.finalize();
-    if force {
-        return Ok((checksum.into(), false));
+    let checksum = hasher.finalize().into();
+    let reason = if force {
+        Some(FreshnessReason::Forced)
+    } else if let Ok(prev_checksum) = fs::read(cookie) {
+        if prev_checksum != checksum {
+            Some(FreshnessReason::SourcesChanged)
+        } else if !library.exists() {
+            Some(FreshnessReason::MissingLibrary)
+        } else if verify_checksum && !self::verify_checksum(library) {
+            Some(FreshnessReason::ChecksumMismatch)
+        } else {
+            None
+        }
+    } else {
+        Some(FreshnessReason::NotBuilt)
+    };
+    Ok((checksum, reason))
+}
+
+fn scanner_kind(src_dir: &Path) -> Option<ScannerKind> {
+    if src_dir.join("scanner.c").exists() {
+        Some(ScannerKind::C)
+    } else if src_dir.join("scanner.cc").exists() {
+        Some(ScannerKind::Cpp)
+    } else {
+        None
     }
-    let Ok(prev_checksum) = fs::read(cookie) else {
-        return Ok((checksum.into(), false));
+}
+
+fn parser_compressed(grammar_name: &str, grammar_dir: &Path) -> Result<bool> {
+    let metadata = Metadata::read(&grammar_dir.join("metadata.json"))
+        .with_context(|| format!("failed to read metadata for {grammar_name}"))?;
+    let Some(parser_definition) = metadata.parser_definition() else {
+        bail!("source directories with parser.c files must have parser definition metadata");
     };
-    Ok((checksum.into(), prev_checksum == checksum[..]))
+    Ok(parser_definition.compressed)
+}
+
+pub fn build_plan(
+    grammar_name: &str,
+    grammar_dir: &Path,
+    force: bool,
+    verify_checksum: bool,
+) -> Result<BuildPlan> {
+    let library = grammar_dir.join(grammar_name).with_extension(LIB_EXTENSION);
+    let (_, reason) = is_fresh(grammar_dir, &library, force, verify_checksum)?;
+    Ok(BuildPlan {
+        fresh: reason.is_none(),
+        reason,
+        scanner: scanner_kind(&grammar_dir.join("src")),
+        parser_compressed: parser_compressed(grammar_name, grammar_dir)?,
+    })
+}
+
+/// The path of the file storing the SHA-256 checksum of a compiled grammar library.
+fn checksum_path(library: &Path) -> PathBuf {
+    library.with_extension(format!("{LIB_EXTENSION}.sha256"))
+}
+
+fn library_checksum(library: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    File::open(library)
+        .and_then(|mut file| io::copy(&mut file, &mut hasher))
+        .with_context(|| format!("failed to read {}", library.display()))?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+/// Checks that the compiled grammar library matches the checksum written when it was built.
+pub(crate) fn verify_checksum(library: &Path) -> bool {
+    let Ok(expected) = fs::read_to_string(checksum_path(library)) else {
+        return false;
+    };
+    library_checksum(library).is_ok_and(|checksum| checksum == expected.trim())
 }
 
 #[cfg(not(windows))]
@@ -140,7 +266,12 @@ impl CompilerCommand {
     }
 }
 
-pub fn build_grammar(grammar_name: &str, grammar_dir: &Path, force: bool) -> Result<()> {
+pub fn build_grammar(
+    grammar_name: &str,
+    grammar_dir: &Path,
+    force: bool,
+    verify_checksum: bool,
+) -> Result<()> {
     let src_dir = grammar_dir.join("src");
     let mut parser = src_dir.join("parser.c");
     ensure!(
@@ -148,17 +279,13 @@ pub fn build_grammar(grammar_name: &str, grammar_dir: &Path, force: bool) -> Res
         "failed to compile {grammar_name}: {} not found!",
         parser.display()
     );
-    let (hash, fresh) = is_fresh(grammar_dir, force)?;
-    if fresh {
+    let library = grammar_dir.join(grammar_name).with_extension(LIB_EXTENSION);
+    let (hash, reason) = is_fresh(grammar_dir, &library, force, verify_checksum)?;
+    if reason.is_none() {
         return Ok(());
     }
     let build_dir = TempDir::new().context("failed to create temporary build directory")?;
-    let metadata = Metadata::read(&grammar_dir.join("metadata.json"))
-        .with_context(|| format!("failed to read metadata for {grammar_name}"))?;
-    let Some(parser_definition) = metadata.parser_definition() else {
-        bail!("source directories with parser.c files must have parser definition metadata");
-    };
-    if parser_definition.compressed {
+    if parser_compressed(grammar_name, grammar_dir)? {
         let decompressed_parser = build_dir.path().join(format!("{grammar_name}.c"));
         let mut dst = File::create(&decompressed_parser).with_context(|| {
             format!(
@@ -168,7 +295,7 @@ pub fn build_grammar(grammar_name: &str, grammar_dir: &Path, force: bool) -> Res
         })?;
         File::open(&parser)
             .map_err(anyhow::Error::from)
-            .and_then(|mut reader| decompress(&mut reader, &mut dst))
+            .and_then(|mut reader| Ok(decompress(&mut reader, &mut dst)?))
             .with_context(|| {
                 format!("failed to decompress parser {}", build_dir.path().display())
             })?;
@@ -176,20 +303,15 @@ pub fn build_grammar(grammar_name: &str, grammar_dir: &Path, force: bool) -> Res
     }
     let mut commands = Vec::new();
     let mut obj_files = Vec::new();
-    if src_dir.join("scanner.c").exists() {
-        let scanner_cmd = CompilerCommand::Build.setup(
-            build_dir.path(),
-            &src_dir,
-            &src_dir.join("scanner.c"),
-            SCANNER_OBJECT,
-        );
-        obj_files.push(SCANNER_OBJECT);
-        commands.push(scanner_cmd)
-    } else if src_dir.join("scanner.cc").exists() {
+    if let Some(scanner) = scanner_kind(&src_dir) {
+        let scanner_file = match scanner {
+            ScannerKind::C => "scanner.c",
+            ScannerKind::Cpp => "scanner.cc",
+        };
         let scanner_cmd = CompilerCommand::Build.setup(
             build_dir.path(),
             &src_dir,
-            &src_dir.join("scanner.cc"),
+            &src_dir.join(scanner_file),
             SCANNER_OBJECT,
         );
         obj_files.push(SCANNER_OBJECT);
@@ -207,22 +329,26 @@ pub fn build_grammar(grammar_name: &str, grammar_dir: &Path, force: bool) -> Res
     for mut cmd in commands {
         let output = cmd.output().context("Failed to execute compiler")?;
         if !output.status.success() {
-            bail!(
-                "Parser compilation failed.\nStdout: {}\nStderr: {}",
-                String::from_utf8_lossy(&output.stdout),
-                String::from_utf8_lossy(&output.stderr)
-            );
+            // MSVC prints its diagnostics to stdout.
+            let mut stderr = String::from_utf8_lossy(&output.stdout).into_owned();
+            stderr.push_str(&String::from_utf8_lossy(&output.stderr));
+            bail!(SkidderError::CompileFailed {
+                grammar: grammar_name.to_owned(),
+                stderr,
+            });
         }
     }
     let from = build_dir.path().join(lib_name);
-    let to = grammar_dir.join(grammar_name).with_extension(LIB_EXTENSION);
-    fs::copy(&from, &to).with_context(|| {
+    fs::copy(&from, &library).with_context(|| {
         format!(
             "failed to copy compiled library from {} to {}",
             from.display(),
-            to.display()
+            library.display()
         )
     })?;
+    let checksum_path = checksum_path(&library);
+    fs::write(&checksum_path, library_checksum(&library)?)
+        .with_context(|| format!("failed to write {}", checksum_path.display()))?;
     let _ = fs::write(grammar_dir.join(".BUILD_COOKIE"), hash);
     Ok(())
 }
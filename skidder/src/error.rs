@@ -0,0 +1,92 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+
+/// The error returned by the public functions of skidder.
+#[derive(Debug)]
+pub enum SkidderError {
+    /// None of the configured repositories contains the grammar.
+    GrammarNotFound(String),
+    /// A git command returned a non-zero exit code.
+    GitFailed {
+        dir: PathBuf,
+        args: Vec<String>,
+        status: ExitStatus,
+    },
+    /// The compiler failed to compile a grammar.
+    CompileFailed { grammar: String, stderr: String },
+    /// Some grammars failed to build, see [`crate::build_all_grammars`]. Contains the name of
+    /// each grammar that failed together with its error.
+    BuildFailed(Vec<(String, SkidderError)>),
+    /// A `metadata.json` file could not be parsed.
+    MetadataInvalid {
+        path: PathBuf,
+        error: serde_json::Error,
+    },
+    /// An IO operation failed. `context` describes the operation.
+    Io { context: String, error: io::Error },
+    /// Any other error.
+    Other(anyhow::Error),
+}
+
+impl fmt::Display for SkidderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GrammarNotFound(grammar) => {
+                write!(
+                    f,
+                    "grammar {grammar} not found in any configured repository"
+                )
+            }
+            Self::GitFailed { dir, args, status } => write!(
+                f,
+                "git {} returned non-zero exit-code in {}: {status}",
+                args.join(" "),
+                dir.display()
+            ),
+            Self::CompileFailed { grammar, stderr } => {
+                write!(f, "failed to compile {grammar}:\n{stderr}")
+            }
+            Self::BuildFailed(failed) => {
+                let names: Vec<_> = failed.iter().map(|(name, _)| name.as_str()).collect();
+                write!(f, "failed to build grammars {}", names.join(", "))
+            }
+            Self::MetadataInvalid { path, .. } => {
+                write!(f, "invalid metadata.json file at {}", path.display())
+            }
+            Self::Io { context, .. } => f.write_str(context),
+            // Include the context chain since `source` can't return the inner errors.
+            Self::Other(err) => write!(f, "{err:#}"),
+        }
+    }
+}
+
+impl std::error::Error for SkidderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::MetadataInvalid { error, .. } => Some(error),
+            Self::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Recovers the structured error from an error returned by the internal functions, which use
+/// `anyhow` and create a [`SkidderError`] where a failure is worth matching on.
+impl From<anyhow::Error> for SkidderError {
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<SkidderError>() {
+            Ok(err) => return err,
+            Err(err) => err,
+        };
+        let context = (err.chain().count() > 1).then(|| err.to_string());
+        match err.downcast::<io::Error>() {
+            Ok(error) => Self::Io {
+                context: context.unwrap_or_else(|| "IO error".to_owned()),
+                error,
+            },
+            Err(err) => Self::Other(err),
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use serde::Deserialize;
+use walkdir::WalkDir;
+
+use crate::license::{detect_license, is_known_license_expression, DetectedLicense};
+use crate::{Metadata, ParserDefinition, SkidderError};
+
+const LICENSE_FILE_NAMES: &[&str] = &["LICENSE", "LICENSE.txt", "LICENCE", "LICENCE", "COPYING"];
+
+/// The zstd compression level used for `parser.c` and `grammar.json` by default.
+pub const DEFAULT_COMPRESSION_LEVEL: u32 = 22;
+
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    /// Whether to import the `.scm` query files of the grammar.
+    pub import_queries: bool,
+    /// Whether to (re)generate the `metadata.json` file from the git repository of the grammar.
+    pub metadata: bool,
+    /// Only import the `.scm` query files, leaving the parser, the license and the metadata of
+    /// an existing import untouched. Implies `import_queries`.
+    pub queries_only: bool,
+    /// The zstd compression level used to compress `parser.c` and `grammar.json`.
+    pub compression_level: u32,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            import_queries: false,
+            metadata: false,
+            queries_only: false,
+            compression_level: DEFAULT_COMPRESSION_LEVEL,
+        }
+    }
+}
+
+/// Finds the grammars in `src_path`, returning the name and the directory of each grammar
+/// sorted by name.
+///
+/// If `src_path` contains a `src/parser.c` it is a single grammar named after the directory.
+/// Otherwise each subdirectory containing a `src/parser.c` is a grammar: some grammar
+/// repositories generate multiple parsers from one source tree, for example
+/// `tree-sitter-typescript` contains the `typescript` and `tsx` grammars which share the external
+/// scanner in the `common` directory.
+///
+/// Each of these grammars should be imported separately with [`import`], which copies the
+/// shared `common` directory into every imported grammar. The imported grammars are independent
+/// of each other: each gets its own `metadata.json` with the same `repo` and `rev`, and the
+/// shared scanner is compiled separately for each grammar since the symbols it exports are
+/// named after the grammar.
+pub fn grammar_dirs(src_path: &Path) -> Result<Vec<(String, PathBuf)>, SkidderError> {
+    let name = |path: &Path| {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .map(str::to_owned)
+            .with_context(|| format!("invalid grammar directory {}", path.display()))
+    };
+    if src_path.join("src").join("parser.c").exists() {
+        return Ok(vec![(name(src_path)?, src_path.to_owned())]);
+    }
+    let mut grammars = Vec::new();
+    let entries =
+        fs::read_dir(src_path).with_context(|| format!("failed to read {}", src_path.display()))?;
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("failed to read {}", src_path.display()))?
+            .path();
+        if path.join("src").join("parser.c").exists() {
+            grammars.push((name(&path)?, path));
+        }
+    }
+    grammars.sort();
+    Ok(grammars)
+}
+
+/// Imports the grammar `grammar_name` from the grammar repository at `src_path` into the
+/// `dst_path` directory.
+///
+/// `src_path` must contain a generated `src/parser.c` unless [`ImportOptions::queries_only`] is
+/// set, see [`grammar_dirs`] for source trees which contain multiple grammars. Problems which
+/// don't prevent the import, like a license which couldn't be identified, are returned as
+/// warnings in the [`ImportReport`] rather than printed.
+pub fn import(
+    src_path: &Path,
+    grammar_name: &str,
+    dst_path: &Path,
+    options: &ImportOptions,
+) -> Result<ImportReport, SkidderError> {
+    let mut warnings = Vec::new();
+    let metadata = import_impl(src_path, grammar_name, dst_path, options, &mut warnings)?;
+    Ok(ImportReport { metadata, warnings })
+}
+
+/// The outcome of a successful [`import`].
+#[derive(Debug)]
+pub struct ImportReport {
+    /// The metadata written to `dst_path/metadata.json` if [`ImportOptions::metadata`] is set.
+    pub metadata: Option<Metadata>,
+    /// Problems found while importing the grammar, for example a missing LICENSE file.
+    pub warnings: Vec<String>,
+}
+
+fn import_impl(
+    src_path: &Path,
+    grammar_name: &str,
+    dst_path: &Path,
+    options: &ImportOptions,
+    warnings: &mut Vec<String>,
+) -> Result<Option<Metadata>> {
+    let parser_path = src_path.join("src").join("parser.c");
+    ensure!(
+        options.queries_only || parser_path.exists(),
+        "no parser.c found at {}",
+        parser_path.display()
+    );
+    let mut dst_path = dst_path.to_owned();
+    fs::create_dir_all(&dst_path)
+        .with_context(|| format!("failed to create {}", dst_path.display()))?;
+    for dir in ["src", "../common"] {
+        let src_path = src_path.join(dir);
+        if !src_path.exists() {
+            continue;
+        }
+        dst_path.push(dir.strip_prefix("../").unwrap_or(dir));
+        for file in WalkDir::new(&src_path) {
+            let file = file?;
+            if !file.file_type().is_file() {
+                continue;
+            }
+            let Some(file_name) = file.file_name().to_str() else {
+                continue;
+            };
+            let Some((_, extension)) = file_name.rsplit_once('.') else {
+                continue;
+            };
+            if options.queries_only {
+                if extension != "scm" {
+                    continue;
+                }
+            } else if !(matches!(extension, "h" | "c" | "cc")
+                || extension == "scm" && options.import_queries
+                || file_name == "grammar.json")
+                || file_name.starts_with("parser_abi") && extension == "c"
+            {
+                continue;
+            }
+            let relative_path = file.path().strip_prefix(&src_path).unwrap();
+            let dst_path = dst_path.join(relative_path);
+            fs::create_dir_all(dst_path.parent().unwrap()).with_context(|| {
+                format!("failed to create {}", dst_path.parent().unwrap().display())
+            })?;
+            let res = if matches!(file_name, "parser.c" | "grammar.json")
+                && file.path().parent() == Some(&src_path)
+                && dir == "src"
+            {
+                compress(file.path(), &dst_path, options.compression_level)?;
+                continue;
+            } else if matches!(extension, "h" | "c" | "cc")
+                && src_path.join("../../common").exists()
+            {
+                fs::read_to_string(file.path()).and_then(|contents| {
+                    let contents = contents.replace("../../common/", "../common/");
+                    fs::write(&dst_path, contents)
+                })
+            } else {
+                fs::copy(file.path(), &dst_path).map(|_| ())
+            };
+            res.with_context(|| {
+                format!(
+                    "failed to copy {} to {}",
+                    file.path().display(),
+                    dst_path.display()
+                )
+            })?;
+        }
+        dst_path.pop();
+    }
+    if options.queries_only {
+        return Ok(None);
+    }
+    let license_file = LICENSE_FILE_NAMES
+        .iter()
+        .map(|name| src_path.join(name))
+        .find(|src_path| src_path.exists());
+    let mut license = None;
+    if let Some(license_file) = license_file {
+        let license_file_content = fs::read_to_string(&license_file)
+            .with_context(|| format!("failed to read {}", license_file.display()))?;
+        fs::write(dst_path.join("LICENSE"), &license_file_content)
+            .with_context(|| format!("failed to write {}", dst_path.join("LICENSE").display()))?;
+        match detect_license(&license_file_content) {
+            DetectedLicense::Found(expression) => license = Some(expression),
+            DetectedLicense::Ambiguous(candidates) => warnings.push(format!(
+                "failed to identify license in {}, found {}",
+                license_file.display(),
+                candidates.join(", ")
+            )),
+            DetectedLicense::Unknown => warnings.push(format!(
+                "failed to identify license in {}",
+                license_file.display()
+            )),
+        }
+    } else {
+        warnings.push(format!("{grammar_name} does not have a LICENSE file!"));
+    }
+    if !options.metadata {
+        return Ok(None);
+    }
+
+    let metadata_path = dst_path.join("metadata.json");
+    let rev = git_output(&["rev-parse", "HEAD"], src_path)
+        .with_context(|| format!("failed to obtain git revision at {}", src_path.display()))?;
+    let repo = git_output(&["remote", "get-url", "origin"], src_path)
+        .with_context(|| format!("failed to obtain git remote at {}", src_path.display()))?;
+    let package_metadata: Option<PackageJson> = fs::read_to_string(src_path.join("package.json"))
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok());
+    if let Some(package_metadata) = package_metadata {
+        // Unknown licenses are kept as they are since they may still be valid SPDX expressions.
+        if !is_known_license_expression(&package_metadata.license) {
+            warnings.push(format!(
+                "unknown license {:?} in package.json",
+                package_metadata.license
+            ));
+        }
+        match &license {
+            Some(license) if license != &package_metadata.license => warnings.push(format!(
+                "license in package identifier differs from detected license {license} != {}",
+                &package_metadata.license
+            )),
+            _ => license = Some(package_metadata.license),
+        }
+    }
+
+    let old_metadata = Metadata::read(&metadata_path)
+        .ok()
+        .and_then(Metadata::parser_definition)
+        .filter(|old_meta| old_meta.repo == repo && !old_meta.license.is_empty());
+
+    if let Some(old_metadata) = &old_metadata {
+        match &license {
+            Some(license) => {
+                if license != &old_metadata.license {
+                    warnings.push(format!(
+                        "license has changed {} => {license}",
+                        old_metadata.license
+                    ));
+                }
+            }
+            None => {
+                warnings.push(format!(
+                    "couldn't determine license for {grammar_name}, keeping {:?}",
+                    old_metadata.license
+                ));
+                license = Some(old_metadata.license.clone())
+            }
+        }
+    }
+    if license.is_none() {
+        warnings.push(format!("couldn't determine license for {grammar_name}"));
+    }
+
+    let metadata = Metadata::ParserDefinition(ParserDefinition {
+        repo,
+        rev,
+        license: license.unwrap_or_default(),
+        compressed: true,
+    });
+    metadata.write(&metadata_path).with_context(|| {
+        format!(
+            "failed to write metadata.json to {}",
+            metadata_path.display()
+        )
+    })?;
+    Ok(Some(metadata))
+}
+
+#[derive(Deserialize)]
+struct PackageJson {
+    license: String,
+}
+
+fn git_output(args: &[&str], dir: &Path) -> Result<String> {
+    let res = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .context("failed to invoke git")?;
+    if !res.status.success() {
+        let _ = io::stdout().write_all(&res.stdout);
+        let _ = io::stderr().write_all(&res.stderr);
+        bail!("git returned non-zero exit-code: {}", res.status);
+    }
+    String::from_utf8(res.stdout)
+        .context("git returned invalid utf8")
+        .map(|output| output.trim_end().to_string())
+}
+
+/// Compresses `src` into `dst` with the `zstd` command line tool so that it can be read with
+/// [`crate::decompress`].
+pub fn compress(src: &Path, dst: &Path, compression_level: u32) -> Result<(), SkidderError> {
+    let success = Command::new("zstd")
+        .arg("--ultra")
+        .arg(format!("-{compression_level}"))
+        .args(["-f", "-o"])
+        .arg(dst)
+        .arg(src)
+        .status()
+        .with_context(|| format!("failed to compress {}", src.display()))?
+        .success();
+    if !success {
+        return Err(SkidderError::Other(anyhow!(
+            "failed to compress {}",
+            src.display()
+        )));
+    }
+    Ok(())
+}
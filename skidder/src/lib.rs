@@ -2,7 +2,7 @@ use std::fs::File;
 use std::io::{BufReader, Read, Seek, SeekFrom, Write};
 use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::process::{Command, ExitStatus, Stdio};
 use std::sync::atomic::{self, AtomicUsize};
 use std::sync::Mutex;
 use std::time::Duration;
@@ -21,15 +21,103 @@ const LIB_EXTENSION: &str = "so";
 const LIB_EXTENSION: &str = "dll";
 
 mod build;
+mod error;
+mod import;
+mod license;
+
+pub use build::{BuildPlan, FreshnessReason, ScannerKind};
+pub use error::SkidderError;
+pub use import::{
+    compress, grammar_dirs, import, ImportOptions, ImportReport, DEFAULT_COMPRESSION_LEVEL,
+};
+pub use license::{detect_license, is_known_license_expression, DetectedLicense};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
+    /// The repositories to look for grammars in.
+    ///
+    /// If multiple repositories provide a grammar with the same name, the grammar of the
+    /// repository listed first takes precedence.
     pub repos: Vec<Repo>,
     pub index: PathBuf,
+    #[serde(default)]
     pub verbose: bool,
+    /// Whether to verify compiled grammar libraries against the checksum written when they were
+    /// built. Libraries which fail verification are treated as stale.
+    #[serde(default)]
+    pub verify_checksums: bool,
+    /// A directory of queries laid out as `<grammar>/<name>.scm`, like the `queries` directory
+    /// of Helix or nvim-treesitter. Queries found here take precedence over the queries in the
+    /// grammar directories. See [`Config::query_path`].
+    #[serde(default)]
+    pub queries_path: Option<PathBuf>,
 }
 
 impl Config {
+    /// Reads a config from a JSON file or, with the `toml` feature, from a TOML file if the file
+    /// has a `.toml` extension.
+    ///
+    /// Relative paths of the `index` and of local repositories are resolved relative to the
+    /// directory containing the config file. The config must contain at least one repository and
+    /// if any git repositories are configured the `index` must be writable.
+    pub fn from_path(path: &Path) -> Result<Config, SkidderError> {
+        Ok(Self::from_path_impl(path)?)
+    }
+
+    fn from_path_impl(path: &Path) -> Result<Config> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let is_toml = path.extension().is_some_and(|ext| ext == "toml");
+        let mut config: Config = if is_toml {
+            #[cfg(feature = "toml")]
+            {
+                toml::from_str(&contents)
+                    .with_context(|| format!("invalid config file {}", path.display()))?
+            }
+            #[cfg(not(feature = "toml"))]
+            bail!(
+                "reading {} requires the `toml` feature of skidder",
+                path.display()
+            )
+        } else {
+            serde_json::from_str(&contents)
+                .with_context(|| format!("invalid config file {}", path.display()))?
+        };
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        config.index = base.join(&config.index);
+        if let Some(queries_path) = &mut config.queries_path {
+            *queries_path = base.join(&*queries_path);
+        }
+        for repo in &mut config.repos {
+            if let Repo::Local { path } = repo {
+                *path = base.join(&*path);
+            }
+        }
+
+        ensure!(
+            !config.repos.is_empty(),
+            "no repositories configured in {}",
+            path.display()
+        );
+        if config
+            .repos
+            .iter()
+            .any(|repo| matches!(repo, Repo::Git { .. }))
+        {
+            fs::create_dir_all(&config.index).with_context(|| {
+                format!(
+                    "failed to create index directory {}",
+                    config.index.display()
+                )
+            })?;
+            tempfile::tempfile_in(&config.index).with_context(|| {
+                format!("index directory {} is not writable", config.index.display())
+            })?;
+        }
+        Ok(config)
+    }
+
     pub fn compiled_parser_path(&self, grammar: &str) -> Option<(String, PathBuf)> {
         let (repo, metadata) = self.repos.iter().find_map(|repo| {
             let metadata = repo.read_metadata(self, grammar).ok()?;
@@ -46,7 +134,21 @@ impl Config {
             .join(&grammar)
             .join(&grammar)
             .with_extension(LIB_EXTENSION);
-        parser.exists().then_some((grammar, parser))
+        let valid = parser.exists() && (!self.verify_checksums || build::verify_checksum(&parser));
+        valid.then_some((grammar, parser))
+    }
+
+    /// Returns the index into [`Config::repos`] and the directory of every repository that
+    /// provides `grammar`, in order of precedence.
+    ///
+    /// The first entry is the grammar returned by [`Config::grammar_dir`].
+    pub fn find_grammar(&self, grammar: &str) -> Vec<(usize, PathBuf)> {
+        self.repos
+            .iter()
+            .enumerate()
+            .filter(|(_, repo)| repo.has_grammar(self, grammar))
+            .map(|(i, repo)| (i, repo.dir(self).join(grammar)))
+            .collect()
     }
 
     pub fn grammar_dir(&self, grammar: &str) -> Option<PathBuf> {
@@ -56,6 +158,23 @@ impl Config {
         })
     }
 
+    /// Returns the path of the query `name` (for example `highlights`) of `grammar`.
+    ///
+    /// `<queries_path>/<grammar>/<name>.scm` is used if [`Config::queries_path`] is set and the
+    /// file exists. Otherwise this falls back to `<name>.scm` in the [`Config::grammar_dir`].
+    /// Returns `None` if neither file exists.
+    pub fn query_path(&self, grammar: &str, name: &str) -> Option<PathBuf> {
+        let file_name = format!("{name}.scm");
+        self.queries_path
+            .iter()
+            .map(|queries_path| queries_path.join(grammar).join(&file_name))
+            .chain(
+                self.grammar_dir(grammar)
+                    .map(|grammar_dir| grammar_dir.join(&file_name)),
+            )
+            .find(|path| path.exists())
+    }
+
     fn git(&self, args: &[&str], dir: &Path) -> Result<()> {
         let mut cmd = Command::new("git");
         cmd.args(args).current_dir(dir);
@@ -73,7 +192,7 @@ impl Config {
             res.status
         };
         if !status.success() {
-            bail!("git returned non-zero exit-code: {status}");
+            bail!(git_failed(args, dir, status));
         }
         Ok(())
     }
@@ -110,18 +229,32 @@ impl Config {
         if !res.status.success() {
             let _ = io::stdout().write_all(&res.stdout);
             let _ = io::stderr().write_all(&res.stderr);
-            bail!("git returned non-zero exit-code: {}", res.status);
+            bail!(git_failed(args, dir, res.status));
         }
         String::from_utf8(res.stdout).context("git returned invalid utf8")
     }
 }
 
+fn git_failed(args: &[&str], dir: &Path, status: ExitStatus) -> SkidderError {
+    SkidderError::GitFailed {
+        dir: dir.to_owned(),
+        args: args.iter().map(|&arg| arg.to_owned()).collect(),
+        status,
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Repo {
     Git {
         name: String,
         remote: String,
         branch: String,
+        /// A revision to pin the repository to instead of tracking the tip of `branch`.
+        ///
+        /// The revision must be reachable from `branch` unless it is a full commit hash which can
+        /// be fetched directly.
+        #[serde(default)]
+        rev: Option<String>,
     },
     Local {
         path: PathBuf,
@@ -143,12 +276,13 @@ impl Repo {
             .exists()
     }
 
-    pub fn read_metadata(&self, config: &Config, grammar: &str) -> Result<Metadata> {
+    pub fn read_metadata(&self, config: &Config, grammar: &str) -> Result<Metadata, SkidderError> {
         let path = self.dir(config).join(grammar).join("metadata.json");
-        Metadata::read(&path).with_context(|| format!("failed to read metadata for {grammar}"))
+        Ok(Metadata::read(&path)
+            .with_context(|| format!("failed to read metadata for {grammar}"))?)
     }
 
-    pub fn list_grammars(&self, config: &Config) -> Result<Vec<PathBuf>> {
+    pub fn list_grammars(&self, config: &Config) -> Result<Vec<PathBuf>, SkidderError> {
         let dir = self.dir(config);
         if !dir.exists() {
             return Ok(vec![]);
@@ -172,19 +306,53 @@ impl Repo {
                 Ok(metadata.parser_definition().map(|_| dent.path()))
             })
             .filter_map(|res| res.transpose())
-            .collect()
+            .collect::<Result<_>>()
+            .map_err(SkidderError::from)
+    }
+
+    pub fn fetch(&self, config: &Config, update: bool) -> Result<(), SkidderError> {
+        Ok(self.fetch_impl(config, update)?)
     }
 
-    pub fn fetch(&self, config: &Config, update: bool) -> Result<()> {
-        let Repo::Git { remote, branch, .. } = self else {
+    fn fetch_impl(&self, config: &Config, update: bool) -> Result<()> {
+        let Repo::Git {
+            remote,
+            branch,
+            rev,
+            ..
+        } = self
+        else {
             return Ok(());
         };
         let dir = self.dir(config);
         if dir.join(".git").exists() {
+            if let Some(rev) = rev {
+                // A pinned revision never needs to be updated once it is checked out.
+                let head = config.git_output(&["rev-parse", "HEAD"], &dir)?;
+                let pinned = config
+                    .git_output(
+                        &[
+                            "rev-parse",
+                            "--verify",
+                            "--quiet",
+                            &format!("{rev}^{{commit}}"),
+                        ],
+                        &dir,
+                    )
+                    .ok();
+                if pinned.is_some_and(|pinned| pinned.trim() == head.trim()) {
+                    return Ok(());
+                }
+            }
             let current_branch = config.git_output(&["rev-parse", "--abbrev-ref", "HEAD"], &dir)?;
-            let switch_branch = current_branch != *branch;
+            let switch_branch = current_branch.trim() != *branch;
             if !update && !switch_branch {
-                return Ok(());
+                // The pinned revision changed since the repository was checked out. It must be
+                // checked out even if the repository isn't updated otherwise.
+                return match rev {
+                    Some(rev) => checkout_rev(config, &dir, rev),
+                    None => Ok(()),
+                };
             }
             if switch_branch {
                 config.git(&["reset", "--hard"], &dir)?;
@@ -207,8 +375,10 @@ impl Repo {
                 // true alternative.
                 config.git(&["checkout", branch], &dir)?;
             }
-            config.git(&["reset", "--hard", &format!("origin/{branch}")], &dir)?;
-            return Ok(());
+            return match rev {
+                Some(rev) => checkout_rev(config, &dir, rev),
+                None => config.git(&["reset", "--hard", &format!("origin/{branch}")], &dir),
+            };
         }
         let _ = fs::create_dir_all(&dir);
         ensure!(dir.exists(), "failed to create directory {}", dir.display());
@@ -227,21 +397,74 @@ impl Repo {
                 ".",
             ],
             &dir,
+        )?;
+        if let Some(rev) = rev {
+            checkout_rev(config, &dir, rev)?;
+        }
+        Ok(())
+    }
+}
+
+/// Resets the repository at `dir` to `rev` after the branch was fetched.
+fn checkout_rev(config: &Config, dir: &Path, rev: &str) -> Result<()> {
+    // The branch was cloned with `--single-branch` so the revision may not have been fetched
+    // if it is not reachable from the branch. Commit hashes can be fetched directly, the partial
+    // clone filter is inherited from the clone so this only fetches the blobs that are needed
+    // for the checkout.
+    if config
+        .git_output(
+            &[
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                &format!("{rev}^{{commit}}"),
+            ],
+            dir,
         )
+        .is_err()
+    {
+        config.git(&["fetch", "origin", rev], dir)?;
     }
+    config.git(&["reset", "--hard", rev], dir)
+}
+
+/// Clones or updates the git repositories of the config, see [`Repo::fetch`].
+///
+/// No progress is reported, use [`fetch_with_progress`] to show which repository is being
+/// fetched.
+pub fn fetch(config: &Config, update_existing_grammar: bool) -> Result<(), SkidderError> {
+    fetch_with_progress(config, update_existing_grammar, |_| ())
 }
 
-pub fn fetch(config: &Config, update_existing_grammar: bool) -> Result<()> {
+/// Fetches the repositories like [`fetch`] while calling `on_fetch` with the name of each git
+/// repository before it is cloned or updated.
+pub fn fetch_with_progress(
+    config: &Config,
+    update_existing_grammar: bool,
+    mut on_fetch: impl FnMut(&str),
+) -> Result<(), SkidderError> {
     for repo in &config.repos {
+        if let Repo::Git { name, .. } = repo {
+            on_fetch(name);
+        }
         repo.fetch(config, update_existing_grammar)?
     }
     Ok(())
 }
 
-pub fn build_grammar(config: &Config, grammar: &str, force_rebuild: bool) -> Result<PathBuf> {
+pub fn build_grammar(
+    config: &Config,
+    grammar: &str,
+    force_rebuild: bool,
+) -> Result<PathBuf, SkidderError> {
     for repo in &config.repos {
         if repo.has_grammar(config, grammar) {
-            build::build_grammar(grammar, &repo.dir(config).join(grammar), force_rebuild)?;
+            build::build_grammar(
+                grammar,
+                &repo.dir(config).join(grammar),
+                force_rebuild,
+                config.verify_checksums,
+            )?;
             return Ok(repo
                 .dir(config)
                 .join(grammar)
@@ -249,24 +472,98 @@ pub fn build_grammar(config: &Config, grammar: &str, force_rebuild: bool) -> Res
                 .with_extension(LIB_EXTENSION));
         }
     }
-    bail!("grammar not found in any configured repository")
+    Err(SkidderError::GrammarNotFound(grammar.to_owned()))
+}
+
+/// Reports whether [`build_grammar`] would compile the grammar and why, without invoking the
+/// compiler.
+pub fn build_grammar_plan(
+    config: &Config,
+    grammar: &str,
+    force_rebuild: bool,
+) -> Result<BuildPlan, SkidderError> {
+    let Some(grammar_dir) = config.grammar_dir(grammar) else {
+        return Err(SkidderError::GrammarNotFound(grammar.to_owned()));
+    };
+    let plan = build::build_plan(
+        grammar,
+        &grammar_dir,
+        force_rebuild,
+        config.verify_checksums,
+    )?;
+    Ok(plan)
 }
 
-pub fn list_grammars(config: &Config) -> Result<Vec<PathBuf>> {
+pub fn list_grammars(config: &Config) -> Result<Vec<PathBuf>, SkidderError> {
+    Ok(list_grammars_by_repo(config)?
+        .into_iter()
+        .map(|(_, path)| path)
+        .collect())
+}
+
+/// Lists the grammars of all repositories like [`list_grammars`] together with the index into
+/// [`Config::repos`] of the repository the grammar is taken from.
+///
+/// If multiple repositories provide a grammar with the same name, only the grammar of the
+/// repository with the highest precedence (see [`Config::repos`]) is listed.
+pub fn list_grammars_by_repo(config: &Config) -> Result<Vec<(usize, PathBuf)>, SkidderError> {
     let mut res = Vec::new();
-    for repo in &config.repos {
-        res.append(&mut repo.list_grammars(config)?)
+    for (i, repo) in config.repos.iter().enumerate() {
+        res.extend(
+            repo.list_grammars(config)?
+                .into_iter()
+                .map(|path| (i, path)),
+        )
     }
-    res.sort_by(|path1, path2| path1.file_name().cmp(&path2.file_name()));
-    res.dedup_by(|path1, path2| path1.file_name() == path2.file_name());
+    // The sort is stable so the grammar from the first repository is kept by `dedup_by`.
+    res.sort_by(|(_, path1), (_, path2)| path1.file_name().cmp(&path2.file_name()));
+    res.dedup_by(|(_, path1), (_, path2)| path1.file_name() == path2.file_name());
     Ok(res)
 }
 
+/// Builds all grammars and returns the number of grammars that were built or already up to date.
+///
+/// The errors of grammars that fail to build are printed above the progress bar as they happen
+/// and returned as [`SkidderError::BuildFailed`].
 pub fn build_all_grammars(
     config: &Config,
     force_rebuild: bool,
     concurrency: Option<NonZeroUsize>,
-) -> Result<usize> {
+) -> Result<usize, SkidderError> {
+    let report = build_all_grammars_impl(config, force_rebuild, concurrency, true)?;
+    if report.failed.is_empty() {
+        return Ok(report.succeeded.len());
+    }
+    Err(SkidderError::BuildFailed(report.failed))
+}
+
+/// The outcome of building every grammar with [`build_all_grammars_detailed`].
+#[derive(Debug, Default)]
+pub struct BuildReport {
+    /// The names of the grammars that were built or already up to date.
+    pub succeeded: Vec<String>,
+    /// The names of the grammars that failed to build together with the error.
+    pub failed: Vec<(String, SkidderError)>,
+}
+
+/// Builds all grammars like [`build_all_grammars`] but returns the result of each build
+/// instead of failing if any grammar fails to build.
+///
+/// Both lists of the report are sorted by the name of the grammar.
+pub fn build_all_grammars_detailed(
+    config: &Config,
+    force_rebuild: bool,
+    concurrency: Option<NonZeroUsize>,
+) -> Result<BuildReport, SkidderError> {
+    build_all_grammars_impl(config, force_rebuild, concurrency, false)
+}
+
+fn build_all_grammars_impl(
+    config: &Config,
+    force_rebuild: bool,
+    concurrency: Option<NonZeroUsize>,
+    print_errors: bool,
+) -> Result<BuildReport, SkidderError> {
     let grammars = list_grammars(config)?;
     let bar = ProgressBar::new(grammars.len() as u64).with_style(
         ProgressStyle::with_template("{spinner} {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
@@ -278,29 +575,38 @@ pub fn build_all_grammars(
     let concurrency = concurrency
         .or_else(|| thread::available_parallelism().ok())
         .map_or(4, usize::from);
-    let failed = Mutex::new(Vec::new());
+    let report = Mutex::new(BuildReport::default());
     thread::scope(|scope| {
         for _ in 0..concurrency {
-            scope.spawn(|| loop {
-                let Some(grammar) = grammars.get(i.fetch_add(1, atomic::Ordering::Relaxed)) else {
-                    break;
-                };
-                let name = grammar.file_name().unwrap().to_str().unwrap();
-                if let Err(err) = build::build_grammar(name, grammar, force_rebuild) {
-                    for err in err.chain() {
-                        bar.println(format!("error: {err}"))
+            scope.spawn(|| {
+                while let Some(grammar) = grammars.get(i.fetch_add(1, atomic::Ordering::Relaxed)) {
+                    let name = grammar.file_name().unwrap().to_str().unwrap();
+                    let res =
+                        build::build_grammar(name, grammar, force_rebuild, config.verify_checksums);
+                    if let Err(err) = &res {
+                        if print_errors {
+                            for err in err.chain() {
+                                bar.println(format!("error: {err}"))
+                            }
+                        }
+                    }
+                    let mut report = report.lock().unwrap();
+                    match res {
+                        Ok(()) => report.succeeded.push(name.to_owned()),
+                        Err(err) => report.failed.push((name.to_owned(), err.into())),
                     }
-                    failed.lock().unwrap().push(name.to_owned())
+                    drop(report);
+                    bar.inc(1);
                 }
-                bar.inc(1);
             });
         }
     });
-    let failed = failed.into_inner().unwrap();
-    if !failed.is_empty() {
-        bail!("failed to build grammars {failed:?}")
-    }
-    Ok(grammars.len())
+    let mut report = report.into_inner().unwrap();
+    report.succeeded.sort();
+    report
+        .failed
+        .sort_by(|(name1, _), (name2, _)| name1.cmp(name2));
+    Ok(report)
 }
 
 // TODO: version the metadata? Or allow unknown fields but warn on them?
@@ -324,16 +630,23 @@ impl Metadata {
         }
     }
 
-    pub fn read(path: &Path) -> Result<Metadata> {
-        let json = fs::read_to_string(path)
-            .with_context(|| format!("couldn't read {}", path.display()))?;
-        serde_json::from_str(&json)
-            .with_context(|| format!("invalid metadata.json file at {}", path.display()))
+    pub fn read(path: &Path) -> Result<Metadata, SkidderError> {
+        let json = fs::read_to_string(path).map_err(|error| SkidderError::Io {
+            context: format!("couldn't read {}", path.display()),
+            error,
+        })?;
+        serde_json::from_str(&json).map_err(|error| SkidderError::MetadataInvalid {
+            path: path.to_owned(),
+            error,
+        })
     }
 
-    pub fn write(&self, path: &Path) -> Result<()> {
+    pub fn write(&self, path: &Path) -> Result<(), SkidderError> {
         let json = serde_json::to_string_pretty(&self).unwrap();
-        fs::write(path, json).with_context(|| format!("failed to write {}", path.display()))
+        fs::write(path, json).map_err(|error| SkidderError::Io {
+            context: format!("failed to write {}", path.display()),
+            error,
+        })
     }
 }
 
@@ -358,7 +671,13 @@ pub struct ParserDefinition {
 // adding an extra trait bound would help... oh well
 
 /// decompresses a file compressed by skidder
-pub fn decompress(src: &mut File, mut dst: impl Write) -> Result<()> {
+///
+/// Returns an error instead of panicking if the file is truncated or not a valid zstd file.
+pub fn decompress(src: &mut File, dst: impl Write) -> Result<(), SkidderError> {
+    Ok(decompress_impl(src, dst)?)
+}
+
+fn decompress_impl(src: &mut File, mut dst: impl Write) -> Result<()> {
     const BATCH_SIZE: usize = 8 * 1024;
     let size = src.metadata()?.len();
 
@@ -372,23 +691,436 @@ pub fn decompress(src: &mut File, mut dst: impl Write) -> Result<()> {
                 length: skip_size,
                 ..
             })) => {
-                src.seek(SeekFrom::Current(skip_size as i64)).unwrap();
+                src.seek(SeekFrom::Current(skip_size as i64))
+                    .context("failed to skip zstd frame")?;
                 continue;
             }
-            other => other?,
+            other => other.context("invalid zstd frame header")?,
         }
         while !decoder.is_finished() {
-            decoder.decode_blocks(&mut src, BlockDecodingStrategy::UptoBytes(BATCH_SIZE))?;
+            decoder
+                .decode_blocks(&mut src, BlockDecodingStrategy::UptoBytes(BATCH_SIZE))
+                .context("failed to decode zstd block")?;
             while decoder.can_collect() > BATCH_SIZE {
-                let read = decoder.read(&mut copy_buffer).unwrap();
-                assert_eq!(read, BATCH_SIZE);
+                let read = decoder
+                    .read(&mut copy_buffer)
+                    .context("failed to read decompressed data")?;
+                ensure!(
+                    read == BATCH_SIZE,
+                    "decompressor returned {read} bytes, expected {BATCH_SIZE}"
+                );
                 dst.write_all(&copy_buffer)?;
             }
         }
         while decoder.can_collect() != 0 {
-            let read = decoder.read(&mut copy_buffer).unwrap();
+            let read = decoder
+                .read(&mut copy_buffer)
+                .context("failed to read decompressed data")?;
             dst.write_all(&copy_buffer[..read])?;
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use std::path::{Path, PathBuf};
+
+    use super::{
+        build_all_grammars, build_grammar, build_grammar_plan, list_grammars,
+        list_grammars_by_repo, Config, FreshnessReason, Metadata, ParserDefinition, Repo,
+        SkidderError, LIB_EXTENSION,
+    };
+
+    #[test]
+    fn config_from_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("skidder.json");
+        fs::write(
+            &path,
+            r#"{
+                "repos": [
+                    { "Local": { "path": "grammars" } },
+                    { "Git": { "name": "upstream", "remote": "https://example.com", "branch": "main" } }
+                ],
+                "index": "index"
+            }"#,
+        )
+        .unwrap();
+        let config = Config::from_path(&path).unwrap();
+        assert_eq!(config.index, dir.path().join("index"));
+        assert!(config.index.is_dir());
+        assert!(!config.verbose);
+        assert!(
+            matches!(&config.repos[0], Repo::Local { path } if *path == dir.path().join("grammars"))
+        );
+        assert!(matches!(&config.repos[1], Repo::Git { rev: None, .. }));
+
+        fs::write(&path, r#"{ "repos": [], "index": "index" }"#).unwrap();
+        assert!(Config::from_path(&path).is_err());
+        assert!(matches!(
+            Config::from_path(&dir.path().join("missing.json")),
+            Err(SkidderError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn grammar_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let repos = ["overrides", "base"].map(|name| dir.path().join(name));
+        for (repo, grammar) in [(0, "rust"), (1, "rust"), (1, "c")] {
+            let grammar_dir = repos[repo].join(grammar);
+            fs::create_dir_all(&grammar_dir).unwrap();
+            let metadata = Metadata::ParserDefinition(ParserDefinition {
+                repo: String::new(),
+                rev: String::new(),
+                license: String::new(),
+                compressed: false,
+            });
+            metadata.write(&grammar_dir.join("metadata.json")).unwrap();
+        }
+        let config = Config {
+            repos: repos
+                .iter()
+                .map(|path| Repo::Local { path: path.clone() })
+                .collect(),
+            index: PathBuf::new(),
+            verbose: false,
+            verify_checksums: false,
+            queries_path: None,
+        };
+        assert_eq!(
+            config.find_grammar("rust"),
+            vec![(0, repos[0].join("rust")), (1, repos[1].join("rust"))]
+        );
+        assert_eq!(config.grammar_dir("rust"), Some(repos[0].join("rust")));
+        assert_eq!(
+            list_grammars_by_repo(&config).unwrap(),
+            vec![(1, repos[1].join("c")), (0, repos[0].join("rust"))]
+        );
+    }
+
+    #[test]
+    fn query_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let grammar_dir = dir.path().join("grammars").join("rust");
+        fs::create_dir_all(&grammar_dir).unwrap();
+        let metadata = Metadata::ParserDefinition(ParserDefinition {
+            repo: String::new(),
+            rev: String::new(),
+            license: String::new(),
+            compressed: false,
+        });
+        metadata.write(&grammar_dir.join("metadata.json")).unwrap();
+        fs::write(grammar_dir.join("highlights.scm"), "").unwrap();
+        fs::write(grammar_dir.join("injections.scm"), "").unwrap();
+        let queries_dir = dir.path().join("queries").join("rust");
+        fs::create_dir_all(&queries_dir).unwrap();
+        fs::write(queries_dir.join("highlights.scm"), "").unwrap();
+        fs::write(queries_dir.join("textobjects.scm"), "").unwrap();
+
+        let path = dir.path().join("skidder.json");
+        fs::write(
+            &path,
+            r#"{
+                "repos": [{ "Local": { "path": "grammars" } }],
+                "index": "index",
+                "queries_path": "queries"
+            }"#,
+        )
+        .unwrap();
+        let config = Config::from_path(&path).unwrap();
+        assert_eq!(config.queries_path, Some(dir.path().join("queries")));
+        // The queries directory takes precedence over the grammar directory.
+        assert_eq!(
+            config.query_path("rust", "highlights"),
+            Some(queries_dir.join("highlights.scm"))
+        );
+        assert_eq!(
+            config.query_path("rust", "injections"),
+            Some(grammar_dir.join("injections.scm"))
+        );
+        assert_eq!(
+            config.query_path("rust", "textobjects"),
+            Some(queries_dir.join("textobjects.scm"))
+        );
+        assert_eq!(config.query_path("rust", "locals"), None);
+        assert_eq!(config.query_path("c", "highlights"), None);
+
+        let config = Config {
+            queries_path: None,
+            ..config
+        };
+        assert_eq!(
+            config.query_path("rust", "highlights"),
+            Some(grammar_dir.join("highlights.scm"))
+        );
+        assert_eq!(config.query_path("rust", "textobjects"), None);
+    }
+
+    #[test]
+    fn structured_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            repos: vec![Repo::Local {
+                path: dir.path().to_owned(),
+            }],
+            index: PathBuf::new(),
+            verbose: false,
+            verify_checksums: false,
+            queries_path: None,
+        };
+        assert!(matches!(
+            build_grammar(&config, "missing", false),
+            Err(SkidderError::GrammarNotFound(grammar)) if grammar == "missing"
+        ));
+
+        let grammar_dir = dir.path().join("broken");
+        fs::create_dir_all(grammar_dir.join("src")).unwrap();
+        fs::write(grammar_dir.join("src").join("parser.c"), "not C").unwrap();
+        let metadata = Metadata::ParserDefinition(ParserDefinition {
+            repo: String::new(),
+            rev: String::new(),
+            license: String::new(),
+            compressed: false,
+        });
+        metadata.write(&grammar_dir.join("metadata.json")).unwrap();
+        assert!(matches!(
+            build_grammar(&config, "broken", false),
+            Err(SkidderError::CompileFailed { grammar, stderr }) if grammar == "broken" && !stderr.is_empty()
+        ));
+        // The error of each grammar is kept.
+        let Err(SkidderError::BuildFailed(failed)) = build_all_grammars(&config, false, None)
+        else {
+            panic!("building all grammars should fail");
+        };
+        assert!(matches!(
+            &failed[..],
+            [(name, SkidderError::CompileFailed { .. })] if name == "broken"
+        ));
+
+        fs::write(grammar_dir.join("metadata.json"), "{").unwrap();
+        assert!(matches!(
+            list_grammars(&config),
+            Err(SkidderError::MetadataInvalid { path, .. }) if path == grammar_dir.join("metadata.json")
+        ));
+    }
+
+    #[test]
+    fn build_freshness() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("../test-grammars/comment");
+        let grammar_dir = dir.path().join("comment");
+        for entry in walkdir::WalkDir::new(src.join("src")) {
+            let entry = entry.unwrap();
+            let dst = grammar_dir.join(entry.path().strip_prefix(&src).unwrap());
+            if entry.file_type().is_dir() {
+                fs::create_dir_all(dst).unwrap();
+            } else {
+                fs::copy(entry.path(), dst).unwrap();
+            }
+        }
+        fs::copy(src.join("metadata.json"), grammar_dir.join("metadata.json")).unwrap();
+        let mut config = Config {
+            repos: vec![Repo::Local {
+                path: dir.path().to_owned(),
+            }],
+            index: PathBuf::new(),
+            verbose: false,
+            verify_checksums: false,
+            queries_path: None,
+        };
+        let reason =
+            |config: &Config, force| build_grammar_plan(config, "comment", force).unwrap().reason;
+
+        assert_eq!(reason(&config, false), Some(FreshnessReason::NotBuilt));
+        build_grammar(&config, "comment", false).unwrap();
+        assert_eq!(reason(&config, false), None);
+        assert_eq!(reason(&config, true), Some(FreshnessReason::Forced));
+
+        // Only the C sources are hashed.
+        let src_dir = grammar_dir.join("src");
+        fs::write(src_dir.join("grammar.json"), "{}").unwrap();
+        assert_eq!(reason(&config, false), None);
+        let mut scanner = fs::read_to_string(src_dir.join("scanner.c")).unwrap();
+        scanner.push('\n');
+        fs::write(src_dir.join("scanner.c"), scanner).unwrap();
+        assert_eq!(
+            reason(&config, false),
+            Some(FreshnessReason::SourcesChanged)
+        );
+        build_grammar(&config, "comment", false).unwrap();
+        assert_eq!(reason(&config, false), None);
+
+        let library = grammar_dir.join("comment").with_extension(LIB_EXTENSION);
+        fs::remove_file(&library).unwrap();
+        assert_eq!(
+            reason(&config, false),
+            Some(FreshnessReason::MissingLibrary)
+        );
+        build_grammar(&config, "comment", false).unwrap();
+        assert!(library.exists());
+
+        let mut bytes = fs::read(&library).unwrap();
+        bytes.push(0);
+        fs::write(&library, bytes).unwrap();
+        assert_eq!(reason(&config, false), None);
+        config.verify_checksums = true;
+        assert_eq!(
+            reason(&config, false),
+            Some(FreshnessReason::ChecksumMismatch)
+        );
+        build_grammar(&config, "comment", false).unwrap();
+        assert_eq!(reason(&config, false), None);
+    }
+
+    #[test]
+    fn decompress_invalid() {
+        // A parser compressed with the `zstd` command line tool.
+        let compressed = fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../test-grammars/comment/src/parser.c"),
+        )
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("parser.c");
+        let decompress = |bytes: &[u8]| {
+            fs::write(&path, bytes).unwrap();
+            let mut decompressed = Vec::new();
+            super::decompress(&mut fs::File::open(&path).unwrap(), &mut decompressed)
+                .map(|_| decompressed)
+        };
+
+        let decompressed = decompress(&compressed).unwrap();
+        assert!(String::from_utf8(decompressed)
+            .unwrap()
+            .contains("tree_sitter_comment"));
+        for len in [1, 4, 8, compressed.len() / 2, compressed.len() - 1] {
+            assert!(
+                decompress(&compressed[..len]).is_err(),
+                "truncated to {len}"
+            );
+        }
+        assert!(decompress(b"not a zstd file").is_err());
+        let mut corrupted = compressed.clone();
+        corrupted[20..40].fill(0xff);
+        assert!(decompress(&corrupted).is_err());
+    }
+
+    #[test]
+    fn fetch_with_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = dir.path().join("remote");
+        fs::create_dir_all(&remote).unwrap();
+        fs::write(remote.join("README"), "").unwrap();
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .current_dir(&remote)
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {args:?}");
+        };
+        git(&["init", "--quiet", "--initial-branch=main"]);
+        git(&["add", "README"]);
+        git(&["commit", "--quiet", "-m", "init"]);
+
+        let config = Config {
+            repos: vec![
+                Repo::Local {
+                    path: dir.path().join("local"),
+                },
+                Repo::Git {
+                    name: "upstream".to_owned(),
+                    remote: remote.to_str().unwrap().to_owned(),
+                    branch: "main".to_owned(),
+                    rev: None,
+                },
+            ],
+            index: dir.path().join("index"),
+            verbose: false,
+            verify_checksums: false,
+            queries_path: None,
+        };
+        let mut fetched = Vec::new();
+        super::fetch_with_progress(&config, false, |name| fetched.push(name.to_owned())).unwrap();
+        assert_eq!(fetched, ["upstream"]);
+        assert!(dir.path().join("index/upstream/README").exists());
+    }
+
+    #[test]
+    fn fetch_pinned_rev() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = dir.path().join("remote");
+        fs::create_dir_all(&remote).unwrap();
+        let git = |args: &[&str]| {
+            let output = std::process::Command::new("git")
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .current_dir(&remote)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "git {args:?}");
+            String::from_utf8(output.stdout).unwrap().trim().to_owned()
+        };
+        git(&["init", "--quiet", "--initial-branch=main"]);
+        let mut revs = Vec::new();
+        for version in ["1", "2"] {
+            fs::write(remote.join("VERSION"), version).unwrap();
+            git(&["add", "VERSION"]);
+            git(&["commit", "--quiet", "-m", version]);
+            revs.push(git(&["rev-parse", "HEAD"]));
+        }
+
+        let config = |rev: &str| Config {
+            repos: vec![Repo::Git {
+                name: "upstream".to_owned(),
+                remote: remote.to_str().unwrap().to_owned(),
+                branch: "main".to_owned(),
+                rev: Some(rev.to_owned()),
+            }],
+            index: dir.path().join("index"),
+            verbose: false,
+            verify_checksums: false,
+            queries_path: None,
+        };
+        let version = || fs::read_to_string(dir.path().join("index/upstream/VERSION")).unwrap();
+        super::fetch(&config(&revs[0]), false).unwrap();
+        assert_eq!(version(), "1");
+        // Changing the pinned revision of an existing clone checks out the new revision even
+        // without updating the repository.
+        super::fetch(&config(&revs[1]), false).unwrap();
+        assert_eq!(version(), "2");
+        super::fetch(&config(&revs[0]), false).unwrap();
+        assert_eq!(version(), "1");
+    }
+
+    #[test]
+    fn grammar_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("tree-sitter-typescript");
+        for grammar in ["typescript", "tsx"] {
+            fs::create_dir_all(repo.join(grammar).join("src")).unwrap();
+            fs::write(repo.join(grammar).join("src/parser.c"), "").unwrap();
+        }
+        fs::create_dir_all(repo.join("common")).unwrap();
+        fs::write(repo.join("common/scanner.h"), "").unwrap();
+
+        assert_eq!(
+            super::grammar_dirs(&repo).unwrap(),
+            [
+                ("tsx".to_owned(), repo.join("tsx")),
+                ("typescript".to_owned(), repo.join("typescript")),
+            ]
+        );
+        assert_eq!(
+            super::grammar_dirs(&repo.join("tsx")).unwrap(),
+            [("tsx".to_owned(), repo.join("tsx"))]
+        );
+        assert!(super::grammar_dirs(&repo.join("common"))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,269 @@
+//! Detection of the SPDX license identifier of a grammar from its license file.
+
+/// Phrases (matched case-insensitively and ignoring differences in whitespace) identifying a
+/// license.
+///
+/// The third element lists the licenses whose phrases are also matched by the text of this
+/// license, for example the text of the LGPL mentions the GPL. These are discarded when this
+/// license is detected.
+const LICENSE_PHRASES: &[(&str, &str, &[&str])] = &[
+    // Existing grammar metadata uses the lowercase id. The bare word would also match
+    // "unlicensed" so only the website and the first sentence of the license are matched.
+    ("unlicense.org", "unlicense", &[]),
+    (
+        "This is free and unencumbered software released into the public domain",
+        "unlicense",
+        &[],
+    ),
+    ("European Union Public Licence v. 1.2", "EUPL-1.2", &[]),
+    ("The Artistic License 2.0", "Artistic-2.0", &[]),
+    ("Apache License Version 2.0", "Apache-2.0", &[]),
+    (
+        "LLVM Exceptions to the Apache 2.0 License",
+        "Apache-2.0 WITH LLVM-exception",
+        &["Apache-2.0"],
+    ),
+    (
+        "GNU Affero General Public License Version 3",
+        "AGPL-3.0",
+        &["GPL-3.0"],
+    ),
+    (
+        "GNU Lesser General Public License Version 3",
+        "LGPL-3.0",
+        &["GPL-3.0"],
+    ),
+    (
+        "GNU Lesser General Public License Version 2.1",
+        "LGPL-2.1",
+        &["GPL-2.0", "GPL-3.0"],
+    ),
+    ("GNU General Public License Version 3", "GPL-3.0", &[]),
+    ("GNU General Public License Version 2", "GPL-2.0", &[]),
+    ("Mozilla Public License Version 2.0", "MPL-2.0", &[]),
+    ("Mozilla Public License, v. 2.0", "MPL-2.0", &[]),
+    ("MIT License", "MIT", &[]),
+    (
+        "Permission is hereby granted, free of charge, to any person obtaining a copy",
+        "MIT",
+        &[],
+    ),
+    (
+        "Permission to use, copy, modify, and/or distribute this software for any purpose with or without fee is hereby granted",
+        "ISC",
+        &[],
+    ),
+    ("ISC License", "ISC", &[]),
+    ("BSD Zero Clause License", "0BSD", &["ISC"]),
+    ("BSD 2-Clause License", "BSD-2-Clause", &[]),
+    ("BSD 3-Clause License", "BSD-3-Clause", &["BSD-2-Clause"]),
+    (
+        "Redistribution and use in source and binary forms, with or without modification, are permitted",
+        "BSD-2-Clause",
+        &[],
+    ),
+    (
+        "Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote products",
+        "BSD-3-Clause",
+        &["BSD-2-Clause"],
+    ),
+    ("Boost Software License - Version 1.0", "BSL-1.0", &[]),
+    ("zlib License", "Zlib", &[]),
+    ("Creative Commons Zero v1.0 Universal", "CC0-1.0", &[]),
+    ("DO WHAT THE FUCK YOU WANT TO PUBLIC LICENSE", "WTFPL", &[]),
+];
+
+/// Phrases which indicate that the licenses found in a license file apply alternatively.
+const DUAL_LICENSE_PHRASES: &[&str] = &["at your option", "dual licensed", "dual-licensed"];
+
+/// The SPDX license identifiers accepted in the `license` field of grammar metadata.
+///
+/// Like SPDX specifies, identifiers are matched case-insensitively.
+const KNOWN_LICENSES: &[&str] = &[
+    "0BSD",
+    "AGPL-3.0",
+    "Apache-2.0",
+    "Artistic-2.0",
+    "BSD-2-Clause",
+    "BSD-3-Clause",
+    "BSL-1.0",
+    "CC0-1.0",
+    "EUPL-1.2",
+    "GPL-2.0",
+    "GPL-3.0",
+    "ISC",
+    "LGPL-2.1",
+    "LGPL-3.0",
+    "MIT",
+    "MPL-2.0",
+    "Unlicense",
+    "WTFPL",
+    "Zlib",
+];
+
+/// The SPDX license exception identifiers accepted after a `WITH` operator.
+const KNOWN_EXCEPTIONS: &[&str] = &["LLVM-exception"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DetectedLicense {
+    /// A SPDX license expression, for example `MIT` or `MIT OR Apache-2.0`.
+    Found(String),
+    /// Multiple licenses were found but it's unclear how they apply.
+    Ambiguous(Vec<&'static str>),
+    /// No known license was found.
+    Unknown,
+}
+
+/// Detects the SPDX license expression for the contents of a license file.
+pub fn detect_license(text: &str) -> DetectedLicense {
+    let text = normalize(text);
+    let mut found: Vec<(&'static str, &[&str])> = Vec::new();
+    for &(phrase, license, overrides) in LICENSE_PHRASES {
+        if found.iter().all(|&(existing, _)| existing != license)
+            && text.contains(&normalize(phrase))
+        {
+            found.push((license, overrides));
+        }
+    }
+    let overridden: Vec<&str> = found
+        .iter()
+        .flat_map(|&(_, overrides)| overrides.iter().copied())
+        .collect();
+    let licenses: Vec<&'static str> = found
+        .iter()
+        .map(|&(license, _)| license)
+        .filter(|license| !overridden.contains(license))
+        .collect();
+    match licenses.as_slice() {
+        [] => DetectedLicense::Unknown,
+        [license] => DetectedLicense::Found((*license).to_owned()),
+        licenses
+            if DUAL_LICENSE_PHRASES
+                .iter()
+                .any(|phrase| text.contains(phrase)) =>
+        {
+            let expression = licenses
+                .iter()
+                .map(|license| {
+                    if license.contains(' ') {
+                        format!("({license})")
+                    } else {
+                        (*license).to_owned()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" OR ");
+            DetectedLicense::Found(expression)
+        }
+        _ => DetectedLicense::Ambiguous(licenses),
+    }
+}
+
+/// Checks whether `expression` is a SPDX license expression made up of known license and
+/// exception identifiers.
+pub fn is_known_license_expression(expression: &str) -> bool {
+    let expression = expression.replace(['(', ')'], " ");
+    let mut expect_license = true;
+    let mut after_with = false;
+    let mut empty = true;
+    for token in expression.split_whitespace() {
+        empty = false;
+        if expect_license {
+            let known = if after_with {
+                KNOWN_EXCEPTIONS
+                    .iter()
+                    .any(|exception| exception.eq_ignore_ascii_case(token))
+            } else {
+                let token = token.trim_end_matches('+');
+                KNOWN_LICENSES
+                    .iter()
+                    .any(|license| license.eq_ignore_ascii_case(token))
+            };
+            if !known {
+                return false;
+            }
+            expect_license = false;
+            after_with = false;
+        } else {
+            match token {
+                "OR" | "AND" => {}
+                "WITH" => after_with = true,
+                _ => return false,
+            }
+            expect_license = true;
+        }
+    }
+    !empty && !expect_license
+}
+
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detect() {
+        assert_eq!(
+            detect_license("MIT License\n\nCopyright (c) 2024"),
+            DetectedLicense::Found("MIT".to_owned())
+        );
+        assert_eq!(
+            detect_license(
+                "Redistribution and use in source and binary forms, with or without\n   modification, \
+                 are permitted ...\n  Neither the name of the copyright holder nor the names of its\n\
+                 contributors may be used to endorse or promote products"
+            ),
+            DetectedLicense::Found("BSD-3-Clause".to_owned())
+        );
+        assert_eq!(
+            detect_license(
+                "Licensed under either of Apache License, Version 2.0 or MIT license at your \
+                 option.\n\nApache License\nVersion 2.0, January 2004\n\nMIT License"
+            ),
+            DetectedLicense::Found("Apache-2.0 OR MIT".to_owned())
+        );
+        assert_eq!(
+            detect_license("Apache License Version 2.0\n\nMIT License"),
+            DetectedLicense::Ambiguous(vec!["Apache-2.0", "MIT"])
+        );
+        assert_eq!(
+            detect_license(
+                "This is free and unencumbered software released into the public domain.\n\n\
+                 For more information, please refer to <http://unlicense.org>"
+            ),
+            DetectedLicense::Found("unlicense".to_owned())
+        );
+        assert_eq!(
+            detect_license("MIT License\n\nUnlicensed copies are not permitted."),
+            DetectedLicense::Found("MIT".to_owned())
+        );
+        assert_eq!(
+            detect_license("Unlicensed use is prohibited"),
+            DetectedLicense::Unknown
+        );
+        assert_eq!(
+            detect_license("All rights reserved"),
+            DetectedLicense::Unknown
+        );
+    }
+
+    #[test]
+    fn known_expressions() {
+        assert!(is_known_license_expression("MIT"));
+        assert!(is_known_license_expression("unlicense"));
+        assert!(is_known_license_expression("(MIT OR Apache-2.0)"));
+        assert!(is_known_license_expression(
+            "Apache-2.0 WITH LLVM-exception"
+        ));
+        assert!(!is_known_license_expression("Apache-2.0 WITH MIT"));
+        assert!(!is_known_license_expression("MIT OR"));
+        assert!(!is_known_license_expression("Proprietary"));
+        assert!(!is_known_license_expression(""));
+    }
+}
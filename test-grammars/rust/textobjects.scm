@@ -0,0 +1,7 @@
+(function_item
+  body: (_) @function.inside) @function.around
+
+(closure_expression
+  body: (_) @function.inside) @function.around
+
+(line_comment)+ @comment.around